@@ -0,0 +1,79 @@
+//! Compares the throughput of sending files over a (local) TCP connection using the zero-copy path of
+//! `HTTPResponse::send_to_tcp_stream` (`sendfile` on Linux) with that of the generic `HTTPResponse::write_to`,
+//! which copies the file through a buffer in userspace.
+//!
+//! Run using `cargo bench`. The size of the test file (in MiB) can be changed using the `BENCH_FILE_SIZE_MIB`
+//! environment variable.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use http_share::HTTPResponse;
+
+/// How many times every measurement is repeated (the fastest run counts).
+const RUNS: usize = 5;
+
+fn main() -> io::Result<()> {
+    let file_size_mib: u64 = std::env::var("BENCH_FILE_SIZE_MIB").ok().and_then(|size| size.parse().ok()).unwrap_or(512);
+    let file_path: PathBuf = std::env::temp_dir().join(format!("http_share_bench_{}.bin", std::process::id()));
+    create_test_file(&file_path, file_size_mib * 1024 * 1024)?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+
+    println!("Sending a {} MiB file over a local TCP connection, the best of {} runs:", file_size_mib, RUNS);
+    let result = (|| {
+        for (name, range) in [("whole file", None), ("range (2nd half)", Some((file_size_mib * 512 * 1024, u64::MAX)))] {
+            let zero_copy = measure(&listener, || Ok(HTTPResponse::builder().file(File::open(&file_path)?, range)), true)?;
+            let buffered = measure(&listener, || Ok(HTTPResponse::builder().file(File::open(&file_path)?, range)), false)?;
+            println!("  {:<17} send_to_tcp_stream: {:>8.1} MiB/s   write_to: {:>8.1} MiB/s", name, zero_copy, buffered);
+        }
+        Ok(())
+    })();
+    fs::remove_file(&file_path)?;
+    result
+}
+
+/// Writes `size` bytes of (not too easily compressible) data to a new file at `path`.
+fn create_test_file(path: &PathBuf, size: u64) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut block = vec![0u8; 1024 * 1024];
+    for _ in 0..(size / block.len() as u64) {
+        for byte in block.iter_mut() {
+            state ^= state << 13; state ^= state >> 7; state ^= state << 17; // xorshift
+            *byte = state as u8;
+        }
+        writer.write_all(&block)?;
+    }
+    writer.flush()
+}
+
+/// Sends the response created by `response` to a client reading everything from a local connection,
+/// `RUNS` times, and returns the best throughput in MiB/s. `zero_copy` decides whether the response
+/// is sent using `send_to_tcp_stream` or `write_to`.
+fn measure(listener: &TcpListener, response: impl Fn() -> io::Result<HTTPResponse>, zero_copy: bool) -> io::Result<f64> {
+    let address = listener.local_addr()?;
+    let mut best = Duration::MAX;
+    let mut bytes_received: u64 = 0;
+    for _ in 0..RUNS {
+        let client = thread::spawn(move || -> io::Result<u64> {
+            let mut stream = TcpStream::connect(address)?;
+            io::copy(&mut stream, &mut io::sink())
+        });
+        let (mut stream, _) = listener.accept()?;
+        let response = response()?;
+        let start = Instant::now();
+        if zero_copy {
+            response.send_to_tcp_stream(&mut stream)?;
+        } else {
+            response.write_to(&mut stream)?;
+        }
+        drop(stream); // Closing the connection ends the client's `io::copy`.
+        bytes_received = client.join().unwrap()?; // Wait until everything arrived.
+        best = best.min(start.elapsed());
+    }
+    Ok(bytes_received as f64 / (1024.0 * 1024.0) / best.as_secs_f64())
+}
+
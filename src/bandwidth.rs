@@ -0,0 +1,48 @@
+//! The bandwidth limits of this server (`--rate-limit...`, cf. `config::BandwidthLimits`): keeps the token
+//! buckets shared by several responses (the global one and those per IP address and per user) and puts
+//! together the `Throttle` for every response.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use http_share::throttle::{Throttle, TokenBucket};
+use lazy_static::lazy_static;
+use crate::config::{UserAccount, CONFIG};
+
+lazy_static! {
+    /// The bucket shared by all responses, if there is a global limit.
+    static ref GLOBAL_BUCKET: Option<Arc<TokenBucket>> = CONFIG.bandwidth_limits.global.map(|rate| Arc::new(TokenBucket::new(rate)));
+    /// The buckets shared by all responses to the same IP address.
+    static ref IP_BUCKETS: Mutex<HashMap<IpAddr, Arc<TokenBucket>>> = Mutex::new(HashMap::new());
+    /// The buckets shared by all responses to the same (logged in) user.
+    static ref USER_BUCKETS: Mutex<HashMap<String, Arc<TokenBucket>>> = Mutex::new(HashMap::new());
+}
+
+/// The bandwidth limits applying to a response to the client at `ip_addr`, logged in as `user`.
+/// Range requests (`is_range_request`) are prioritized if configured so (`--prioritize-ranges`).
+pub fn throttle(ip_addr: Option<IpAddr>, user: &UserAccount, is_range_request: bool) -> Throttle {
+    let limits = CONFIG.bandwidth_limits;
+    let mut buckets: Vec<Arc<TokenBucket>> = Vec::new();
+    if let Some(rate) = limits.per_connection {
+        buckets.push(Arc::new(TokenBucket::new(rate)));
+    }
+    if let (Some(rate), Some(ip_addr)) = (limits.per_ip, ip_addr) {
+        buckets.push(shared_bucket(&IP_BUCKETS, ip_addr, rate));
+    }
+    if let (Some(rate), false) = (limits.per_user, user.name.is_empty()) { // Anonymous users are only limited per IP address.
+        buckets.push(shared_bucket(&USER_BUCKETS, user.name.clone(), rate));
+    }
+    if let Some(bucket) = GLOBAL_BUCKET.as_ref() {
+        buckets.push(Arc::clone(bucket));
+    }
+    Throttle::new(buckets).prioritized(limits.prioritize_ranges && is_range_request)
+}
+
+/// Helper function for `throttle`: returns the bucket for `key` in `buckets`, creating it if there is none.
+/// Buckets that aren't used by any response anymore are removed, so that the map doesn't keep growing.
+fn shared_bucket<K: Hash + Eq>(buckets: &Mutex<HashMap<K, Arc<TokenBucket>>>, key: K, rate: u64) -> Arc<TokenBucket> {
+    let mut buckets = buckets.lock().unwrap();
+    buckets.retain(|_, bucket| Arc::strong_count(bucket) > 1);
+    Arc::clone(buckets.entry(key).or_insert_with(|| Arc::new(TokenBucket::new(rate))))
+}
@@ -0,0 +1,267 @@
+//! Browsing inside of ZIP and tar archives (also gzip or Zstandard compressed ones) as if they were
+//! folders: "/bundle.zip/" lists the contents of "bundle.zip" (in List, Table and Grid View) and
+//! "/bundle.zip/photos/a.jpg" serves a single file from it, without extracting anything else.
+//!
+//! Files that are stored uncompressed (in ZIP archives and uncompressed tar archives) are served with
+//! support for 'Range' requests, so e.g. a video inside of an archive can be played and skipped through.
+//! Compressed tar archives have to be decompressed up to the requested file, there is no way around that.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Error, Read};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use flate2::read::GzDecoder;
+use http_share::{HTTPRequest, HTTPResponse};
+use http_share::tar::{TarEntry, TarReader};
+use http_share::throttle::Throttle;
+use http_share::zip::{read_zip_entries, CompressionMethod};
+use crate::config::UserAccount;
+use crate::hidden::FolderRules;
+use crate::{FolderItem, ListingKind};
+
+/// The kinds of archives that can be browsed, recognized by their file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /// The kind of the archive called `name`, `None` if it's not an archive that can be browsed.
+    fn of(name: &str) -> Option<Self> {
+        let name: String = name.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(ArchiveKind::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the file called `name` is an archive whose contents can be browsed.
+pub fn is_browsable_archive(name: &str) -> bool {
+    ArchiveKind::of(name).is_some()
+}
+
+/// What is known about a file or folder inside of an archive, shown in Table View.
+#[derive(Debug, Clone)]
+pub struct MemberDetails {
+    pub is_dir: bool,
+    /// The size of a file in bytes (uncompressed).
+    pub size: u64,
+    /// The number of items in a folder.
+    pub item_count: usize,
+    pub modified: Option<SystemTime>,
+}
+
+/// A file or folder inside of an archive.
+struct Member {
+    /// The full path within the archive, without leading or trailing '/'.
+    path: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// How the contents of a file inside of an archive can be read.
+enum MemberContents {
+    /// The contents are stored uncompressed in the archive `file` at `offset`, i.e. any range of them
+    /// can be read directly.
+    Stored { file: File, offset: u64, size: u64 },
+    /// The contents have to be decompressed from the start, using the `reader`.
+    Compressed { reader: Box<dyn Read + Send> },
+}
+
+/// Splits a (percent-decoded) path like "/Downloads/bundle.zip/photos/a.jpg" into the path of the
+/// archive on disk ("Downloads/bundle.zip" within the `root_dir`) and the path inside of it ("photos/a.jpg").
+/// The path inside of the archive may be empty ("/Downloads/bundle.zip/"), but the '/' after the name
+/// of the archive is necessary, as "/Downloads/bundle.zip" is a regular download of the archive.
+/// Returns `None` if the path doesn't lead into an archive.
+pub fn split_archive_path(decoded_get_path: &str, root_dir: &Path) -> Option<(PathBuf, String)> {
+    let relative_path: &str = decoded_get_path.trim_start_matches('/');
+    relative_path.match_indices('/')
+        .map(|(index, _)| (&relative_path[..index], &relative_path[index + 1..]))
+        .find(|(archive_path, _)| ArchiveKind::of(archive_path).is_some() && root_dir.join(archive_path).is_file())
+        .map(|(archive_path, inner_path)| (root_dir.join(archive_path), inner_path.trim_matches('/').to_string()))
+}
+
+/// Responds to a `GET` request for `inner_path` inside of the archive at `archive_path`, either with
+/// the listing of a folder or with the contents of a file (cf. `dir_response` and `file_response`).
+pub fn archive_response(http_request: &HTTPRequest, archive_path: &Path, inner_path: &str, root_dir: &Path,
+                        user: UserAccount, throttle: Throttle, stream: &mut TcpStream) -> io::Result<()> {
+    let query_string: Option<&str> = http_request.get_get_path().split_once('?').map(|(_, query_string)| query_string);
+    let relative_archive_path: String = archive_path.strip_prefix(root_dir).unwrap().display().to_string();
+    let kind: ArchiveKind = ArchiveKind::of(&relative_archive_path).expect("split_archive_path only returns archives");
+    // The contents of archives are hidden like those of folders (cf. hidden.rs):
+    let rules: FolderRules = FolderRules::of(archive_path.parent().unwrap_or(root_dir), root_dir, &user);
+    let mut members: Vec<Member> = match find_member(archive_path, kind, inner_path) {
+        // A file:
+        Ok(Lookup::File(contents)) if !rules.hides_archive_member(archive_path, inner_path, false) => {
+            return member_response(http_request, inner_path, contents, throttle, stream);
+        },
+        Ok(Lookup::File(_)) => Vec::new(), // (a hidden file, not found below)
+        Ok(Lookup::NotAFile(members)) => members,
+        Err(err) => {
+            HTTPResponse::new_500_server_error(format!("Could not read the archive /{}: {}", relative_archive_path, err)).send_to_tcp_stream(stream)?;
+            return Err(Error::other(format!("could not read the archive {}: {}", archive_path.display(), err)));
+        }
+    };
+
+    members.retain(|member| !rules.hides_archive_member(archive_path, &member.path, member.is_dir));
+
+    // A folder, which exists if it has an entry of its own or if anything (visible) is inside of it:
+    let prefix: String = if inner_path.is_empty() { String::new() } else { format!("{}/", inner_path) };
+    if !inner_path.is_empty() && !members.iter().any(|member| member.path == inner_path || member.path.starts_with(&prefix)) {
+        HTTPResponse::new_404_not_found(format!("{}/{}", relative_archive_path, inner_path)).send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("Could not find {} in the archive {}", inner_path, archive_path.display())));
+    }
+    let dir_path: String = if inner_path.is_empty() { relative_archive_path } else { format!("{}/{}", relative_archive_path, inner_path) };
+    let folder_items: Vec<FolderItem> = folder_contents(&members, &prefix).into_iter()
+        .map(|(name, details)| FolderItem { path: format!("{}/{}", dir_path, name), archive_member: Some(details), is_dir: None })
+        .collect();
+    crate::listing_response(http_request, folder_items, query_string, dir_path, user, ListingKind::Archive, stream)
+}
+
+/// Helper function for `archive_response`: lists the files and folders directly inside of the folder
+/// whose path (within the archive) is `prefix` (ending with a '/', or empty for the top level).
+/// Folders don't need entries of their own, they're also derived from the paths of the files in them.
+fn folder_contents(members: &[Member], prefix: &str) -> BTreeMap<String, MemberDetails> {
+    let mut contents: BTreeMap<String, MemberDetails> = BTreeMap::new();
+    let mut items_in_subfolders: HashSet<(&str, &str)> = HashSet::new(); // (subfolder name, item name)
+    for member in members {
+        let relative_path: &str = match member.path.strip_prefix(prefix) {
+            Some(relative_path) if !relative_path.is_empty() => relative_path,
+            _ => continue,
+        };
+        let mut components = relative_path.split('/');
+        let name: &str = components.next().unwrap(); // (`split` always returns at least 1 item)
+        match components.next() {
+            Some(item_name) => { // Something inside of a subfolder:
+                items_in_subfolders.insert((name, item_name));
+                contents.entry(name.to_string()).or_insert(MemberDetails { is_dir: true, size: 0, item_count: 0, modified: None });
+            },
+            None if member.is_dir => {
+                contents.entry(name.to_string()).or_insert(MemberDetails { is_dir: true, size: 0, item_count: 0, modified: None })
+                    .modified = member.modified;
+            },
+            None => {
+                contents.insert(name.to_string(), MemberDetails { is_dir: false, size: member.size, item_count: 0, modified: member.modified });
+            },
+        }
+    }
+    for (subfolder_name, _) in items_in_subfolders {
+        if let Some(details) = contents.get_mut(subfolder_name) {
+            details.item_count += 1;
+        }
+    }
+    return contents;
+}
+
+/// The result of `find_member`.
+enum Lookup {
+    /// The path is the one of a file, whose contents can be read.
+    File(MemberContents),
+    /// The path is not the one of a file (but maybe of a folder), all members of the archive are returned.
+    NotAFile(Vec<Member>),
+}
+
+/// Looks for the file `inner_path` inside of the archive at `archive_path`, reading the archive only once:
+/// compressed tar archives are only decompressed up to that file, so that the file can be read right away.
+fn find_member(archive_path: &Path, kind: ArchiveKind, inner_path: &str) -> io::Result<Lookup> {
+    let mut members: Vec<Member> = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut file = File::open(archive_path)?;
+            for entry in read_zip_entries(&mut BufReader::new(&mut file))? {
+                let path: String = entry.name.trim_matches('/').to_string();
+                if !entry.is_dir() && path == inner_path {
+                    if entry.method == Some(CompressionMethod::Stored) && !entry.encrypted {
+                        let offset: u64 = entry.data_offset(&mut file)?;
+                        return stored_member(file, offset, entry.uncompressed_size);
+                    }
+                    return Ok(Lookup::File(MemberContents::Compressed { reader: entry.reader(file)? }));
+                }
+                if !path.is_empty() {
+                    members.push(Member { path, is_dir: entry.is_dir(), size: entry.uncompressed_size, modified: entry.modified });
+                }
+            }
+        },
+        ArchiveKind::Tar => {
+            // Uncompressed tar archives are read by seeking from header to header:
+            let mut tar_reader = TarReader::new_seekable(File::open(archive_path)?);
+            while let Some(entry) = tar_reader.next_entry()? {
+                if !entry.is_dir && entry.name == inner_path {
+                    return stored_member(File::open(archive_path)?, entry.data_offset, entry.size);
+                }
+                push_tar_member(&mut members, entry);
+            }
+        },
+        ArchiveKind::TarGz | ArchiveKind::TarZst => {
+            let file = File::open(archive_path)?;
+            let mut tar_reader: TarReader<Box<dyn Read + Send>> = match kind {
+                ArchiveKind::TarGz => TarReader::new(Box::new(GzDecoder::new(BufReader::new(file)))),
+                _ => TarReader::new(Box::new(zstd::Decoder::new(file)?)),
+            };
+            while let Some(entry) = tar_reader.next_entry()? {
+                if !entry.is_dir && entry.name == inner_path {
+                    return Ok(Lookup::File(MemberContents::Compressed { reader: Box::new(tar_reader) }));
+                }
+                push_tar_member(&mut members, entry);
+            }
+        },
+    }
+    return Ok(Lookup::NotAFile(members));
+}
+
+/// Helper function for `find_member`: the contents of a file stored uncompressed in the archive `file`,
+/// checking that the archive isn't cut off within them (so that no shorter body than announced is sent).
+fn stored_member(file: File, offset: u64, size: u64) -> io::Result<Lookup> {
+    if offset.saturating_add(size) > file.metadata()?.len() {
+        return Err(Error::new(io::ErrorKind::UnexpectedEof, "the archive ends in the middle of the file"));
+    }
+    return Ok(Lookup::File(MemberContents::Stored { file, offset, size }));
+}
+
+/// Helper function for `find_member`: adds the tar `entry` to the `members`.
+fn push_tar_member(members: &mut Vec<Member>, entry: TarEntry) {
+    if !entry.name.is_empty() {
+        members.push(Member { path: entry.name, is_dir: entry.is_dir, size: entry.size, modified: Some(entry.modified) });
+    }
+}
+
+/// Responds with the `contents` of the file `inner_path` inside of an archive, only with the requested
+/// range of it if the `http_request` contains a 'Range' header and the file is stored uncompressed.
+/// It's sent only as fast as the `throttle` allows.
+fn member_response(http_request: &HTTPRequest, inner_path: &str, contents: MemberContents, throttle: Throttle, stream: &mut TcpStream) -> io::Result<()> {
+    let response = HTTPResponse::builder().header("Content-Type", http_share::mime::mime_type(Path::new(inner_path)));
+    match contents {
+        MemberContents::Stored { size: 0, .. } => response.header("Accept-Ranges", "bytes").empty(),
+        MemberContents::Stored { file, offset, size } if http_request.contains_range_header() => {
+            let (range_start, range_end) = http_request.get_requested_range();
+            if range_start >= size || range_end.is_some_and(|range_end| range_end < range_start) {
+                response.status(416).header("Content-Range", format!("bytes */{}", size)).empty()
+            } else {
+                // There is no <range-end> specified (e.g. a range of "0-" was requested) -> take all remaining bytes:
+                let range_end: u64 = range_end.unwrap_or(u64::MAX).min(size - 1);
+                response.status(206)
+                    .header("Accept-Ranges", "bytes")
+                    .header("Content-Range", format!("bytes {}-{}/{}", range_start, range_end, size))
+                    .file(file, Some((offset + range_start, offset + range_end)))
+            }
+        },
+        MemberContents::Stored { file, offset, size } => response.header("Accept-Ranges", "bytes").file(file, Some((offset, offset + size - 1))),
+        // (Sent chunked, as a corrupt archive might contain less than the size it claims, which would leave
+        // the client waiting for the rest:)
+        MemberContents::Compressed { reader } => response.header("Accept-Ranges", "none").reader(reader, None),
+    }.compressed(http_request.get_header("Accept-Encoding")).throttled(throttle).send_to_tcp_stream(stream)
+}
@@ -0,0 +1,240 @@
+//! The cache of generated thumbnails (cf. thumbnail.rs), so that they are only generated once, even across
+//! restarts: the thumbnails are kept on disk (in `--thumbnail-cache=PATH` or else the user's cache folder,
+//! e.g. `~/.cache/http_share/thumbnails`), the most recently used ones in memory as well.
+//!
+//! Thumbnails are cached by the path of their file, their size and the modification time of the file, so that
+//! changed files get new thumbnails. The disk cache is limited in size (`--thumbnail-cache-size=SIZE`): when
+//! it grows larger, the least recently used thumbnails are deleted. `http_share cache prune` additionally
+//! deletes the thumbnails of files that changed or don't exist anymore.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ansi_term::Colour::Red;
+use lazy_static::lazy_static;
+use separator::Separatable;
+use crate::config::{Config, CONFIG};
+use crate::date_time_str;
+use crate::thumbnail::Thumbnail;
+
+/// How many bytes of thumbnails are kept in memory at most.
+const MAX_MEMORY_SIZE: usize = 64 << 20;
+
+/// The first line of every cache file.
+const FILE_HEADER: &str = "http_share thumbnail v1";
+
+/// The file extension of cache files.
+const FILE_EXTENSION: &str = "thumb";
+
+/// The number of cache files written so far, which makes the names of their temporary files unique.
+static WRITTEN_FILES: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    /// The most recently used thumbnails, by their key (cf. `key`).
+    static ref MEMORY_CACHE: Mutex<MemoryCache> = Mutex::new(MemoryCache { entries: HashMap::new(), uses: 0, size: 0 });
+    /// The total size of the cache files in bytes, once it was determined.
+    static ref DISK_USAGE: Mutex<Option<u64>> = Mutex::new(None);
+}
+
+/// The thumbnails kept in memory, cf. `MAX_MEMORY_SIZE`.
+struct MemoryCache {
+    /// The thumbnails by their key and the value of `uses` when they were used last.
+    entries: HashMap<String, (Thumbnail, u64)>,
+    /// The number of times a thumbnail was used so far.
+    uses: u64,
+    /// The total size of the thumbnails in bytes.
+    size: usize,
+}
+
+impl MemoryCache {
+    /// The thumbnail with the `key`, if it is in memory.
+    fn get(&mut self, key: &str) -> Option<Thumbnail> {
+        self.uses += 1;
+        let uses: u64 = self.uses;
+        self.entries.get_mut(key).map(|(thumbnail, last_use)| { *last_use = uses; thumbnail.clone() })
+    }
+
+    /// Keeps the `thumbnail` with the `key` in memory, evicting the least recently used ones when necessary.
+    fn insert(&mut self, key: String, thumbnail: Thumbnail) {
+        self.uses += 1;
+        self.size += thumbnail.data.len();
+        if let Some((replaced, _)) = self.entries.insert(key, (thumbnail, self.uses)) {
+            self.size -= replaced.data.len();
+        }
+        while self.size > MAX_MEMORY_SIZE {
+            let Some(least_recently_used) = self.entries.iter().min_by_key(|(_, (_, last_use))| *last_use).map(|(key, _)| key.clone()) else { break };
+            let (evicted, _) = self.entries.remove(&least_recently_used).unwrap();
+            self.size -= evicted.data.len();
+        }
+    }
+}
+
+/// The folder the thumbnails are cached in according to the `config`: `--thumbnail-cache=PATH` or else the
+/// user's cache folder ($XDG_CACHE_HOME, ~/.cache or %LOCALAPPDATA%).
+fn cache_dir(config: &Config) -> PathBuf {
+    if let Some(dir) = &config.thumbnail_cache_dir {
+        return dir.clone();
+    }
+    let absolute_var = |name: &str| env::var_os(name).map(PathBuf::from).filter(|path| path.is_absolute());
+    absolute_var("XDG_CACHE_HOME")
+        .or_else(|| absolute_var("HOME").map(|home| home.join(".cache")))
+        .or_else(|| absolute_var("LOCALAPPDATA"))
+        .unwrap_or_else(env::temp_dir)
+        .join("http_share")
+        .join("thumbnails")
+}
+
+/// The key of the thumbnail of the file at `path` (as an absolute path) in the given `size`, including the
+/// modification time of the file, `None` if the file can't be read.
+fn key(path: &Path, size: u32) -> Option<String> {
+    let path: PathBuf = fs::canonicalize(path).ok()?;
+    let modified = fs::metadata(&path).ok()?.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}\n{}\n{}.{:09}", path.display(), size, modified.as_secs(), modified.subsec_nanos()))
+}
+
+/// The path of the cache file of the thumbnail with the `key` within the `cache_dir`, named by two 64 bit
+/// FNV-1a hashes of the key (with different offset bases, so that collisions are even less likely).
+fn cache_file(cache_dir: &Path, key: &str) -> PathBuf {
+    let fnv1a = |offset_basis: u64| key.bytes().fold(offset_basis, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    cache_dir.join(format!("{:016x}{:016x}.{}", fnv1a(0xcbf29ce484222325), fnv1a(0x84222325cbf29ce4), FILE_EXTENSION))
+}
+
+/// The cached thumbnail of the file at `path` in the given `size`, generated using `generate` (and cached)
+/// if it isn't cached yet.
+pub fn thumbnail(path: &Path, size: u32, generate: impl FnOnce() -> Result<Thumbnail, String>) -> Result<Thumbnail, String> {
+    let Some(key) = key(path, size) else { return generate() };
+    if let Some(thumbnail) = MEMORY_CACHE.lock().unwrap().get(&key) {
+        return Ok(thumbnail);
+    }
+    let cache_dir: PathBuf = cache_dir(&CONFIG);
+    let cache_file: PathBuf = cache_file(&cache_dir, &key);
+    let thumbnail: Thumbnail = match read(&cache_file, &key) {
+        Some(thumbnail) => {
+            // The modification time of cache files is the time they were used last (cf. `evict`):
+            let _ = File::options().write(true).open(&cache_file).and_then(|file| file.set_modified(SystemTime::now()));
+            thumbnail
+        },
+        None => {
+            let thumbnail: Thumbnail = generate()?;
+            if let Err(err) = write(&cache_dir, &cache_file, &key, &thumbnail) {
+                eprintln!("{}", Red.paint(format!("[{}] Error: Could not cache the thumbnail of {} in {}: {}", date_time_str(), path.display(), cache_dir.display(), err)));
+            }
+            thumbnail
+        }
+    };
+    MEMORY_CACHE.lock().unwrap().insert(key, thumbnail.clone());
+    return Ok(thumbnail);
+}
+
+/// Helper function for `thumbnail`: reads the cache file at `cache_file`, `None` if it doesn't exist or is
+/// the one of another thumbnail (with the same hash) or of an older version of the file.
+fn read(cache_file: &Path, key: &str) -> Option<Thumbnail> {
+    let (cached_key, content_type, data) = parse(&fs::read(cache_file).ok()?)?;
+    if cached_key != key {
+        return None;
+    }
+    let content_type: &'static str = ["image/jpeg", "image/png"].iter().copied().find(|known| *known == content_type)?;
+    Some(Thumbnail { content_type, data })
+}
+
+/// Helper function for `read` and `prune`: splits the `contents` of a cache file into the key, the content type
+/// and the thumbnail itself.
+fn parse(contents: &[u8]) -> Option<(String, String, Vec<u8>)> {
+    // The header lines: FILE_HEADER, the key (3 lines, cf. `key`) and the content type:
+    let mut lines: Vec<&[u8]> = Vec::new();
+    let mut rest: &[u8] = contents;
+    while lines.len() < 5 {
+        let newline: usize = rest.iter().position(|&byte| byte == b'\n')?;
+        lines.push(&rest[..newline]);
+        rest = &rest[newline + 1..];
+    }
+    let lines: Vec<String> = lines.into_iter().map(|line| String::from_utf8_lossy(line).to_string()).collect();
+    if lines[0] != FILE_HEADER {
+        return None;
+    }
+    Some((lines[1..4].join("\n"), lines[4].clone(), rest.to_vec()))
+}
+
+/// Helper function for `thumbnail`: writes the `thumbnail` with the `key` to the `cache_file` in the `cache_dir`
+/// (atomically, so that no other thread reads a partially written one) and evicts the least recently used
+/// thumbnails if the cache grew too large.
+fn write(cache_dir: &Path, cache_file: &Path, key: &str, thumbnail: &Thumbnail) -> io::Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    let mut contents: Vec<u8> = format!("{}\n{}\n{}\n", FILE_HEADER, key, thumbnail.content_type).into_bytes();
+    contents.extend_from_slice(&thumbnail.data);
+    let counter: u64 = WRITTEN_FILES.fetch_add(1, Ordering::Relaxed);
+    let temporary_file: PathBuf = cache_file.with_extension(format!("{}.tmp{}_{}", FILE_EXTENSION, std::process::id(), counter));
+    fs::write(&temporary_file, &contents)?;
+    fs::rename(&temporary_file, cache_file)?;
+
+    let mut disk_usage = DISK_USAGE.lock().unwrap();
+    let usage: u64 = match *disk_usage {
+        Some(usage) => usage + contents.len() as u64,
+        None => cache_files(cache_dir).iter().map(|(_, size, _)| size).sum(), // (including the new one)
+    };
+    *disk_usage = Some(if usage > CONFIG.thumbnail_cache_size { evict(cache_dir, CONFIG.thumbnail_cache_size).0 } else { usage });
+    return Ok(());
+}
+
+/// The cache files in the `cache_dir` with their size and modification time (i.e. when they were used last).
+fn cache_files(cache_dir: &Path) -> Vec<(PathBuf, u64, SystemTime)> {
+    let Ok(entries) = fs::read_dir(cache_dir) else { return Vec::new() };
+    entries.flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == FILE_EXTENSION))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().unwrap_or(UNIX_EPOCH)))
+        })
+        .collect()
+}
+
+/// Deletes the least recently used cache files in the `cache_dir` until they take up at most 90% of the
+/// `max_size` (so that not every new thumbnail causes an eviction). Returns the size of the remaining cache
+/// files and the number of deleted ones.
+fn evict(cache_dir: &Path, max_size: u64) -> (u64, usize) {
+    let mut files: Vec<(PathBuf, u64, SystemTime)> = cache_files(cache_dir);
+    files.sort_by_key(|(_, _, last_used)| *last_used);
+    let mut usage: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let mut deleted: usize = 0;
+    if usage <= max_size {
+        return (usage, 0);
+    }
+    for (path, size, _) in files {
+        if usage <= max_size / 10 * 9 {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            usage -= size;
+            deleted += 1;
+        }
+    }
+    return (usage, deleted);
+}
+
+/// `http_share cache prune [OPTIONS]`: deletes the cached thumbnails of files that changed or don't exist
+/// anymore, then the least recently used ones if the cache is larger than allowed, the cache folder and its
+/// maximum size being given by the `config` (i.e. the same options as when starting the server).
+pub fn prune(config: &Config) {
+    let cache_dir: PathBuf = cache_dir(config);
+    let mut stale: usize = 0;
+    for (path, _, _) in cache_files(&cache_dir) {
+        let up_to_date: bool = fs::read(&path).ok()
+            .and_then(|contents| parse(&contents))
+            .is_some_and(|(cached_key, _, _)| {
+                let mut lines = cached_key.lines();
+                let (source, size) = (lines.next().unwrap_or(""), lines.next().and_then(|size| size.parse().ok()).unwrap_or(0));
+                key(Path::new(source), size).as_deref() == Some(cached_key.as_str())
+            });
+        if !up_to_date && fs::remove_file(&path).is_ok() {
+            stale += 1;
+        }
+    }
+    let (usage, evicted) = evict(&cache_dir, config.thumbnail_cache_size);
+    println!("Pruned the thumbnail cache in {}: deleted {} outdated and {} least recently used thumbnails, {}B are left (maximum: {}B).",
+             cache_dir.display(), stale, evicted, usage.separated_string(), config.thumbnail_cache_size.separated_string());
+}
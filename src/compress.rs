@@ -0,0 +1,143 @@
+//! Compression of response bodies ('Content-Encoding'), negotiated using the 'Accept-Encoding'
+//! request header (RFC 9110, section 12.5.3).
+//!
+//! Bodies are compressed while they are being sent, so even large text files never have to be held
+//! in memory (cf. `HTTPResponse::compressed`).
+
+use std::io::{self, Write};
+
+/// Bodies known to be smaller than this (in bytes) are not worth compressing: the compressed body
+/// would hardly be smaller, if at all.
+pub const MIN_COMPRESSED_SIZE: u64 = 1024;
+
+/// A compression scheme for response bodies, i.e. a value of the 'Content-Encoding' header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl ContentEncoding {
+    /// All supported encodings, in the order they are preferred by the server when the client
+    /// accepts several of them equally.
+    pub const ALL: [ContentEncoding; 3] = [ContentEncoding::Brotli, ContentEncoding::Zstd, ContentEncoding::Gzip];
+
+    /// The name of this encoding as used in the 'Accept-Encoding' and 'Content-Encoding' headers.
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    /// The file extension of precompressed files in this encoding (without the dot), e.g. "gz" for
+    /// "app.js.gz" next to "app.js".
+    pub fn extension(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Zstd => "zst",
+            ContentEncoding::Gzip => "gz",
+        }
+    }
+
+    /// The encodings accepted according to the value of an 'Accept-Encoding' header, the most preferred
+    /// one first. Encodings with a "q=0" weight are excluded, "*" stands for all encodings not mentioned
+    /// otherwise. Without such a header (`None`), no encoding is accepted.
+    pub fn accepted(accept_encoding: Option<&str>) -> Vec<ContentEncoding> {
+        let weights: Vec<(String, f32)> = accept_encoding.unwrap_or("").split(',')
+            .filter_map(|coding| {
+                let mut parameters = coding.split(';');
+                let name: String = parameters.next()?.trim().to_lowercase();
+                let weight: f32 = parameters
+                    .filter_map(|parameter| parameter.trim().strip_prefix("q=").or_else(|| parameter.trim().strip_prefix("Q=")))
+                    .find_map(|weight| weight.trim().parse().ok())
+                    .unwrap_or(1.0);
+                if name.is_empty() { None } else { Some((name, weight)) }
+            })
+            .collect();
+        let weight_of = |name: &str| -> Option<f32> {
+            let aliases: &[&str] = if name == "gzip" { &["gzip", "x-gzip"] } else { std::slice::from_ref(&name) };
+            weights.iter().find(|(coding, _)| aliases.contains(&coding.as_str()))
+                .or_else(|| weights.iter().find(|(coding, _)| coding == "*"))
+                .map(|(_, weight)| *weight)
+        };
+
+        let mut accepted: Vec<(ContentEncoding, f32)> = Self::ALL.iter()
+            .filter_map(|&encoding| weight_of(encoding.name()).map(|weight| (encoding, weight)))
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        accepted.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap()); // A stable sort, i.e. ties keep the server's preference.
+        return accepted.into_iter().map(|(encoding, _)| encoding).collect();
+    }
+
+    /// Writes the body generated by `write_body` to `writer`, compressed using this encoding.
+    pub fn encode(self, writer: &mut dyn Write, write_body: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        match self {
+            ContentEncoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(writer, 64 * 1024, 5, 22);
+                write_body(&mut encoder)?;
+                encoder.flush()?; // `into_inner` ignores errors, so they are surfaced here.
+                encoder.into_inner();
+            },
+            ContentEncoding::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, 3)?;
+                write_body(&mut encoder)?;
+                encoder.finish()?;
+            },
+            ContentEncoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+                write_body(&mut encoder)?;
+                encoder.finish()?;
+            },
+        }
+        return Ok(());
+    }
+}
+
+/// Whether content of the given MIME type (the value of a 'Content-Type' header) is worth compressing,
+/// i.e. it's text-based. Images, videos, archives etc. are compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime_type: String = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    return mime_type.starts_with("text/")
+        || mime_type.ends_with("+xml")
+        || mime_type.ends_with("+json")
+        || ["application/json", "application/xml", "application/javascript", "application/wasm", "image/x-icon", "image/bmp"]
+            .contains(&mime_type.as_str());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ContentEncoding::*;
+
+    #[test]
+    fn accepted_encodings() {
+        assert_eq!(ContentEncoding::accepted(None), vec![]);
+        assert_eq!(ContentEncoding::accepted(Some("")), vec![]);
+        assert_eq!(ContentEncoding::accepted(Some("gzip, deflate, br")), vec![Brotli, Gzip]);
+        assert_eq!(ContentEncoding::accepted(Some("identity")), vec![]);
+        assert_eq!(ContentEncoding::accepted(Some("x-gzip")), vec![Gzip]);
+        assert_eq!(ContentEncoding::accepted(Some("GZip;Q=1, ZSTD")), vec![Zstd, Gzip]);
+    }
+
+    #[test]
+    fn accepted_encodings_by_weight() {
+        assert_eq!(ContentEncoding::accepted(Some("gzip;q=0.5, zstd;q=0.8, br;q=0.1")), vec![Zstd, Gzip, Brotli]);
+        assert_eq!(ContentEncoding::accepted(Some("gzip;q=0, br")), vec![Brotli]);
+        assert_eq!(ContentEncoding::accepted(Some("*")), ContentEncoding::ALL.to_vec());
+        assert_eq!(ContentEncoding::accepted(Some("*;q=0.5, gzip")), vec![Gzip, Brotli, Zstd]);
+        assert_eq!(ContentEncoding::accepted(Some("*;q=0, zstd")), vec![Zstd]);
+        assert_eq!(ContentEncoding::accepted(Some("br;q=invalid")), vec![Brotli]); // (the default weight)
+    }
+
+    #[test]
+    fn compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(!is_compressible("image/jpeg"));
+        assert!(!is_compressible("application/zip"));
+    }
+}
@@ -0,0 +1,348 @@
+//! The server configuration, given as command line options, e.g.:
+//! `./http_share --uploads --max-upload-size=2G --upload-collision=rename`
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The configuration of this server, parsed from the command line arguments on first use.
+    pub static ref CONFIG: Config = Config::from_args(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("Error: {}\r\n\r\n{}", err, Config::USAGE);
+        process::exit(1);
+    });
+}
+
+/// What to do when an uploaded file has the same name as a file that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Keep the existing file and store the upload as "name (1).ext", "name (2).ext", etc.
+    Rename,
+    /// Replace the existing file with the uploaded one.
+    Overwrite,
+    /// Keep the existing file and refuse the upload.
+    Reject,
+}
+
+/// What a user is allowed to do besides viewing and downloading files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    /// Whether the user may upload files (if uploads are enabled using `--uploads`).
+    pub upload: bool,
+    /// Whether the user may create folders and rename, move and delete files and folders
+    /// (if file management is enabled using `--file-management`).
+    pub manage: bool,
+    /// Whether the user may see and download hidden files and folders (cf. hidden.rs).
+    pub hidden: bool,
+}
+
+impl Permissions {
+    /// All permissions.
+    pub const ALL: Permissions = Permissions { upload: true, manage: true, hidden: true };
+}
+
+/// A user that can log in to the server (using HTTP Basic Authentication).
+#[derive(Debug, Clone)]
+pub struct UserAccount {
+    /// The username, empty for anonymous users (when no credentials are necessary).
+    pub name: String,
+    pub password: String,
+    pub permissions: Permissions,
+}
+
+impl UserAccount {
+    /// The anonymous user, used when no credentials are necessary. Anonymous users may upload files
+    /// (when uploads are enabled) but never manage files.
+    pub fn anonymous() -> Self {
+        Self { name: String::new(), password: String::new(), permissions: Permissions { upload: true, manage: false, hidden: false } }
+    }
+
+    /// Whether this user may upload files right now.
+    pub fn can_upload(&self) -> bool {
+        CONFIG.uploads_enabled && self.permissions.upload
+    }
+
+    /// Whether this user may create, rename, move and delete files and folders right now.
+    /// Anonymous users never may, even if they were given the permission.
+    pub fn can_manage(&self) -> bool {
+        CONFIG.file_management_enabled && self.permissions.manage && !self.name.is_empty()
+    }
+
+    /// Whether this user may see and download hidden files and folders (cf. hidden.rs).
+    /// Anonymous users never may.
+    pub fn can_see_hidden(&self) -> bool {
+        self.permissions.hidden && !self.name.is_empty()
+    }
+
+    /// Parses a user account given as "NAME:PASSWORD" or "NAME:PASSWORD:PERMISSIONS", where PERMISSIONS
+    /// is a comma-separated list of "upload", "manage" and "hidden".
+    fn parse(user: &str) -> Result<Self, String> {
+        let mut parts = user.splitn(3, ':');
+        let name: &str = parts.next().filter(|name| !name.is_empty()).ok_or(format!("Invalid user (missing name): {}", user))?;
+        let password: &str = parts.next().ok_or(format!("Invalid user (missing password): {}", user))?;
+        let mut permissions = Permissions::default();
+        for permission in parts.next().unwrap_or("").split(',').filter(|permission| !permission.is_empty()) {
+            match permission {
+                "upload" => permissions.upload = true,
+                "manage" => permissions.manage = true,
+                "hidden" => permissions.hidden = true,
+                _ => return Err(format!("Invalid permission: {}", permission)),
+            }
+        }
+        return Ok(Self { name: name.to_string(), password: password.to_string(), permissions });
+    }
+}
+
+/// The longest `--upload-expiration` in hours, 100 years.
+const MAX_UPLOAD_EXPIRATION_HOURS: u64 = 100 * 365 * 24;
+
+/// The configuration of this server.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Whether files may be uploaded into the shared folder (`--uploads`).
+    pub uploads_enabled: bool,
+    /// The maximum size of a single upload request in bytes (`--max-upload-size=...`), `None` = unlimited.
+    pub max_upload_size: Option<u64>,
+    /// What to do when an uploaded file already exists (`--upload-collision=rename|overwrite|reject`).
+    pub upload_collision: CollisionPolicy,
+    /// How long partial resumable uploads are kept before they are deleted (`--upload-expiration=HOURS`).
+    pub upload_expiration: Duration,
+    /// Whether files and folders may be created, renamed, moved and deleted (`--file-management`).
+    pub file_management_enabled: bool,
+    /// The folder deleted files are moved into (`--trash=PATH`), relative to the shared folder unless absolute.
+    pub trash_dir: PathBuf,
+    /// Whether the shared folder can be mounted as a network drive using WebDAV (`--webdav`).
+    pub webdav_enabled: bool,
+    /// Additional user accounts (`--user=NAME:PASSWORD:PERMISSIONS`), besides the one entered on startup.
+    pub users: Vec<UserAccount>,
+    /// The bandwidth limits in bytes per second, cf. `BandwidthLimits`.
+    pub bandwidth_limits: BandwidthLimits,
+    /// Whether files and folders whose names start with a '.' are shown (`--show-dotfiles`), cf. hidden.rs.
+    pub show_dotfiles: bool,
+    /// Whether the names of all files and folders are kept in memory for instant searches (`--search-index`).
+    pub search_index_enabled: bool,
+    /// Whether the contents of text files and PDFs are indexed for full-text searches (`--fulltext-index`).
+    pub fulltext_index_enabled: bool,
+    /// Whether the `index.html` of a folder is served instead of its listing (`--index-html`), cf. site.rs.
+    pub index_html_enabled: bool,
+    /// Whether the shared folder is served as a website with clean URLs (`--static-site`), cf. site.rs.
+    pub static_site: bool,
+    /// The page shown for missing files (`--not-found-page=PATH`), relative to the shared folder unless absolute.
+    pub not_found_page: Option<PathBuf>,
+    /// The file served for missing paths without a file extension (`--spa-fallback=PATH`), relative to the
+    /// shared folder unless absolute.
+    pub spa_fallback: Option<PathBuf>,
+    /// The sizes (in pixels) thumbnails of images and videos are generated in (`--thumbnail-sizes=SIZES`),
+    /// cf. thumbnail.rs.
+    pub thumbnail_sizes: Vec<u32>,
+    /// The folder generated thumbnails are cached in (`--thumbnail-cache=PATH`), `None` = the user's cache
+    /// folder, cf. cache.rs.
+    pub thumbnail_cache_dir: Option<PathBuf>,
+    /// The maximum total size of the cached thumbnails in bytes (`--thumbnail-cache-size=SIZE`).
+    pub thumbnail_cache_size: u64,
+}
+
+/// Limits of the bandwidth used for sending responses, in bytes per second (`None` = unlimited).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BandwidthLimits {
+    /// The limit for all responses together (`--rate-limit=RATE`).
+    pub global: Option<u64>,
+    /// The limit for all responses to the same IP address together (`--rate-limit-per-ip=RATE`).
+    pub per_ip: Option<u64>,
+    /// The limit for all responses to the same logged in user together (`--rate-limit-per-user=RATE`).
+    pub per_user: Option<u64>,
+    /// The limit for every single response (`--rate-limit-per-connection=RATE`).
+    pub per_connection: Option<u64>,
+    /// Whether range requests (i.e. streamed videos and music) are served before other responses, like
+    /// downloads of whole files or folders, when the bandwidth is limited (`--prioritize-ranges`).
+    pub prioritize_ranges: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            uploads_enabled: false,
+            max_upload_size: None,
+            upload_collision: CollisionPolicy::Rename,
+            upload_expiration: Duration::from_secs(24 * 60 * 60),
+            file_management_enabled: false,
+            trash_dir: PathBuf::from(".http_share_trash"),
+            webdav_enabled: false,
+            users: Vec::new(),
+            bandwidth_limits: BandwidthLimits::default(),
+            show_dotfiles: false,
+            search_index_enabled: false,
+            fulltext_index_enabled: false,
+            index_html_enabled: false,
+            static_site: false,
+            not_found_page: None,
+            spa_fallback: None,
+            thumbnail_sizes: vec![256, 512, 1024],
+            thumbnail_cache_dir: None,
+            thumbnail_cache_size: 1 << 30,
+        }
+    }
+}
+
+impl Config {
+    /// The help text printed for `--help` and when invalid options were given.
+    pub const USAGE: &'static str =
+"Usage: http_share [OPTIONS]
+
+Shares the files of the folder the http_share binary is put in via HTTP.
+`http_share cache prune [OPTIONS]` deletes outdated and least recently used thumbnails from the cache.
+
+Options:
+  --uploads                      Allow uploading files into the shared folder
+  --max-upload-size=SIZE         Maximum size of one upload, e.g. 500M or 2G (default: unlimited)
+  --upload-collision=POLICY      What to do when an uploaded file already exists:
+                                 rename (default), overwrite or reject
+  --upload-expiration=HOURS      How long unfinished resumable uploads are kept, at most 876000
+                                 (100 years, default: 24)
+  --file-management              Allow creating folders and renaming, moving and deleting files and
+                                 folders (only for users with the 'manage' permission)
+  --trash=PATH                   Folder deleted files are moved into (default: .http_share_trash)
+  --webdav                       Allow mounting the shared folder as a network drive using WebDAV
+                                 (writing requires the same permissions as in the browser)
+  --user=NAME:PASSWORD[:PERMS]   An additional user account, PERMS being a comma-separated list of
+                                 'upload', 'manage' and 'hidden' (seeing hidden files) (can be
+                                 given multiple times); the user entered on startup always has
+                                 all permissions
+  --rate-limit=RATE              Maximum bandwidth for all downloads together, in bytes per second,
+                                 e.g. 500K or 10M (default: unlimited)
+  --rate-limit-per-ip=RATE       Maximum bandwidth for all downloads to one IP address together
+  --rate-limit-per-user=RATE     Maximum bandwidth for all downloads of one logged in user together
+  --rate-limit-per-connection=RATE
+                                 Maximum bandwidth for every single download
+  --prioritize-ranges            Serve range requests (streamed videos and music) before other
+                                 downloads when the bandwidth is limited
+  --show-dotfiles                Don't hide files and folders whose names start with a '.' (files
+                                 matching the patterns in .httpshareignore files are still hidden)
+  --search-index                 Keep the names of all files and folders in memory (updated when
+                                 they change) so that searching by name is instant
+  --fulltext-index               Index the contents of text files (incl. source code) and PDFs (in
+                                 the folder .http_share_fulltext) for searching in them
+  --index-html                   Serve the index.html (or index.htm) of a folder instead of listing
+                                 it (the listing is still shown when a query like ?view=list is given)
+  --static-site                  Serve the shared folder as a website: like --index-html, plus clean
+                                 URLs (/about for about.html) and 404.html as the error page
+  --not-found-page=PATH          Page shown for missing files (default with --static-site: 404.html)
+  --spa-fallback=PATH            File served for missing paths without a file extension, for the
+                                 client-side routers of single-page apps (e.g. index.html)
+  --thumbnail-sizes=SIZES        Comma-separated sizes in pixels thumbnails are generated in
+                                 (default: 256,512,1024)
+  --thumbnail-cache=PATH         Folder thumbnails are cached in (default: ~/.cache/http_share/thumbnails)
+  --thumbnail-cache-size=SIZE    Maximum size of the thumbnail cache, e.g. 500M (default: 1G)
+  --help                         Print this help text";
+
+    /// Parses the given command line arguments (without the name of the binary).
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::default();
+        for arg in args {
+            let (option, value): (&str, Option<&str>) = match arg.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            match (option, value) {
+                ("--help", None) => {
+                    println!("{}", Self::USAGE);
+                    process::exit(0);
+                },
+                ("--uploads", None) => config.uploads_enabled = true,
+                ("--max-upload-size", Some(size)) => config.max_upload_size = Some(parse_size(size)?),
+                ("--upload-collision", Some("rename")) => config.upload_collision = CollisionPolicy::Rename,
+                ("--upload-collision", Some("overwrite")) => config.upload_collision = CollisionPolicy::Overwrite,
+                ("--upload-collision", Some("reject")) => config.upload_collision = CollisionPolicy::Reject,
+                ("--upload-expiration", Some(hours)) => config.upload_expiration = hours.parse::<u64>().ok()
+                    .filter(|&hours| hours <= MAX_UPLOAD_EXPIRATION_HOURS) // (so that the expiration dates can be formatted)
+                    .and_then(|hours| hours.checked_mul(60 * 60))
+                    .map(Duration::from_secs)
+                    .ok_or(format!("Invalid number of hours: {}", hours))?,
+                ("--file-management", None) => config.file_management_enabled = true,
+                ("--trash", Some(path)) if !path.is_empty() => config.trash_dir = PathBuf::from(path),
+                ("--webdav", None) => config.webdav_enabled = true,
+                ("--user", Some(user)) => config.users.push(UserAccount::parse(user)?),
+                ("--rate-limit", Some(rate)) => config.bandwidth_limits.global = Some(parse_size(rate)?),
+                ("--rate-limit-per-ip", Some(rate)) => config.bandwidth_limits.per_ip = Some(parse_size(rate)?),
+                ("--rate-limit-per-user", Some(rate)) => config.bandwidth_limits.per_user = Some(parse_size(rate)?),
+                ("--rate-limit-per-connection", Some(rate)) => config.bandwidth_limits.per_connection = Some(parse_size(rate)?),
+                ("--prioritize-ranges", None) => config.bandwidth_limits.prioritize_ranges = true,
+                ("--show-dotfiles", None) => config.show_dotfiles = true,
+                ("--search-index", None) => config.search_index_enabled = true,
+                ("--fulltext-index", None) => config.fulltext_index_enabled = true,
+                ("--index-html", None) => config.index_html_enabled = true,
+                ("--static-site", None) => config.static_site = true,
+                ("--not-found-page", Some(path)) if !path.is_empty() => config.not_found_page = Some(PathBuf::from(path)),
+                ("--spa-fallback", Some(path)) if !path.is_empty() => config.spa_fallback = Some(PathBuf::from(path)),
+                ("--thumbnail-sizes", Some(sizes)) => config.thumbnail_sizes = sizes.split(',')
+                    .map(|size| size.trim().parse::<u32>().ok().filter(|&size| size > 0).ok_or(format!("Invalid thumbnail size: {}", size)))
+                    .collect::<Result<Vec<u32>, String>>()?,
+                ("--thumbnail-cache", Some(path)) if !path.is_empty() => config.thumbnail_cache_dir = Some(PathBuf::from(path)),
+                ("--thumbnail-cache-size", Some(size)) => config.thumbnail_cache_size = parse_size(size)?,
+                _ => return Err(format!("Invalid option: {}", arg)),
+            }
+        }
+        return Ok(config);
+    }
+}
+
+/// Parses a size like "1024", "500K", "500M" or "2G" (binary units) into a number of bytes.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier): (&str, u64) = match size.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&size[..size.len() - 1], 1 << 10),
+        Some('M') => (&size[..size.len() - 1], 1 << 20),
+        Some('G') => (&size[..size.len() - 1], 1 << 30),
+        Some('T') => (&size[..size.len() - 1], 1 << 40),
+        _ => (size, 1),
+    };
+    return number.trim().parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or(format!("Invalid size: {}", size));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500K"), Ok(500 << 10));
+        assert_eq!(parse_size("500m"), Ok(500 << 20));
+        assert_eq!(parse_size(" 2 G "), Ok(2 << 30));
+        assert_eq!(parse_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_size("0"), Ok(0));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for size in ["", "K", "abc", "-1", "1.5G", "5P", "99999999999T", "18446744073709551616"] {
+            assert_eq!(parse_size(size), Err(format!("Invalid size: {}", size.trim())));
+        }
+    }
+
+    #[test]
+    fn upload_expiration() {
+        let config = |arg: &str| Config::from_args(std::iter::once(arg.to_string()));
+        assert_eq!(config("--upload-expiration=48").unwrap().upload_expiration, Duration::from_secs(48 * 60 * 60));
+        assert!(config(&format!("--upload-expiration={}", MAX_UPLOAD_EXPIRATION_HOURS)).is_ok());
+        assert!(config(&format!("--upload-expiration={}", MAX_UPLOAD_EXPIRATION_HOURS + 1)).is_err());
+        assert!(config("--upload-expiration=99999999999999999").is_err());
+        assert!(config("--upload-expiration=-1").is_err());
+    }
+
+    #[test]
+    fn users() {
+        let user: UserAccount = UserAccount::parse("bob:pw:upload,hidden").unwrap();
+        assert_eq!((user.name.as_str(), user.password.as_str()), ("bob", "pw"));
+        assert!(user.permissions.upload && user.permissions.hidden && !user.permissions.manage);
+        let user: UserAccount = UserAccount::parse("carol:").unwrap();
+        assert!(user.password.is_empty() && !user.permissions.upload);
+        assert!(UserAccount::parse(":pw").is_err());
+        assert!(UserAccount::parse("dave").is_err());
+        assert!(UserAccount::parse("alice:se:cret").is_err()); // ("cret" isn't a permission)
+    }
+}
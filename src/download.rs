@@ -0,0 +1,281 @@
+//! Downloading a whole folder as one archive (`?download=zip`, `?download=tar`, `?download=tar.gz`
+//! or `?download=tar.zst` on any directory page), e.g. `curl http://host/dir?download=tar | tar x`.
+//! Some of the items of a folder can be downloaded as one archive, too, by `POST`ing their names
+//! (as "item=..." form fields) to the same URL, which is what the "Download selected" button does.
+//!
+//! The archive is generated while it is being sent, i.e. neither temporary files nor much memory
+//! are needed, however large the folder is. Everything not shown in the directory listing (like the
+//! folders this server uses internally and hidden files, cf. hidden.rs) is left out of the archive, too.
+//! Symbolic links are treated the same way as everywhere else on this server: links to files are
+//! archived as the files they point to, links to folders are left out (as they might lead into a loop).
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, Error, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use ansi_term::Colour::Red;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http_share::{HTTPRequest, HTTPResponse};
+use http_share::tar::TarWriter;
+use http_share::throttle::Throttle;
+use http_share::zip::{CompressionMethod, ZipWriter};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use crate::config::UserAccount;
+use crate::date_time_str;
+use crate::{hidden, settings};
+use crate::manage::form_urlencoded_pairs;
+
+/// The maximum size of the body of a "Download selected" request, in bytes.
+const MAX_SELECTION_BODY_SIZE: u64 = 1024 * 1024;
+
+/// The archive formats a folder can be downloaded as, the values of the "download=..." URL GET parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// Parses the value of the "download=..." URL GET parameter.
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            "tar.zst" => Some(ArchiveFormat::TarZst),
+            _ => None,
+        }
+    }
+
+    /// The file extension of archives of this format (without the leading '.').
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+
+    /// The MIME type of archives of this format.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::TarZst => "application/zstd",
+        }
+    }
+
+    /// Writes an archive of this format to `writer`, containing the given `items`, i.e. files and
+    /// folders (including everything in them that is visible to the `user`) and the names they are given in the archive.
+    fn write_archive(self, writer: &mut dyn Write, items: &[(PathBuf, String)], root_dir: &Path, user: &UserAccount) -> io::Result<()> {
+        match self {
+            ArchiveFormat::Zip => {
+                let mut zip_writer = ZipWriter::new(writer);
+                add_items(&mut zip_writer, items, root_dir, user)?;
+                zip_writer.finish()?;
+            },
+            ArchiveFormat::Tar => {
+                let mut tar_writer = TarWriter::new(writer);
+                add_items(&mut tar_writer, items, root_dir, user)?;
+                tar_writer.finish()?;
+            },
+            ArchiveFormat::TarGz => {
+                let mut tar_writer = TarWriter::new(GzEncoder::new(writer, Compression::default()));
+                add_items(&mut tar_writer, items, root_dir, user)?;
+                tar_writer.finish()?.finish()?;
+            },
+            ArchiveFormat::TarZst => {
+                let mut tar_writer = TarWriter::new(zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?);
+                add_items(&mut tar_writer, items, root_dir, user)?;
+                tar_writer.finish()?.finish()?;
+            },
+        }
+        return Ok(());
+    }
+}
+
+/// Responds to `stream` with the folder `dir_path` packed into an archive of the given `format`
+/// (the value of the "download=..." URL GET parameter), only as fast as the `throttle` allows.
+/// Only what is visible to the `user` is put into the archive.
+pub fn download_response(dir_path: &Path, root_dir: &Path, user: &UserAccount, format: &str, throttle: Throttle, stream: &mut TcpStream) -> io::Result<()> {
+    let format: ArchiveFormat = parse_format(format, stream)?;
+    let folder_name: String = folder_name(dir_path);
+    let items: Vec<(PathBuf, String)> = vec![(dir_path.to_path_buf(), folder_name.clone())];
+    archive_response(format, items, &folder_name, root_dir, user, throttle, stream)
+}
+
+/// Responds to `stream` with the items of the folder `dir_path` selected in the `http_request`
+/// (a `POST` request whose form fields called "item" contain their names) packed
+/// into an archive of the given `format`. The items are put at the top level of the archive.
+/// Only what is visible to the `user` may be selected and is put into the archive.
+pub fn selection_download_response(http_request: &HTTPRequest, dir_path: &Path, root_dir: &Path, user: &UserAccount, format: &str, throttle: Throttle, stream: &mut TcpStream) -> io::Result<()> {
+    let format: ArchiveFormat = parse_format(format, stream)?;
+    let mut body = String::new();
+    http_request.body_reader(stream).take(MAX_SELECTION_BODY_SIZE).read_to_string(&mut body)?;
+
+    let mut items: Vec<(PathBuf, String)> = Vec::new();
+    for (_, name) in form_urlencoded_pairs(&body).filter(|(key, _)| key == "item") {
+        // Only items directly within the folder may be selected:
+        let fs_path: PathBuf = dir_path.join(&name);
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\'])
+            || fs::symlink_metadata(&fs_path).is_err() || hidden::is_hidden_in_folder(&fs_path, fs_path.is_dir(), root_dir, user)
+            || (fs_path.is_dir() && settings::is_locked(&fs_path, user)) {
+            HTTPResponse::new_404_not_found(&name).send_to_tcp_stream(stream)?;
+            return Err(Error::other(format!("selected {} for downloading, which is not in {}", name, dir_path.display())));
+        }
+        if !items.iter().any(|(_, item_name)| *item_name == name) {
+            items.push((fs_path, name));
+        }
+    }
+    if items.is_empty() {
+        HTTPResponse::builder().status(400).bytes("No items were selected.").send_to_tcp_stream(stream)?;
+        return Err(Error::other("requested to download a selection of no items"));
+    }
+    archive_response(format, items, &folder_name(dir_path), root_dir, user, throttle, stream)
+}
+
+/// Helper function for `download_response` and `selection_download_response`: parses the `format`
+/// and responds with a '400 Bad Request' if it's not supported.
+fn parse_format(format: &str, stream: &mut TcpStream) -> io::Result<ArchiveFormat> {
+    match ArchiveFormat::parse(format) {
+        Some(format) => Ok(format),
+        None => {
+            HTTPResponse::builder().status(400).bytes(format!("Unsupported archive format: {}", format)).send_to_tcp_stream(stream)?;
+            Err(Error::other(format!("requested a folder as an unsupported archive format: {}", format)))
+        }
+    }
+}
+
+/// The name of the folder `dir_path`, the shared folder itself being named after the folder the binary is in.
+fn folder_name(dir_path: &Path) -> String {
+    dir_path.canonicalize().ok()
+        .and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or("http_share".to_string())
+}
+
+/// Helper function for `download_response` and `selection_download_response`: responds with an
+/// archive called `archive_name` (plus the extension) containing the `items`, which is generated
+/// while it's being sent (only as fast as the `throttle` allows), leaving out what is hidden from the `user`.
+fn archive_response(format: ArchiveFormat, items: Vec<(PathBuf, String)>, archive_name: &str, root_dir: &Path, user: &UserAccount, throttle: Throttle, stream: &mut TcpStream) -> io::Result<()> {
+    // The archive is only generated while the response is being written, so everything it needs has to be owned:
+    let root_dir: PathBuf = root_dir.to_path_buf();
+    let user: UserAccount = user.clone();
+    HTTPResponse::builder()
+        .header("Content-Type", format.mime_type())
+        .header("Content-Disposition", content_disposition(&format!("{}.{}", archive_name, format.extension())))
+        .stream(move |writer| format.write_archive(writer, &items, &root_dir, &user))
+        .throttled(throttle)
+        .send_to_tcp_stream(stream)
+}
+
+/// Returns the value of a 'Content-Disposition' header making the browser save the response as
+/// `filename` (cf. RFC 6266): old browsers only understand the ASCII `filename`, all others use the
+/// (percent-encoded) UTF-8 `filename*`.
+fn content_disposition(filename: &str) -> String {
+    let ascii_filename: String = filename.chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii_filename, utf8_percent_encode(filename, NON_ALPHANUMERIC))
+}
+
+/// The archive writers folders can be added to by `add_folder`.
+trait ArchiveWriter {
+    /// Adds the (empty) folder called `name` in the archive.
+    fn add_directory(&mut self, name: &str, metadata: &Metadata) -> io::Result<()>;
+    /// Adds `file` (located at `path`) under the given `name`.
+    fn add_file(&mut self, name: &str, path: &Path, metadata: &Metadata, file: &mut File) -> io::Result<()>;
+}
+
+impl<W: Write> ArchiveWriter for ZipWriter<W> {
+    fn add_directory(&mut self, name: &str, metadata: &Metadata) -> io::Result<()> {
+        ZipWriter::add_directory(self, name, modified(metadata))
+    }
+
+    fn add_file(&mut self, name: &str, path: &Path, metadata: &Metadata, file: &mut File) -> io::Result<()> {
+        ZipWriter::add_file(self, name, modified(metadata), metadata.len(), CompressionMethod::for_path(path), file)
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add_directory(&mut self, name: &str, metadata: &Metadata) -> io::Result<()> {
+        TarWriter::add_directory(self, name, unix_mode(metadata), modified(metadata))
+    }
+
+    fn add_file(&mut self, name: &str, _path: &Path, metadata: &Metadata, file: &mut File) -> io::Result<()> {
+        TarWriter::add_file(self, name, unix_mode(metadata), modified(metadata), metadata.len(), file)
+    }
+}
+
+/// Helper function for `ArchiveFormat::write_archive`: adds all `items` (paths and the names they
+/// are given in the archive) to the archive.
+fn add_items(archive_writer: &mut dyn ArchiveWriter, items: &[(PathBuf, String)], root_dir: &Path, user: &UserAccount) -> io::Result<()> {
+    for (fs_path, name) in items {
+        add_item(archive_writer, fs_path, name, root_dir, user)?;
+    }
+    return Ok(());
+}
+
+/// Adds the file or folder (including everything in it) at `fs_path` to the archive, calling it `name` in there.
+/// Files that cannot be read are skipped (and logged), so that one of them doesn't spoil the entire archive.
+fn add_item(archive_writer: &mut dyn ArchiveWriter, fs_path: &Path, name: &str, root_dir: &Path, user: &UserAccount) -> io::Result<()> {
+    if fs_path.is_dir() {
+        return add_folder(archive_writer, fs_path, name, root_dir, user);
+    }
+    let (mut file, metadata) = match File::open(fs_path).and_then(|file| file.metadata().map(|metadata| (file, metadata))) {
+        Ok(file_and_metadata) => file_and_metadata,
+        Err(err) => {
+            eprintln!("{}", Red.paint(format!("[{}] Error: Left {} out of an archive as it could not be read: {}", date_time_str(), fs_path.display(), err)));
+            return Ok(());
+        }
+    };
+    archive_writer.add_file(name, fs_path, &metadata, &mut file)
+}
+
+/// Helper function for `add_item`: recursively adds the folder `fs_path` and everything in it to the
+/// archive, calling it `name` in there, except for what is hidden from the `user`.
+fn add_folder(archive_writer: &mut dyn ArchiveWriter, fs_path: &Path, name: &str, root_dir: &Path, user: &UserAccount) -> io::Result<()> {
+    archive_writer.add_directory(name, &fs::metadata(fs_path)?)?;
+
+    let hidden_items = hidden::FolderRules::of(fs_path, root_dir, user);
+    let mut folder_items: Vec<PathBuf> = fs::read_dir(fs_path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| !hidden_items.hides(path, path.is_dir()))
+        .filter(|path| !(path.is_dir() && settings::is_locked(path, user))) // Protected subfolders need their own password.
+        .filter(|path| !(path.is_dir() && path.is_symlink())) // Symbolic links to folders might lead into a loop.
+        .collect();
+    folder_items.sort_by_key(|path| path.file_name().map(|name| name.to_string_lossy().to_lowercase()));
+
+    for path in folder_items {
+        let item_name: String = format!("{}/{}", name, path.file_name().unwrap().to_string_lossy());
+        add_item(archive_writer, &path, &item_name, root_dir, user)?;
+    }
+    return Ok(());
+}
+
+/// The modification time of a file or folder, the Unix epoch if it is unknown.
+fn modified(metadata: &Metadata) -> SystemTime {
+    metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// The Unix permissions of a file or folder, e.g. `0o644`.
+#[cfg(unix)]
+fn unix_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// The Unix permissions of a file or folder, which are made up on other operating systems.
+#[cfg(not(unix))]
+fn unix_mode(metadata: &Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, false) => 0o644,
+        (false, true) => 0o444,
+    }
+}
@@ -0,0 +1,486 @@
+//! Full-text search in the contents of text files (plain text, Markdown, source code, ...) and PDFs
+//! ("q=..." URL GET parameter, e.g. `/Notes?q=meeting+agenda`), enabled using `--fulltext-index`.
+//!
+//! A background thread keeps an inverted index of these files (which words they contain and how often),
+//! updated by a file system watcher whenever files change. It is stored on disk (in `INDEX_DIR_NAME`), so
+//! that after a restart only the files changed in the meantime have to be read again. Hidden files and
+//! folders (cf. hidden.rs) are not indexed, even for users allowed to see them.
+//!
+//! A search lists the files within the current folder that contain all words of the query, those containing
+//! them most often first, each with a snippet of its text in which the words are highlighted. Files hidden
+//! since they were indexed and files the server may not read (anymore) are left out.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Error, Write};
+use std::mem;
+use std::net::TcpStream;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ansi_term::Colour::Red;
+use http_share::{HTTPRequest, HTTPResponse};
+use lazy_static::lazy_static;
+use notify::EventKind;
+use notify::event::{AccessKind, AccessMode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use crate::config::UserAccount;
+use crate::search::{relative_path, walk, watch};
+use crate::sort::natural_cmp;
+use crate::{date_time_str, decoded_query_param, hidden, html_escape, is_internal_path, query_param, settings, write_search_form};
+
+/// The name of the hidden folder (in the root directory) in which the index is stored.
+pub const INDEX_DIR_NAME: &str = ".http_share_fulltext";
+
+/// The first line of the index file, to recognize (older) files in another format.
+const INDEX_FILE_HEADER: &str = "http_share full-text index v1";
+
+/// Larger files are not indexed.
+const MAX_FILE_SIZE: u64 = 64 << 20;
+
+/// How long to wait for further changes before updating the index (files often change several times in a row).
+const UPDATE_DELAY: Duration = Duration::from_secs(2);
+
+/// The index is updated at least this often while files keep changing.
+const MAX_UPDATE_DELAY: Duration = Duration::from_secs(30);
+
+/// How many results are listed when no "max_results=..." is given.
+const DEFAULT_MAX_RESULTS: usize = 50;
+
+/// How many results may be listed at most (even when a larger "max_results=..." is given).
+const MAX_RESULTS: usize = 500;
+
+/// How many bytes of text are shown around the first occurrence of a word in a snippet.
+const SNIPPET_LENGTH: usize = 240;
+
+/// How many bytes of extracted texts are kept in memory at most for showing snippets, cf. `cached_text`.
+const MAX_TEXT_CACHE_SIZE: usize = 32 << 20;
+
+/// The characters percent-encoded in the paths in the index file (so that they fit on one line).
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS.add(b'%');
+
+lazy_static! {
+    /// The index, once it was loaded (when `--fulltext-index` is given).
+    static ref INDEX: RwLock<Option<Index>> = RwLock::new(None);
+    /// What counts as a word: letters and digits (of any script).
+    static ref WORD: Regex = Regex::new(r"[\p{L}\p{N}]+").unwrap();
+    /// The recently extracted texts, cf. `cached_text`.
+    static ref TEXT_CACHE: Mutex<TextCache> = Mutex::new(TextCache { texts: HashMap::new(), uses: 0, size: 0 });
+}
+
+/// The texts of the files whose snippets were shown recently, cf. `cached_text`.
+struct TextCache {
+    texts: HashMap<PathBuf, CachedText>,
+    /// The number of times a text was used so far.
+    uses: u64,
+    /// The total size of the texts in bytes.
+    size: usize,
+}
+
+/// A text in the `TextCache`.
+struct CachedText {
+    /// The modification time and size of the file the text was extracted from.
+    version: (SystemTime, u64),
+    text: Arc<String>,
+    /// The value of `TextCache::uses` when the text was used last.
+    last_use: u64,
+}
+
+/// An indexed file.
+struct Document {
+    /// The modification time (in seconds since the UNIX epoch) and size of the file when it was indexed,
+    /// to notice when it changed.
+    modified: u64,
+    size: u64,
+    /// The (normalized) words in the file and how often it contains them.
+    words: HashMap<String, u32>,
+}
+
+/// The inverted index of all indexed files.
+#[derive(Default)]
+struct Index {
+    /// The indexed files by their path relative to the root directory (separated by '/').
+    documents: BTreeMap<String, Document>,
+    /// The paths of the files containing each word.
+    postings: HashMap<String, HashSet<String>>,
+}
+
+impl Index {
+    /// Adds the file at `path` (relative to the root directory) to the index, replacing it if it was indexed already.
+    fn insert(&mut self, path: String, document: Document) {
+        self.remove(&path);
+        for word in document.words.keys() {
+            self.postings.entry(word.clone()).or_default().insert(path.clone());
+        }
+        self.documents.insert(path, document);
+    }
+
+    /// Removes the file at `path` (relative to the root directory) from the index, if it is indexed.
+    fn remove(&mut self, path: &str) {
+        let Some(document) = self.documents.remove(path) else { return };
+        for word in document.words.keys() {
+            if let Some(paths) = self.postings.get_mut(word) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.postings.remove(word);
+                }
+            }
+        }
+    }
+
+    /// The paths of the indexed files within the folder `dir_path` (relative to the root directory, empty
+    /// for the root directory itself).
+    fn paths_within(&self, dir_path: &str) -> Vec<String> {
+        let prefix: String = if dir_path.is_empty() { String::new() } else { format!("{}/", dir_path) };
+        self.documents.range(prefix.clone()..)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// The files within the folder `dir_path` (relative to the root directory) containing all the `words`,
+    /// with how often they contain them, those containing them most often first.
+    fn search(&self, dir_path: &str, words: &[String]) -> Vec<(String, u32)> {
+        let prefix: String = if dir_path.is_empty() { String::new() } else { format!("{}/", dir_path) };
+        let mut posting_lists: Vec<&HashSet<String>> = match words.iter().map(|word| self.postings.get(word)).collect() {
+            Some(posting_lists) => posting_lists,
+            None => return Vec::new(), // A word is nowhere to be found.
+        };
+        posting_lists.sort_by_key(|paths| paths.len()); // Intersect starting with the shortest list.
+        let mut results: Vec<(String, u32)> = posting_lists[0].iter()
+            .filter(|path| path.starts_with(&prefix))
+            .filter(|path| posting_lists[1..].iter().all(|paths| paths.contains(*path)))
+            .map(|path| (path.clone(), words.iter().map(|word| self.documents[path].words[word]).sum()))
+            .collect();
+        results.sort_by(|(a_path, a_count), (b_path, b_count)| b_count.cmp(a_count).then_with(|| natural_cmp(a_path, b_path)));
+        return results;
+    }
+
+    /// Loads the index stored in the `file`, cf. `save`.
+    fn load(file: &Path) -> io::Result<Self> {
+        let invalid = || Error::new(io::ErrorKind::InvalidData, "invalid index file");
+        let mut lines = BufReader::new(File::open(file)?).lines();
+        if lines.next().transpose()?.as_deref() != Some(INDEX_FILE_HEADER) {
+            return Err(invalid());
+        }
+        let mut index = Index::default();
+        let mut paths: Vec<String> = Vec::new();
+        for line in lines {
+            let line: String = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[..] {
+                ["D", modified, size, path] => {
+                    let path: String = percent_decode_str(path).decode_utf8_lossy().to_string();
+                    let document = Document {
+                        modified: modified.parse().map_err(|_| invalid())?,
+                        size: size.parse().map_err(|_| invalid())?,
+                        words: HashMap::new(),
+                    };
+                    index.documents.insert(path.clone(), document);
+                    paths.push(path);
+                },
+                ["W", word, postings] => {
+                    for posting in postings.split(',') {
+                        let (number, count) = posting.split_once(':').ok_or_else(invalid)?;
+                        let path: &String = number.parse().ok().and_then(|number: usize| paths.get(number)).ok_or_else(invalid)?;
+                        let count: u32 = count.parse().map_err(|_| invalid())?;
+                        index.documents.get_mut(path).unwrap().words.insert(word.to_string(), count);
+                        index.postings.entry(word.to_string()).or_default().insert(path.clone());
+                    }
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        return Ok(index);
+    }
+
+    /// Stores the index in the `file`, replacing it atomically. Every file is stored on a line
+    /// "D\t<modified>\t<size>\t<path>" (the paths being numbered in that order), followed by a line
+    /// "W\t<word>\t<number>:<count>,<number>:<count>,..." for every word.
+    fn save(&self, file: &Path) -> io::Result<()> {
+        let temp_file: PathBuf = file.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temp_file)?);
+        writeln!(writer, "{}", INDEX_FILE_HEADER)?;
+        let mut numbers: HashMap<&str, usize> = HashMap::with_capacity(self.documents.len());
+        for (number, (path, document)) in self.documents.iter().enumerate() {
+            writeln!(writer, "D\t{}\t{}\t{}", document.modified, document.size, utf8_percent_encode(path, PATH_ENCODE_SET))?;
+            numbers.insert(path, number);
+        }
+        for (word, paths) in &self.postings {
+            let postings: Vec<String> = paths.iter()
+                .map(|path| format!("{}:{}", numbers[path.as_str()], self.documents[path].words[word]))
+                .collect();
+            writeln!(writer, "W\t{}\t{}", word, postings.join(","))?;
+        }
+        writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        fs::rename(temp_file, file)
+    }
+}
+
+/// Whether the file at `path` is indexed (judging by its name): text files (incl. source code) and PDFs.
+fn is_indexable(path: &Path) -> bool {
+    let mime_type: &str = http_share::mime::mime_type(path);
+    mime_type.starts_with("text/") || ["application/json", "application/xml", "application/pdf"].contains(&mime_type)
+}
+
+/// The text of the file at `path` (which `is_indexable`), `None` if it can't be read or isn't text after all.
+fn extract_text(path: &Path) -> Option<String> {
+    if http_share::mime::mime_type(path) == "application/pdf" {
+        // The PDF parser panics on some malformed files, which shouldn't take down the indexer:
+        let path: PathBuf = path.to_path_buf();
+        return panic::catch_unwind(move || pdf_extract::extract_text(path)).ok()?.ok();
+    }
+    let bytes: Vec<u8> = fs::read(path).ok()?;
+    if bytes[..bytes.len().min(8192)].contains(&0) { // Binary data after all.
+        return None;
+    }
+    return Some(String::from_utf8_lossy(&bytes).into_owned());
+}
+
+/// The text of the file at `path` like `extract_text` (empty if there is none), taken from `TEXT_CACHE` if
+/// the file didn't change since, so that the snippets of the same results aren't extracted again and again.
+fn cached_text(path: &Path) -> Arc<String> {
+    let version: Option<(SystemTime, u64)> = fs::metadata(path).ok().and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+    let mut cache = TEXT_CACHE.lock().unwrap();
+    cache.uses += 1;
+    let uses: u64 = cache.uses;
+    if let Some(cached) = cache.texts.get_mut(path).filter(|cached| Some(cached.version) == version) {
+        cached.last_use = uses;
+        return cached.text.clone();
+    }
+    drop(cache); // (Extracting the text, especially of PDFs, takes a while.)
+
+    let text: Arc<String> = Arc::new(extract_text(path).unwrap_or_default());
+    let (Some(version), true) = (version, text.len() <= MAX_TEXT_CACHE_SIZE) else { return text };
+    let mut cache = TEXT_CACHE.lock().unwrap();
+    cache.size += text.len();
+    if let Some(replaced) = cache.texts.insert(path.to_path_buf(), CachedText { version, text: text.clone(), last_use: uses }) {
+        cache.size -= replaced.text.len();
+    }
+    while cache.size > MAX_TEXT_CACHE_SIZE {
+        let Some(least_recently_used) = cache.texts.iter().min_by_key(|(_, cached)| cached.last_use).map(|(path, _)| path.clone()) else { break };
+        let evicted: CachedText = cache.texts.remove(&least_recently_used).unwrap();
+        cache.size -= evicted.text.len();
+    }
+    return text;
+}
+
+/// The words in the `text` with their position (in bytes), in their original spelling.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    WORD.find_iter(text).map(|word| (word.start(), word.as_str()))
+}
+
+/// The normalized `word` (i.e. lowercase), `None` if it is too short or long to be indexed.
+fn normalize(word: &str) -> Option<String> {
+    Some(word.to_lowercase()).filter(|word| (2..=64).contains(&word.chars().count()))
+}
+
+/// Brings the index up to date regarding the file or folder at `path` (within the `root_dir`): indexes
+/// the files that are new or changed since they were indexed and removes those that don't exist anymore.
+fn update(path: &Path, root_dir: &Path) {
+    let relative: String = relative_path(path, root_dir);
+    let mut files: Vec<PathBuf> = Vec::new();
+    // What is hidden from anonymous users is not indexed, just like protected folders (cf. settings.rs):
+    let anonymous: UserAccount = UserAccount::anonymous();
+    if hidden::is_hidden(path, root_dir, &anonymous) || settings::is_in_locked_folder(path, root_dir, &anonymous) {
+        // (The hidden file or folder is removed from the index below.)
+    } else if path.is_dir() {
+        walk(path, root_dir, usize::MAX, Some(&anonymous), &mut |path, _| { files.push(path.to_path_buf()); true });
+    } else {
+        files.push(path.to_path_buf());
+    }
+    let files: Vec<(String, PathBuf)> = files.into_iter()
+        .filter(|file| is_indexable(file) && file.is_file())
+        .map(|file| (relative_path(&file, root_dir), file))
+        .collect();
+
+    // Remove the files that were deleted (or renamed or hidden):
+    {
+        let mut index = INDEX.write().unwrap();
+        let index: &mut Index = index.as_mut().unwrap();
+        let existing: BTreeSet<&str> = files.iter().map(|(relative, _)| relative.as_str()).collect();
+        let mut removed: Vec<String> = index.paths_within(&relative);
+        removed.extend(index.documents.get(&relative).map(|_| relative.clone()));
+        for path in removed.iter().filter(|path| !existing.contains(path.as_str())) {
+            index.remove(path);
+        }
+    }
+
+    // Index the new and changed files (reading them without blocking searches):
+    for (relative, file) in files {
+        let Ok(metadata) = fs::metadata(&file) else { continue };
+        let modified: u64 = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_secs());
+        let size: u64 = metadata.len();
+        let unchanged: bool = INDEX.read().unwrap().as_ref().unwrap().documents.get(&relative)
+            .is_some_and(|document| document.modified == modified && document.size == size);
+        if unchanged || size > MAX_FILE_SIZE {
+            continue;
+        }
+        let mut words_in_file: HashMap<String, u32> = HashMap::new();
+        for (_, word) in words(&extract_text(&file).unwrap_or_default()) {
+            if let Some(word) = normalize(word) {
+                *words_in_file.entry(word).or_default() += 1;
+            }
+        }
+        INDEX.write().unwrap().as_mut().unwrap().insert(relative, Document { modified, size, words: words_in_file });
+    }
+}
+
+/// Helper function for `start_index`: stores the index on disk, logging errors.
+fn save_index(file: &Path) {
+    if let Err(err) = INDEX.read().unwrap().as_ref().unwrap().save(file) {
+        eprintln!("{}", Red.paint(format!("[{}] Error: Could not store the full-text index: {}", date_time_str(), err)));
+    }
+}
+
+/// Loads the full-text index of the `root_dir` (or creates it), brings it up to date and keeps it up to date
+/// using a file system watcher, all in the background (`--fulltext-index`).
+pub fn start_index(root_dir: PathBuf) {
+    thread::spawn(move || {
+        // Watch before updating the index, so that no changes made in the meantime are missed:
+        let Some((root_dir, _watcher, receiver)) = watch(root_dir, "full-text index") else { return };
+        let index_file: PathBuf = root_dir.join(INDEX_DIR_NAME).join("index");
+
+        let index: Index = match Index::load(&index_file) {
+            Ok(index) => index,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Index::default(),
+            Err(err) => {
+                eprintln!("{}", Red.paint(format!("[{}] Error: Could not load the full-text index, it is created anew: {}", date_time_str(), err)));
+                Index::default()
+            }
+        };
+        println!("[{}] Full-text index loaded ({} files), updating it...", date_time_str(), index.documents.len());
+        *INDEX.write().unwrap() = Some(index);
+        update(&root_dir, &root_dir);
+        if let Err(err) = fs::create_dir_all(root_dir.join(INDEX_DIR_NAME)) {
+            eprintln!("{}", Red.paint(format!("[{}] Error: Could not create the folder for the full-text index: {}", date_time_str(), err)));
+        }
+        save_index(&index_file);
+        println!("[{}] Full-text index is up to date ({} files).", date_time_str(), INDEX.read().unwrap().as_ref().unwrap().documents.len());
+
+        // Collect the changed paths and update the index once no more changes follow for a moment:
+        let mut changed_paths: BTreeSet<PathBuf> = BTreeSet::new();
+        let mut first_change: Option<Instant> = None;
+        loop {
+            match receiver.recv_timeout(UPDATE_DELAY) {
+                Ok(Ok(event)) => {
+                    // Reading files (like the indexer itself does) doesn't change them:
+                    if matches!(event.kind, EventKind::Access(kind) if kind != AccessKind::Close(AccessMode::Write)) {
+                        continue;
+                    }
+                    changed_paths.extend(event.paths.into_iter().filter(|path| !is_internal_path(path, &root_dir)));
+                    first_change.get_or_insert_with(Instant::now);
+                    if first_change.is_some_and(|first_change| first_change.elapsed() < MAX_UPDATE_DELAY) {
+                        continue;
+                    }
+                },
+                Ok(Err(err)) => {
+                    eprintln!("{}", Red.paint(format!("[{}] Error while watching the shared folder for the full-text index: {}", date_time_str(), err)));
+                    continue;
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            if changed_paths.is_empty() {
+                continue;
+            }
+            for path in mem::take(&mut changed_paths) {
+                update(&path, &root_dir);
+            }
+            first_change = None;
+            save_index(&index_file);
+        }
+    });
+}
+
+/// Responds to `stream` with the files within `dir_path` containing the words of the full-text search given
+/// in the `query_string` ("q=..."), leaving out those hidden from the `user`, cf. the module documentation.
+pub fn search_response(http_request: &HTTPRequest, dir_path: &Path, root_dir: &Path, query_string: Option<&str>, user: &UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    let query: String = decoded_query_param(query_string, "q");
+    let mut query_words: Vec<String> = words(&query).filter_map(|(_, word)| normalize(word)).collect();
+    query_words.sort();
+    query_words.dedup();
+    if query_words.is_empty() {
+        HTTPResponse::builder().status(400).bytes("The search contains no words (of at least two letters or digits).").send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("searched for {:?}, which contains no words", query)));
+    }
+    let max_results: usize = query_param(query_string, "max_results").and_then(|max_results| max_results.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RESULTS).min(MAX_RESULTS);
+
+    let results: Vec<(String, u32)> = match INDEX.read().unwrap().as_ref() {
+        Some(index) => index.search(&relative_path(dir_path, root_dir), &query_words),
+        None => {
+            HTTPResponse::builder().status(503).bytes("Full-text search is not enabled on this server (--fulltext-index).").send_to_tcp_stream(stream)?;
+            return Err(Error::other("requested a full-text search but the index is not enabled"));
+        }
+    };
+    let mut results: Vec<(String, u32)> = results.into_iter()
+        .filter(|(path, _)| !hidden::is_hidden(&root_dir.join(path), root_dir, user) && File::open(root_dir.join(path)).is_ok())
+        .filter(|(path, _)| !settings::is_in_locked_folder(&root_dir.join(path), dir_path, user))
+        .collect();
+    let result_count: usize = results.len(); // (of the files the user may see only)
+    results.truncate(max_results);
+
+    // The body is only generated while the response is being written, so everything it needs has to be owned:
+    let dir_path: String = relative_path(dir_path, root_dir);
+    let query_string: Option<String> = query_string.map(|query_str| query_str.to_string());
+    let root_dir: PathBuf = root_dir.to_path_buf();
+    HTTPResponse::builder()
+        .header("Content-Type", "text/html; charset=utf-8")
+        .stream(move |writer| {
+            writer.write_all(b"<!DOCTYPE html><html><head><meta charset=\"utf-8\"/></head><body>\r\n")?;
+            write!(writer, "Files containing \"{}\" in <a href=\"?\">/{}</a> <i>({} files{})</i><br>\r\n",
+                html_escape(&query), html_escape(&dir_path), result_count,
+                if result_count > results.len() { format!(", the first {} are listed", results.len()) } else { String::new() })?;
+            write_search_form(writer, query_string.as_deref())?;
+            writer.write_all(b"<hr>\r\n")?;
+            writer.flush()?; // Send the header right away, before reading the files for the snippets.
+            if results.is_empty() {
+                writer.write_all(b"Nothing found.")?;
+            }
+            for (path, count) in &results {
+                let text: Arc<String> = cached_text(&root_dir.join(path));
+                write!(writer, "<p><a href=\"/{}\">{}</a> <i>({} matches)</i><br>\r\n<small>{}</small></p>\r\n",
+                    utf8_percent_encode(path, percent_encoding::NON_ALPHANUMERIC), html_escape(path), count, snippet(&text, &query_words))?;
+            }
+            writer.write_all(b"</body></html>\r\n")
+        })
+        .compressed(http_request.get_header("Accept-Encoding"))
+        .send_to_tcp_stream(stream)
+}
+
+/// Helper function for `search_response`: the part of the `text` around the first occurrence of one of the
+/// (normalized) `query_words` as HTML, with all occurrences highlighted.
+fn snippet(text: &str, query_words: &[String]) -> String {
+    let is_match = |word: &str| normalize(word).is_some_and(|word| query_words.contains(&word));
+    let first_match: usize = words(text).find(|(_, word)| is_match(word)).map_or(0, |(position, _)| position);
+    let mut start: usize = first_match.saturating_sub(SNIPPET_LENGTH / 3);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end: usize = (start + SNIPPET_LENGTH).min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let excerpt: &str = &text[start..end];
+    let mut html = String::from(if start > 0 { "…" } else { "" });
+    let mut position: usize = 0; // in the excerpt
+    for (word_position, word) in words(excerpt).filter(|(_, word)| is_match(word)) {
+        html.push_str(&html_escape(&excerpt[position..word_position]));
+        html.push_str(&format!("<mark>{}</mark>", html_escape(word)));
+        position = word_position + word.len();
+    }
+    html.push_str(&html_escape(&excerpt[position..]));
+    if end < text.len() {
+        html.push('…');
+    }
+    // The snippet is shown in one paragraph:
+    return html.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
@@ -0,0 +1,332 @@
+//! Hiding files and folders: hidden items are neither listed (in any view, search or archive) nor served
+//! (requesting them results in a 404 Not Found), unless the user has the 'hidden' permission.
+//!
+//! Hidden are:
+//! * files and folders whose names start with a '.' (like `.git` or `.DS_Store`), unless `--show-dotfiles` is given,
+//! * the http_share binary itself,
+//! * everything matching a pattern in a `.httpshareignore` file (cf. `IGNORE_FILE_NAME`), which uses the
+//!   syntax of `.gitignore` files and applies to the folder it is in, including all subfolders (the files in
+//!   subfolders can add patterns or re-include items using '!'),
+//! * the settings files of folders (cf. settings.rs), even with `--show-dotfiles`,
+//! * the folders used internally (cf. `is_internal_path`), which are hidden from all users.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::config::{UserAccount, CONFIG};
+use crate::{is_internal_path, settings};
+
+/// The name of the files containing the patterns of items to hide, cf. the module documentation.
+pub const IGNORE_FILE_NAME: &str = ".httpshareignore";
+
+lazy_static! {
+    /// The parsed ignore files by their path.
+    static ref IGNORE_FILES: Mutex<HashMap<PathBuf, IgnoreFile>> = Mutex::new(HashMap::new());
+}
+
+/// A parsed ignore file.
+struct IgnoreFile {
+    /// The modification time of the file when it was parsed.
+    modified: Option<SystemTime>,
+    patterns: Arc<Vec<IgnorePattern>>,
+}
+
+/// A pattern of an ignore file.
+struct IgnorePattern {
+    /// Matches the paths (relative to the folder of the ignore file, separated by '/') the pattern applies to.
+    regex: Regex,
+    /// Whether the pattern started with a '!', i.e. re-includes the items it matches.
+    negated: bool,
+    /// Whether the pattern ended with a '/', i.e. only matches folders.
+    dirs_only: bool,
+}
+
+impl IgnorePattern {
+    /// Parses a line of an ignore file, `None` for empty lines, comments and invalid patterns.
+    fn parse(line: &str) -> Option<Self> {
+        let line: &str = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, pattern): (bool, &str) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)), // "\#" and "\!" escape the first character.
+        };
+        let (dirs_only, pattern): (bool, &str) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        // Patterns containing a '/' are relative to the folder of the ignore file, others match names at any depth:
+        let regex: String = match pattern.strip_prefix('/') {
+            Some(pattern) => format!("^{}$", glob_to_regex(pattern)),
+            None if pattern.contains('/') => format!("^{}$", glob_to_regex(pattern)),
+            None => format!("^(?:.*/)?{}$", glob_to_regex(pattern)),
+        };
+        Some(Self { regex: Regex::new(&regex).ok()?, negated, dirs_only })
+    }
+
+    /// Whether this pattern matches the item at `relative_path` (relative to the folder of the ignore file).
+    fn matches(&self, relative_path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dirs_only) && self.regex.is_match(relative_path)
+    }
+}
+
+/// Helper function for `IgnorePattern::parse`: turns a pattern in the syntax of `.gitignore` files into a
+/// regular expression: '*' matches anything except a '/', '?' any character except a '/', "[...]" one of the
+/// given characters and "**" any number of folders.
+fn glob_to_regex(pattern: &str) -> String {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::new();
+    let mut i: usize = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') && chars.get(i + 2) == Some(&'/') => { regex.push_str("(?:.*/)?"); i += 2; },
+            '*' if chars.get(i + 1) == Some(&'*') => { regex.push_str(".*"); i += 1; },
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(length) => {
+                    let class: String = chars[i + 1..i + 1 + length].iter().collect();
+                    let class: String = class.strip_prefix('!').map_or(class.clone(), |class| format!("^{}", class));
+                    regex.push_str(&format!("[{}]", class.replace('\\', "\\\\").replace('[', "\\[")));
+                    i += length + 1;
+                },
+                None => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => { regex.push_str(&regex::escape(&chars[i + 1].to_string())); i += 1; },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    return regex;
+}
+
+/// The patterns of the ignore file in the folder `dir_path` (none if there is no ignore file).
+/// Ignore files are only read again when they changed.
+fn ignore_patterns(dir_path: &Path) -> Arc<Vec<IgnorePattern>> {
+    let ignore_file: PathBuf = dir_path.join(IGNORE_FILE_NAME);
+    let mut ignore_files = IGNORE_FILES.lock().unwrap();
+    let modified: Option<SystemTime> = match fs::metadata(&ignore_file) {
+        Ok(metadata) => metadata.modified().ok(),
+        Err(_) => {
+            ignore_files.remove(&ignore_file);
+            return Arc::new(Vec::new());
+        }
+    };
+    if let Some(parsed) = ignore_files.get(&ignore_file) {
+        if parsed.modified == modified && modified.is_some() {
+            return Arc::clone(&parsed.patterns);
+        }
+    }
+    let patterns: Arc<Vec<IgnorePattern>> = Arc::new(fs::read_to_string(&ignore_file).unwrap_or_default()
+        .lines()
+        .filter_map(IgnorePattern::parse)
+        .collect());
+    ignore_files.insert(ignore_file, IgnoreFile { modified, patterns: Arc::clone(&patterns) });
+    return patterns;
+}
+
+/// Whether the file or folder at `fs_path` (within the `root_dir`) or one of the folders it is in is hidden
+/// from the `user`, cf. the module documentation.
+pub fn is_hidden(fs_path: &Path, root_dir: &Path, user: &UserAccount) -> bool {
+    if is_internal_path(fs_path, root_dir) {
+        return true;
+    }
+    let Ok(relative_path) = fs_path.strip_prefix(root_dir) else { return false };
+    let names: Vec<_> = relative_path.iter().collect();
+    let mut path: PathBuf = root_dir.to_path_buf();
+    for (i, name) in names.iter().enumerate() {
+        path.push(name);
+        let is_dir: bool = i + 1 < names.len() || path.is_dir();
+        if is_hidden_in_folder(&path, is_dir, root_dir, user) {
+            return true;
+        }
+    }
+    return false;
+}
+
+/// Whether the file or folder at `fs_path` (a folder if `is_dir`) is hidden from the `user`, the folder it
+/// is in being visible. Cheaper than `is_hidden`, but use `FolderRules` for all items of a folder.
+pub fn is_hidden_in_folder(fs_path: &Path, is_dir: bool, root_dir: &Path, user: &UserAccount) -> bool {
+    let dir_path: &Path = fs_path.parent().unwrap_or(root_dir);
+    return FolderRules::of(dir_path, root_dir, user).hides(fs_path, is_dir);
+}
+
+/// Which items directly inside of one folder are hidden from a user, e.g. when listing that folder: the
+/// ignore files of the folder and of those it is in are only looked up once, not once for every item.
+pub struct FolderRules {
+    root_dir: PathBuf,
+    /// Whether the user may see hidden items, i.e. only the internal folders are hidden.
+    sees_hidden: bool,
+    /// The patterns of the ignore files of the folders from the root directory down to the folder, each with
+    /// the path of the folder relative to the one of the ignore file (empty or ending with a '/').
+    ignore_files: Vec<(String, Arc<Vec<IgnorePattern>>)>,
+}
+
+impl FolderRules {
+    /// The rules for the items in the folder `dir_path` (within the `root_dir`) regarding the `user`.
+    pub fn of(dir_path: &Path, root_dir: &Path, user: &UserAccount) -> Self {
+        let sees_hidden: bool = user.can_see_hidden();
+        let mut ignore_files: Vec<(String, Arc<Vec<IgnorePattern>>)> = Vec::new();
+        if let (false, Ok(relative_path)) = (sees_hidden, dir_path.strip_prefix(root_dir)) {
+            let names: Vec<String> = relative_path.iter().map(|name| name.to_string_lossy().to_string()).collect();
+            let mut folder: PathBuf = root_dir.to_path_buf();
+            for depth in 0..=names.len() {
+                let patterns: Arc<Vec<IgnorePattern>> = ignore_patterns(&folder);
+                if !patterns.is_empty() {
+                    ignore_files.push((names[depth..].iter().map(|name| format!("{}/", name)).collect(), patterns));
+                }
+                if let Some(name) = names.get(depth) {
+                    folder.push(name);
+                }
+            }
+        }
+        return Self { root_dir: root_dir.to_path_buf(), sees_hidden, ignore_files };
+    }
+
+    /// Whether the file or folder at `fs_path` (a folder if `is_dir`) in the folder of these rules is hidden.
+    pub fn hides(&self, fs_path: &Path, is_dir: bool) -> bool {
+        if is_internal_path(fs_path, &self.root_dir) {
+            return true;
+        }
+        if self.sees_hidden {
+            return false;
+        }
+        let name: String = fs_path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+        if name == settings::SETTINGS_FILE_NAME && !is_dir {
+            return true;
+        }
+        if fs_path.parent() == Some(&self.root_dir) && Some(name.as_ref()) == env::args().next().as_deref().and_then(|binary| Path::new(binary).file_name()) {
+            return true; // The binary itself.
+        }
+        return self.hides_path_in_folder(&name, is_dir);
+    }
+
+    /// Whether the file or folder at `member_path` (separated by '/') inside of the archive at `archive_path`
+    /// in the folder of these rules (cf. browse.rs) or one of the folders it is in is hidden: archives are
+    /// treated like folders, i.e. dotfiles and the patterns of the ignore files apply to their contents too.
+    pub fn hides_archive_member(&self, archive_path: &Path, member_path: &str, is_dir: bool) -> bool {
+        if self.sees_hidden {
+            return false;
+        }
+        let archive_name: String = archive_path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+        let names: Vec<&str> = member_path.split('/').collect();
+        return (1..=names.len()).any(|depth| {
+            let path_in_folder: String = format!("{}/{}", archive_name, names[..depth].join("/"));
+            self.hides_path_in_folder(&path_in_folder, depth < names.len() || is_dir)
+        });
+    }
+
+    /// Helper function for `hides` and `hides_archive_member`: whether the item at `path_in_folder` (relative
+    /// to the folder of these rules) is hidden because of its name or the ignore files, ignoring its parents.
+    fn hides_path_in_folder(&self, path_in_folder: &str, is_dir: bool) -> bool {
+        let name: &str = path_in_folder.rsplit('/').next().unwrap(); // (`rsplit` always returns at least 1 item)
+        if name.starts_with('.') && !CONFIG.show_dotfiles {
+            return true;
+        }
+
+        // The ignore files of the folders further down override those further up, later patterns the earlier ones:
+        let mut hidden: bool = false;
+        for (dir_path, patterns) in &self.ignore_files {
+            let path_in_dir: String = format!("{}{}", dir_path, path_in_folder);
+            for pattern in patterns.iter() {
+                if pattern.matches(&path_in_dir, is_dir) {
+                    hidden = !pattern.negated;
+                }
+            }
+        }
+        return hidden;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the ignore file line `pattern` matches the item at `relative_path`.
+    fn matches(pattern: &str, relative_path: &str, is_dir: bool) -> bool {
+        IgnorePattern::parse(pattern).unwrap().matches(relative_path, is_dir)
+    }
+
+    #[test]
+    fn globs() {
+        assert_eq!(glob_to_regex("*.log"), "[^/]*\\.log");
+        assert_eq!(glob_to_regex("a?c"), "a[^/]c");
+        assert_eq!(glob_to_regex("**/x"), "(?:.*/)?x");
+        assert_eq!(glob_to_regex("x/**"), "x/.*");
+        assert_eq!(glob_to_regex("[!a-c]"), "[^a-c]");
+        assert_eq!(glob_to_regex("[unclosed"), "\\[unclosed");
+        assert_eq!(glob_to_regex("\\*"), "\\*");
+    }
+
+    #[test]
+    fn names_match_at_any_depth() {
+        assert!(matches("*.log", "a.log", false));
+        assert!(matches("*.log", "sub/deep/a.log", false));
+        assert!(!matches("*.log", "a.log.txt", false));
+        assert!(matches("node_modules", "web/node_modules", true));
+        assert!(!matches("node_modules", "web/node_modules_old", true));
+    }
+
+    #[test]
+    fn paths_match_relative_to_the_ignore_file() {
+        assert!(matches("/build", "build", true));
+        assert!(!matches("/build", "sub/build", true));
+        assert!(matches("docs/*.pdf", "docs/a.pdf", false));
+        assert!(!matches("docs/*.pdf", "docs/sub/a.pdf", false));
+        assert!(!matches("docs/*.pdf", "x/docs/a.pdf", false));
+        assert!(matches("docs/**/*.pdf", "docs/a.pdf", false));
+        assert!(matches("docs/**/*.pdf", "docs/x/y/a.pdf", false));
+        assert!(matches("**/tmp", "tmp", true));
+        assert!(matches("**/tmp", "a/b/tmp", true));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("[ab]?.txt", "a1.txt", false));
+        assert!(!matches("[ab]?.txt", "c1.txt", false));
+        assert!(matches("[!ab].txt", "c.txt", false));
+        assert!(!matches("[!ab].txt", "a.txt", false));
+        assert!(!matches("?.txt", "/.txt", false));
+    }
+
+    #[test]
+    fn pattern_lines() {
+        assert!(IgnorePattern::parse("").is_none());
+        assert!(IgnorePattern::parse("   ").is_none());
+        assert!(IgnorePattern::parse("# comment").is_none());
+        assert!(matches("\\#notes", "#notes", false));
+        assert!(matches("\\!important", "!important", false));
+        assert!(matches("*.tmp   ", "a.tmp", false)); // (trailing spaces are ignored)
+
+        let negated: IgnorePattern = IgnorePattern::parse("!keep.log").unwrap();
+        assert!(negated.negated && negated.matches("keep.log", false));
+
+        let dirs_only: IgnorePattern = IgnorePattern::parse("cache/").unwrap();
+        assert!(dirs_only.matches("cache", true));
+        assert!(!dirs_only.matches("cache", false));
+    }
+
+    #[test]
+    fn archive_members() {
+        let patterns: Vec<IgnorePattern> = ["*.log", "cache/", "/bundle.zip/private"].iter()
+            .filter_map(|line| IgnorePattern::parse(line))
+            .collect();
+        let mut rules = FolderRules { root_dir: PathBuf::from("/srv"), sees_hidden: false, ignore_files: vec![(String::new(), Arc::new(patterns))] };
+        let archive_path: &Path = Path::new("/srv/bundle.zip");
+        assert!(rules.hides_archive_member(archive_path, "a.log", false));
+        assert!(rules.hides_archive_member(archive_path, "sub/cache/a.txt", false));
+        assert!(!rules.hides_archive_member(archive_path, "sub/cache", false));
+        assert!(rules.hides_archive_member(archive_path, "private/a.txt", false));
+        assert!(!rules.hides_archive_member(archive_path, "sub/private", true));
+        assert!(!rules.hides_archive_member(archive_path, "sub/readme.txt", false));
+        rules.sees_hidden = true;
+        assert!(!rules.hides_archive_member(archive_path, "a.log", false));
+    }
+}
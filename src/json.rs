@@ -0,0 +1,124 @@
+//! Directory listings as JSON, for scripts (`?format=json` or an 'Accept: application/json' header), e.g.:
+//!
+//! ```json
+//! {"path":"/Photos","item_count":2,"items":[
+//!   {"name":"2021","path":"/Photos/2021","type":"directory","size":null,"item_count":12,"mime_type":null,
+//!    "created":"2021-01-01T12:00:00Z","modified":"2021-12-31T12:00:00Z","accessed":"2022-01-02T12:00:00Z"},
+//!   {"name":"a.jpg","path":"/Photos/a.jpg","type":"file","size":123456,"item_count":null,"mime_type":"image/jpeg",
+//!    "created":"2021-01-01T12:00:00Z","modified":"2021-01-01T12:00:00Z","accessed":"2022-01-02T12:00:00Z"}
+//! ],"next_cursor":null}
+//! ```
+//!
+//! The items are sorted just like in the HTML views (`sort=...`, cf. sort.rs). Big folders are split into pages of
+//! `limit` items (`DEFAULT_PAGE_SIZE` by default): as long as there are more items, `next_cursor` is
+//! given, which is passed as `cursor=...` to get the next page. The `item_count` of folders is capped at
+//! `ItemDetails::MAX_ITEM_COUNT`.
+
+use std::io::{self, Error, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::SystemTime;
+use chrono::{DateTime, SecondsFormat, Utc};
+use http_share::{HTTPRequest, HTTPResponse};
+use crate::{query_param, FolderItem, ItemDetails};
+
+/// The number of items per page when no `limit` is given.
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// The maximum number of items per page.
+const MAX_PAGE_SIZE: usize = 10_000;
+
+/// Whether the `http_request` (with the given `query_string`) asks for a JSON listing instead of an HTML page.
+pub fn wants_json(http_request: &HTTPRequest, query_string: Option<&str>) -> bool {
+    match query_param(query_string, "format") {
+        Some(format) => format == "json",
+        None => http_request.get_header("Accept").is_some_and(|accept| accept.contains("application/json")),
+    }
+}
+
+/// Responds to `stream` with the (already sorted) `folder_items` of the folder `dir_path` (relative to the
+/// root directory) as JSON, only the page of them chosen by the "cursor" and "limit" parameters in the `query_string`.
+pub fn listing_response(http_request: &HTTPRequest, folder_items: Vec<FolderItem>, query_string: Option<&str>, dir_path: String, stream: &mut TcpStream) -> io::Result<()> {
+    let (start, limit): (usize, usize) = match parse_page(query_string) {
+        Some(page) => page,
+        None => {
+            HTTPResponse::builder().status(400).bytes("Invalid cursor or limit.").send_to_tcp_stream(stream)?;
+            return Err(Error::other(format!("requested a JSON listing of /{} with an invalid cursor or limit", dir_path)));
+        }
+    };
+    let item_count: usize = folder_items.len();
+    let end: usize = start.saturating_add(limit).min(item_count);
+    let next_cursor: Option<usize> = Some(end).filter(|&end| end < item_count);
+
+    // The body is only generated while the response is being written, so everything it needs has to be owned:
+    HTTPResponse::builder()
+        .header("Content-Type", "application/json")
+        .header("Vary", "Accept")
+        .stream(move |writer| {
+            write!(writer, "{{\"path\":{},\"item_count\":{},\"items\":[", json_string(&format!("/{}", dir_path)), item_count)?;
+            for (index, item) in folder_items.iter().enumerate().take(end).skip(start) {
+                if index > start {
+                    writer.write_all(b",")?;
+                }
+                write_item(writer, item)?;
+            }
+            write!(writer, "],\"next_cursor\":{}}}", next_cursor.map_or("null".to_string(), |cursor| json_string(&cursor.to_string())))
+        })
+        .compressed(http_request.get_header("Accept-Encoding"))
+        .send_to_tcp_stream(stream)
+}
+
+/// Helper function for `listing_response`: the index of the first item and the number of items to list,
+/// according to the "cursor" and "limit" parameters in the `query_string`. `None` if they are invalid.
+fn parse_page(query_string: Option<&str>) -> Option<(usize, usize)> {
+    let start: usize = match query_param(query_string, "cursor") {
+        Some(cursor) if !cursor.is_empty() => cursor.parse().ok()?,
+        _ => 0,
+    };
+    let limit: usize = match query_param(query_string, "limit") {
+        Some(limit) => limit.parse().ok().filter(|&limit| limit > 0)?,
+        None => DEFAULT_PAGE_SIZE,
+    };
+    return Some((start, limit.min(MAX_PAGE_SIZE)));
+}
+
+/// Helper function for `listing_response`: writes the JSON object describing the `item`.
+fn write_item(writer: &mut dyn Write, item: &FolderItem) -> io::Result<()> {
+    let details: ItemDetails = item.details(true);
+    write!(writer,
+        "{{\"name\":{},\"path\":{},\"type\":\"{}\",\"size\":{},\"item_count\":{},\"mime_type\":{},\"created\":{},\"modified\":{},\"accessed\":{}}}",
+        json_string(item.name()),
+        json_string(&format!("/{}", item.path)),
+        if details.is_dir { "directory" } else { "file" },
+        details.size.map_or("null".to_string(), |size| size.to_string()),
+        details.item_count.map_or("null".to_string(), |item_count| item_count.to_string()),
+        if details.is_dir { "null".to_string() } else { json_string(http_share::mime::mime_type(Path::new(item.name())).split(';').next().unwrap()) },
+        json_time(details.created),
+        json_time(details.modified),
+        json_time(details.accessed),
+    )
+}
+
+/// A point in time as a JSON string in the RFC 3339 format (in UTC), e.g. "2021-12-31T12:00:00Z", or `null`.
+fn json_time(system_time: Option<SystemTime>) -> String {
+    system_time.map_or("null".to_string(), |system_time| json_string(&DateTime::<Utc>::from(system_time).to_rfc3339_opts(SecondsFormat::Secs, true)))
+}
+
+/// `text` as a JSON string literal, including the quotes.
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    return json;
+}
@@ -1,24 +1,62 @@
+#![allow(clippy::needless_return)] // Explicit `return`s are used throughout this project for readability.
+
 use std::fs::File;
-use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::io::{self, BufRead, Read, Write, Seek, SeekFrom};
 use std::net::TcpStream;
 use std::path::Path;
 use std::fmt::Display;
+use throttle::{Throttle, ThrottledWriter};
+
+pub mod compress;
+pub mod mime;
+pub mod multipart;
+#[cfg(target_os = "linux")]
+mod sendfile;
+pub mod tar;
+pub mod throttle;
+pub mod zip;
 
-/// A wrapper around a `String` representing an HTTP request.
+/// A wrapper around a `String` representing (the request line and headers of) an HTTP request.
 pub struct HTTPRequest {
     http_request: String,
+    /// The bytes of the request body that were already read from the stream together with the headers.
+    body_prefix: Vec<u8>,
 }
 
 impl HTTPRequest {
+    /// The maximum size of the request line and headers of an HTTP request, in bytes.
+    pub const MAX_HEADER_SIZE: usize = 64 * 1024;
+
     /// Create a new `HTTPRequest` by reading an HTTP request from a `TcpStream`.
+    /// Only the request line and headers are read, the body (if any) can be read afterwards using
+    /// `body_reader`.
     pub fn read_from_tcp_stream(stream: &mut TcpStream) -> io::Result<Self> {
-        let mut request_buffer = [0u8; 1024];
-        stream.read(&mut request_buffer)?; // "GET /[path] HTTP/1.1 [...]"
+        let mut request_buffer: Vec<u8> = Vec::new();
+        let mut read_buffer = [0u8; 4096];
+        // Keep reading until the empty line terminating the headers was received:
+        let header_end: usize = loop {
+            let bytes_read = stream.read(&mut read_buffer)?; // "GET /[path] HTTP/1.1 [...]"
+            if bytes_read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the end of the HTTP headers"));
+            }
+            request_buffer.extend_from_slice(&read_buffer[..bytes_read]);
+            if let Some(index) = request_buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break index + 4;
+            } else if request_buffer.len() > Self::MAX_HEADER_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP headers too large"));
+            }
+        };
         return Ok(Self {
-            http_request: String::from_utf8_lossy(&request_buffer).to_string(),
+            http_request: String::from_utf8_lossy(&request_buffer[..header_end]).to_string(),
+            body_prefix: request_buffer[header_end..].to_vec(),
         });
     }
 
+    /// Get the method of this request, e.g. "GET" or "POST".
+    pub fn get_method(&self) -> &str {
+        self.http_request.split(' ').next().unwrap_or("GET")
+    }
+
     /// Get the requested path of this GET request.
     pub fn get_get_path(&self) -> &str {
         // An HTTP GET request starts like so: "GET /[path] HTTP/1.1 [...]".
@@ -26,6 +64,40 @@ impl HTTPRequest {
         self.http_request.split(' ').nth(1).unwrap_or("/")
     }
 
+    /// Get the value of the header called `name` (case-insensitive), if this request contains such a header.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.http_request.split("\r\n")
+            .skip(1) // skip the request line
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| {
+                let (header_name, value) = line.split_once(':')?;
+                if header_name.trim().eq_ignore_ascii_case(name) { Some(value.trim()) } else { None }
+            })
+    }
+
+    /// Get the value of the 'Content-Length' header, i.e. the size of the request body in bytes.
+    pub fn get_content_length(&self) -> Option<u64> {
+        self.get_header("Content-Length")?.parse().ok()
+    }
+
+    /// Whether the body of this request is sent using 'Transfer-Encoding: chunked' (e.g. by macOS
+    /// when uploading files via WebDAV), i.e. without a 'Content-Length'.
+    pub fn is_chunked(&self) -> bool {
+        self.get_header("Transfer-Encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// Returns a reader over the body of this request, which continues reading from `stream` where
+    /// `read_from_tcp_stream` stopped. Exactly 'Content-Length' bytes are read (none if that header is missing),
+    /// unless the body is chunked, in which case it's decoded and read until the terminating chunk.
+    pub fn body_reader<'a>(&'a self, stream: &'a mut TcpStream) -> Box<dyn Read + 'a> {
+        if self.is_chunked() {
+            return Box::new(ChunkedReader::new(io::BufReader::new(io::Cursor::new(&self.body_prefix[..]).chain(stream))));
+        }
+        let content_length: u64 = self.get_content_length().unwrap_or(0);
+        let already_read: &[u8] = &self.body_prefix[..self.body_prefix.len().min(content_length as usize)];
+        Box::new(io::Cursor::new(already_read).chain(stream.take(content_length - already_read.len() as u64)))
+    }
+
     /// Whether this HTTP request contains a 'Range' header.
     pub fn contains_range_header(&self) -> bool {
         self.http_request.contains("Range: bytes=")
@@ -73,7 +145,7 @@ impl HTTPRequest {
 
 impl From<String> for HTTPRequest {
     fn from(http_request: String) -> Self {
-        Self { http_request }
+        Self { http_request, body_prefix: Vec::new() }
     }
 }
 impl From<HTTPRequest> for String {
@@ -82,55 +154,220 @@ impl From<HTTPRequest> for String {
     }
 }
 
-/// A wrapper around a `Vec<u8>` representing an HTTP response.
+
+/// The body of an `HTTPResponse`.
+pub enum HTTPResponseBody {
+    /// No body at all, e.g. for a '401 Unauthorized' response.
+    Empty,
+    /// A body that is already held in memory.
+    Bytes(Vec<u8>),
+    /// The contents of a file, copied to the stream without reading the entire file into memory first.
+    /// When `range` is given, only the bytes from `range.0` up to and including `range.1` are sent
+    /// (inclusive, just like in the HTTP 'Range' header).
+    File { file: File, range: Option<(u64, u64)> },
+    /// The contents of any reader. When the `length` is known it's sent as the 'Content-Length',
+    /// otherwise the body is sent using 'Transfer-Encoding: chunked'.
+    Reader { reader: Box<dyn Read + Send>, length: Option<u64> },
+    /// A body that is generated piece by piece, every item becoming one chunk of a
+    /// 'Transfer-Encoding: chunked' response.
+    Chunked(Box<dyn Iterator<Item = io::Result<Vec<u8>>> + Send>),
+    /// A body that is generated by a function writing to a `ChunkedWriter`, i.e. it's sent using
+    /// 'Transfer-Encoding: chunked' while it is being generated. Useful for large generated bodies
+    /// like directory listings or archives, which would otherwise have to be built in memory first.
+    Stream(BodyGenerator),
+}
+
+/// A function generating a response body by writing it to the given writer, cf. `HTTPResponseBody::Stream`.
+pub type BodyGenerator = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// An HTTP response, consisting of a status code, headers and a body.
+/// Use `HTTPResponse::builder` to create arbitrary responses or one of the `new_*` convenience
+/// constructors for the common ones.
 pub struct HTTPResponse {
-    http_response: Vec<u8>,
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: HTTPResponseBody,
+    /// The bandwidth limits applying to sending this response, if any (cf. throttle.rs).
+    throttle: Option<Throttle>,
+}
+
+/// A builder for an `HTTPResponse`, created by `HTTPResponse::builder`.
+pub struct HTTPResponseBuilder {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+}
+
+impl HTTPResponseBuilder {
+    /// Set the status code of the response (the reason phrase is chosen automatically).
+    pub fn status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+
+    /// Add a header to the response. Adding the same header multiple times (e.g. 'Set-Cookie')
+    /// results in multiple header lines.
+    pub fn header(mut self, name: impl Into<String>, value: impl Display) -> Self {
+        self.headers.push((name.into(), value.to_string()));
+        self
+    }
+
+    /// Finish the response with the given `body`.
+    pub fn body(self, body: HTTPResponseBody) -> HTTPResponse {
+        HTTPResponse { status_code: self.status_code, headers: self.headers, body, throttle: None }
+    }
+
+    /// Finish the response without a body.
+    pub fn empty(self) -> HTTPResponse {
+        self.body(HTTPResponseBody::Empty)
+    }
+
+    /// Finish the response with a body that is already held in memory.
+    pub fn bytes(self, content: impl Into<Vec<u8>>) -> HTTPResponse {
+        self.body(HTTPResponseBody::Bytes(content.into()))
+    }
+
+    /// Finish the response with the contents of `file`, optionally only those in the (inclusive) `range`.
+    pub fn file(self, file: File, range: Option<(u64, u64)>) -> HTTPResponse {
+        self.body(HTTPResponseBody::File { file, range })
+    }
+
+    /// Finish the response with the contents of `reader`, `length` being its length if known.
+    pub fn reader(self, reader: impl Read + Send + 'static, length: Option<u64>) -> HTTPResponse {
+        self.body(HTTPResponseBody::Reader { reader: Box::new(reader), length })
+    }
+
+    /// Finish the response with a chunked body, each item of `chunks` becoming one chunk.
+    pub fn chunked(self, chunks: impl Iterator<Item = io::Result<Vec<u8>>> + Send + 'static) -> HTTPResponse {
+        self.body(HTTPResponseBody::Chunked(Box::new(chunks)))
+    }
+
+    /// Finish the response with a body that is generated by `generate` writing to the given writer.
+    /// Every flush of that writer sends the data written so far as one chunk.
+    pub fn stream(self, generate: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static) -> HTTPResponse {
+        self.body(HTTPResponseBody::Stream(Box::new(generate)))
+    }
 }
 
 impl HTTPResponse {
+    /// Start building a new HTTP response. The status code defaults to '200 OK'.
+    pub fn builder() -> HTTPResponseBuilder {
+        HTTPResponseBuilder { status_code: 200, headers: Vec::new() }
+    }
+
+    /// The status code of this response, e.g. 200.
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    /// The value of the first header called `name` (case-insensitive), if there is one.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Add a header to this (already built) response.
+    pub fn add_header(&mut self, name: impl Into<String>, value: impl Display) {
+        self.headers.push((name.into(), value.to_string()));
+    }
+
+    /// Compresses the body of this response using the encoding the client prefers according to the
+    /// value of its 'Accept-Encoding' header (cf. compress.rs), while it is being sent.
+    ///
+    /// Only complete ('200 OK') responses with a text-based 'Content-Type' are compressed, never range
+    /// responses, bodies that are encoded already or bodies smaller than `compress::MIN_COMPRESSED_SIZE`.
+    /// Compressible responses always get a 'Vary: Accept-Encoding' header, for caches.
+    pub fn compressed(mut self, accept_encoding: Option<&str>) -> Self {
+        if self.status_code != 200 || self.get_header("Content-Range").is_some() || self.get_header("Content-Encoding").is_some()
+            || !self.get_header("Content-Type").is_some_and(compress::is_compressible) {
+            return self;
+        }
+        self.add_header("Vary", "Accept-Encoding");
+        let encoding: compress::ContentEncoding = match compress::ContentEncoding::accepted(accept_encoding).first() {
+            Some(&encoding) => encoding,
+            None => return self,
+        };
+        let length: Option<u64> = match &self.body {
+            HTTPResponseBody::Empty => Some(0),
+            HTTPResponseBody::Bytes(content) => Some(content.len() as u64),
+            HTTPResponseBody::File { range: Some((start, end)), .. } => Some((end + 1).saturating_sub(*start)),
+            HTTPResponseBody::File { file, range: None } => file.metadata().ok().map(|metadata| metadata.len()),
+            HTTPResponseBody::Reader { length, .. } => *length,
+            HTTPResponseBody::Chunked(_) | HTTPResponseBody::Stream(_) => None,
+        };
+        if length.is_some_and(|length| length < compress::MIN_COMPRESSED_SIZE) {
+            return self;
+        }
+
+        // The length of the compressed body isn't known up front and ranges would refer to it:
+        self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Accept-Ranges"));
+        self.add_header("Content-Encoding", encoding.name());
+        let body: HTTPResponseBody = std::mem::replace(&mut self.body, HTTPResponseBody::Empty);
+        self.body = HTTPResponseBody::Stream(Box::new(move |writer| encoding.encode(writer, |encoder| match body {
+            HTTPResponseBody::Empty => Ok(()),
+            HTTPResponseBody::Bytes(content) => encoder.write_all(&content),
+            HTTPResponseBody::File { mut file, range: Some((start, end)) } => {
+                file.seek(SeekFrom::Start(start))?;
+                io::copy(&mut file.take((end + 1).saturating_sub(start)), encoder).map(|_| ())
+            },
+            HTTPResponseBody::File { mut file, range: None } => io::copy(&mut file, encoder).map(|_| ()),
+            HTTPResponseBody::Reader { mut reader, .. } => io::copy(&mut reader, encoder).map(|_| ()),
+            HTTPResponseBody::Chunked(chunks) => chunks.into_iter().try_for_each(|chunk| encoder.write_all(&chunk?)),
+            HTTPResponseBody::Stream(generate) => generate(encoder),
+        })));
+        self
+    }
+
+    /// Sends this response only as fast as the `throttle` allows.
+    pub fn throttled(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle).filter(|throttle| !throttle.is_unlimited());
+        self
+    }
+
     /// Create a new '200 OK' HTTP response.
-    pub fn new_200_ok(content: &mut Vec<u8>) -> Self {
-        let mut http_response: Vec<u8> = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", content.len()).as_bytes().into();
-        http_response.append(content);
-        Self { http_response }
+    pub fn new_200_ok(content: impl Into<Vec<u8>>) -> Self {
+        Self::builder().bytes(content)
     }
 
     /// Create a new '206 Partial Content' HTTP response.
     #[allow(dead_code)] // Only 'write_206_partial_file_to_stream' is actually used in this project, i.e. the more memory-efficient version for sending files.
     pub fn new_206_partial_content(content: &[u8], start_index: &str, end_index: &str) -> Self {
         // cf. https://stackoverflow.com/questions/23071164/grails-ios-specific-returning-video-mp4-file-gives-broken-pipe-exception-g
-        let mut http_response: Vec<u8> = format!("HTTP/1.1 206 Partial Content\r\nAccept-Ranges: bytes\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", start_index, end_index, content.len())
-            .as_bytes().into();
-        http_response.append(&mut content[start_index.parse().unwrap()..=end_index.parse().unwrap()].to_vec()); // Only respond with the requested bytes! "=" because end index in HTTP is inclusive (I think)
-        return Self { http_response };
+        let start_index: usize = start_index.parse().unwrap();
+        let end_index: usize = end_index.parse().unwrap();
+        Self::builder()
+            .status(206)
+            .header("Accept-Ranges", "bytes")
+            .header("Content-Range", format!("bytes {}-{}/{}", start_index, end_index, content.len()))
+            .bytes(&content[start_index..=end_index]) // Only respond with the requested bytes! "=" because end index in HTTP is inclusive
     }
 
     /// Create a new '401 Unauthorized' HTTP response.
     /// The "Basic" authentication scheme is requested.
     pub fn new_401_unauthorized(realm_name: impl Display) -> Self {
-        let http_response: Vec<u8> = format!("HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"{}\"\r\n\r\n", realm_name).as_bytes().into();
-        Self { http_response }
+        Self::builder()
+            .status(401)
+            .header("WWW-Authenticate", format!("Basic realm=\"{}\"", realm_name))
+            .empty()
     }
 
     /// Create a new '403 Forbidden' HTTP response.
-    pub fn new_403_forbidden(content: &mut Vec<u8>) -> Self {
-        let mut http_response: Vec<u8> = format!("HTTP/1.1 403 Forbidden\r\nContent-Length: {}\r\n\r\n", content.len()).as_bytes().into();
-        http_response.append(content);
-        Self { http_response }
+    pub fn new_403_forbidden(content: impl Into<Vec<u8>>) -> Self {
+        Self::builder().status(403).bytes(content)
     }
 
     /// Create a new '404 Not Found' HTTP response.
     pub fn new_404_not_found<T: AsRef<str>>(filename: T) -> Self {
-        let message = format!("Error: Could not find file {}", filename.as_ref());
-        let http_response: Vec<u8> = format!("HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\n\r\n{}", message.len(), message).as_bytes().to_vec();
-        Self { http_response }
+        Self::builder()
+            .status(404)
+            .bytes(format!("Error: Could not find file {}", filename.as_ref()))
     }
 
     /// Create a new '500 Internal Server Error' HTTP response with the given `error_message`.
     pub fn new_500_server_error<T: AsRef<str>>(error_message: T) -> Self {
-        let error_message = format!("Internal Server Error occurred: {}", error_message.as_ref());
-        let http_response: Vec<u8> = format!("HTTP/1.1 500 Internal Server Error\r\nContent-Length: {}\r\n\r\n{}", error_message.len(), error_message).as_bytes().to_vec();
-        Self { http_response }
+        Self::builder()
+            .status(500)
+            .bytes(format!("Internal Server Error occurred: {}", error_message.as_ref()))
     }
 
     /// Directly writes the file contents of `filepath` to `stream`.
@@ -140,47 +377,412 @@ impl HTTPResponse {
     pub fn write_200_ok_file_to_stream(filepath: &Path, stream: &mut TcpStream) -> io::Result<()> {
         // Try to open the file before writing `200 OK`, so that the HTTP status code can still be changed in case of an
         // error.
-        let mut file = File::open(filepath)?;
-        let file_size = file.metadata()?.len();
-        // Write http response header
-        stream.write(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", file_size).as_bytes())?;
-        // Write file contents to stream
-        io::copy(&mut file, stream)?;
-        stream.flush()?;
-        Ok(())
+        let file = File::open(filepath)?;
+        Self::builder().file(file, None).send_to_tcp_stream(stream)
     }
 
     /// Directly writes the file contents of `filepath` to `stream` in range of bytes from `range`.
     pub fn write_206_partial_file_to_stream(filepath: &Path, range: (u64, Option<u64>), stream: &mut TcpStream) -> io::Result<()> {
+        Self::new_206_partial_file(filepath, range)?.send_to_tcp_stream(stream)
+    }
+
+    /// Create a new '206 Partial Content' HTTP response with the file contents of `filepath` in range of
+    /// bytes from `range`, which are only read while the response is being sent.
+    /// A '416 Range Not Satisfiable' response if the range lies outside of the file or ends before it starts.
+    pub fn new_206_partial_file(filepath: &Path, range: (u64, Option<u64>)) -> io::Result<Self> {
         // Try to open the file before writing `206 Partial Content`, so that the HTTP status code can still be
         // changed in case of an error.
-        let mut file = File::open(filepath)?;
-        // Place read pointer at given start byte
-        file.seek(SeekFrom::Start(range.0))?;
-        // Only read bytes in given range from file
-        let mut partial_file =
-            if let Some(range_end) = range.1 { // There is a <range-end> specified:
-                file.take(range_end - range.0 + 1) // +1 because end index in HTTP is inclusive!
-            } else { // There is no <range-end> specified (e.g. a range of "0-" was requested):
-                file.take(u64::MAX) // take all remaining bytes
-            };
-        // Write http response header
-        let file_size: u64 = File::open(filepath)?.metadata()?.len();
-        stream.write(format!("HTTP/1.1 206 Partial Content\r\nAccept-Ranges: bytes\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
-                             range.0,
-                             range.1.map(|r| r.to_string()).unwrap_or("".to_string()), // None -> ""
-                             file_size).as_bytes())?;
-        // Write file contents to stream
-        io::copy(&mut partial_file, stream)?;
-        stream.flush()?;
-        Ok(())
+        let file = File::open(filepath)?;
+        let file_size: u64 = file.metadata()?.len();
+        if range.0 >= file_size || range.1.is_some_and(|range_end| range_end < range.0) {
+            return Ok(Self::builder()
+                .status(416)
+                .header("Content-Range", format!("bytes */{}", file_size))
+                .empty());
+        }
+        // There is no <range-end> specified (e.g. a range of "0-" was requested) -> take all remaining bytes:
+        let range_end: u64 = range.1.unwrap_or(u64::MAX).min(file_size.saturating_sub(1));
+        Ok(Self::builder()
+            .status(206)
+            .header("Accept-Ranges", "bytes")
+            .header("Content-Range", format!("bytes {}-{}/{}", range.0, range_end, file_size))
+            .file(file, Some((range.0, range_end))))
+    }
+
+    /// Serializes this HTTP response (status line, headers and body) to `writer`.
+    ///
+    /// A 'Content-Length' header is added automatically whenever the length of the body is known
+    /// up front, otherwise the body is sent using 'Transfer-Encoding: chunked'.
+    pub fn write_to<W: Write + ?Sized>(self, writer: &mut W) -> io::Result<()> {
+        let HTTPResponse { status_code, headers, body, throttle } = self;
+        match throttle {
+            Some(throttle) => Self::write_unthrottled(&mut ThrottledWriter::new(writer, throttle), status_code, headers, body),
+            None => Self::write_unthrottled(writer, status_code, headers, body),
+        }
+    }
+
+    /// Helper function for `write_to`: serializes the response without any bandwidth limits.
+    fn write_unthrottled<W: Write + ?Sized>(writer: &mut W, status_code: u16, headers: Vec<(String, String)>, body: HTTPResponseBody) -> io::Result<()> {
+
+        // Prepare the body, so that its length is known before the headers are written:
+        let (mut reader, length): (Option<Box<dyn Read + Send>>, Option<u64>) = match body {
+            HTTPResponseBody::Empty => (None, Some(0)),
+            HTTPResponseBody::Bytes(content) => {
+                let length = content.len() as u64;
+                (Some(Box::new(io::Cursor::new(content))), Some(length))
+            },
+            HTTPResponseBody::File { mut file, range } => {
+                let (start, length) = file_section(&file, range)?;
+                file.seek(SeekFrom::Start(start))?; // Place read pointer at given start byte
+                (Some(Box::new(file.take(length))), Some(length))
+            },
+            HTTPResponseBody::Reader { reader, length } => (Some(reader), length),
+            HTTPResponseBody::Chunked(chunks) => {
+                return Self::write_head(writer, status_code, headers, None)
+                    .and_then(|_| write_chunks(chunks, writer));
+            },
+            HTTPResponseBody::Stream(generate) => {
+                Self::write_head(writer, status_code, headers, None)?;
+                let mut chunked_writer = ChunkedWriter::new(&mut *writer);
+                generate(&mut chunked_writer)?;
+                chunked_writer.finish()?;
+                return writer.flush();
+            },
+        };
+
+        match length {
+            Some(length) => {
+                Self::write_head(writer, status_code, headers, Some(length))?;
+                if let Some(reader) = reader.as_mut() {
+                    io::copy(reader, writer)?;
+                }
+            },
+            None => {
+                Self::write_head(writer, status_code, headers, None)?;
+                let mut reader = reader.expect("a body of unknown length always has a reader");
+                let mut buffer = vec![0u8; 64 * 1024];
+                let chunks = std::iter::from_fn(move || match reader.read(&mut buffer) {
+                    Ok(0) => None,
+                    Ok(bytes_read) => Some(Ok(buffer[..bytes_read].to_vec())),
+                    Err(err) => Some(Err(err)),
+                });
+                write_chunks(chunks, writer)?;
+            },
+        }
+        writer.flush()
+    }
+
+    /// Helper function for `write_to`: writes the status line and the `headers`.
+    /// When the `content_length` is `None`, the 'Transfer-Encoding: chunked' header is added, otherwise the
+    /// 'Content-Length' header (unless there is one already).
+    fn write_head<W: Write + ?Sized>(writer: &mut W, status_code: u16, mut headers: Vec<(String, String)>, content_length: Option<u64>) -> io::Result<()> {
+        if let Some(length) = content_length {
+            if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Content-Length")) {
+                headers.push(("Content-Length".to_string(), length.to_string()));
+            }
+        }
+        let mut head = format!("HTTP/1.1 {} {}\r\n", status_code, reason_phrase(status_code));
+        for (name, value) in headers.iter() {
+            head += &format!("{}: {}\r\n", name, value);
+        }
+        if content_length.is_none() {
+            head += "Transfer-Encoding: chunked\r\n";
+        }
+        head += "\r\n";
+        writer.write_all(head.as_bytes())
     }
 
     /// Send the created HTTP response to a stream. An IO error may occur, e.g. a "Broken pipe".
-    pub fn send_to_tcp_stream(&self, stream: &mut TcpStream) -> std::io::Result<()> {
-        // Send the HTTP response to the client:
-        stream.write_all(&self.http_response)?;
-        stream.flush()?;
-        Ok(())
+    ///
+    /// On Linux, file bodies are sent using the `sendfile` system call (cf. sendfile.rs), i.e. without
+    /// copying them through buffers in userspace, which `write_to` has to do as it may write to anything.
+    pub fn send_to_tcp_stream(self, stream: &mut TcpStream) -> std::io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let HTTPResponse { status_code, headers, body: HTTPResponseBody::File { file, range }, throttle } = self {
+            let (start, length) = file_section(&file, range)?;
+            Self::write_head(stream, status_code, headers, Some(length))?;
+            sendfile::send_file(&file, start, length, throttle.as_ref(), stream)?;
+            return stream.flush();
+        }
+        self.write_to(stream)
+    }
+}
+
+/// Helper function for sending an `HTTPResponseBody::File`: returns the offset of the first byte to send
+/// and the number of bytes to send, i.e. the whole `file` or only the (inclusive) `range` of it.
+/// Ranges beyond the end of the file are cut short (or are empty altogether).
+fn file_section(file: &File, range: Option<(u64, u64)>) -> io::Result<(u64, u64)> {
+    let file_size: u64 = file.metadata()?.len();
+    return Ok(match range {
+        Some((start, end)) if start > end || start >= file_size => (0, 0),
+        Some((start, end)) => (start, end.min(file_size - 1) - start + 1), // +1 because end index in HTTP is inclusive!
+        None => (0, file_size),
+    });
+}
+
+/// Helper function for `HTTPResponse::write_to`: writes every item of `chunks` as one chunk,
+/// followed by the terminating zero-length chunk.
+fn write_chunks<W: Write + ?Sized>(chunks: impl Iterator<Item = io::Result<Vec<u8>>>, writer: &mut W) -> io::Result<()> {
+    let mut chunked_writer = ChunkedWriter::new(writer);
+    for chunk in chunks {
+        chunked_writer.write_all(&chunk?)?;
+        chunked_writer.flush()?;
+    }
+    chunked_writer.finish()?.flush()
+}
+
+/// A writer that frames everything written to it using 'Transfer-Encoding: chunked'
+/// (see https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Transfer-Encoding) before
+/// passing it on to the `inner` writer.
+///
+/// Small writes are buffered and only sent as one chunk once `CHUNK_SIZE` bytes have accumulated or
+/// `flush` is called, so that the framing overhead stays small.
+/// `finish` has to be called at the end to send the terminating zero-length chunk!
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// The number of buffered bytes after which a chunk is sent automatically.
+    pub const CHUNK_SIZE: usize = 64 * 1024;
+
+    /// Create a new `ChunkedWriter` writing the chunks to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self { inner, buffer: Vec::with_capacity(Self::CHUNK_SIZE) }
+    }
+
+    /// Sends all buffered data as one chunk (if there is any).
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() { // An empty chunk would terminate the body prematurely!
+            return Ok(());
+        }
+        self.inner.write_all(format!("{:X}\r\n", self.buffer.len()).as_bytes())?;
+        self.inner.write_all(&self.buffer)?;
+        self.inner.write_all(b"\r\n")?;
+        self.buffer.clear();
+        return Ok(());
+    }
+
+    /// Sends the remaining buffered data and the terminating zero-length chunk, ending the body.
+    /// Returns the `inner` writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk()?;
+        self.inner.write_all(b"0\r\n\r\n")?;
+        return Ok(self.inner);
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= Self::CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+/// Returns the reason phrase belonging to an HTTP `status_code`, e.g. "Not Found" for 404.
+fn reason_phrase(status_code: u16) -> &'static str {
+    match status_code {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        410 => "Gone",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        423 => "Locked",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        507 => "Insufficient Storage",
+        _ => "Unknown",
+    }
+}
+
+/// A reader decoding a body sent using 'Transfer-Encoding: chunked' (the counterpart of `ChunkedWriter`).
+/// It returns the contents of all chunks one after the other and stops at the terminating zero-length chunk.
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,
+    /// How many bytes of the current chunk are still to be read.
+    remaining_in_chunk: u64,
+    /// Whether the terminating zero-length chunk was reached.
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    /// Create a new `ChunkedReader` decoding the chunked body read from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self { inner, remaining_in_chunk: 0, done: false }
+    }
+
+    /// Reads one line (without the trailing "\r\n").
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body ended before the terminating chunk"));
+        }
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.done || out.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining_in_chunk == 0 {
+            // Each chunk starts with its size in hex, possibly followed by ";"-separated extensions:
+            let size_line: String = self.read_line()?;
+            let size: &str = size_line.split(';').next().unwrap_or("").trim();
+            self.remaining_in_chunk = u64::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid chunk size: {}", size_line)))?;
+            if self.remaining_in_chunk == 0 {
+                // Skip the (optional) trailer headers up to the final empty line:
+                while !self.read_line()?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let max_len = out.len().min(self.remaining_in_chunk as usize);
+        let bytes_read = self.inner.read(&mut out[..max_len])?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body ended in the middle of a chunk"));
+        }
+        self.remaining_in_chunk -= bytes_read as u64;
+        if self.remaining_in_chunk == 0 {
+            self.read_line()?; // the "\r\n" after the chunk data
+        }
+        return Ok(bytes_read);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes the `response` into a string.
+    fn serialized(response: HTTPResponse) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        response.write_to(&mut output).unwrap();
+        return String::from_utf8(output).unwrap();
+    }
+
+    #[test]
+    fn builder() {
+        let response: String = serialized(HTTPResponse::builder().status(404).header("X-Test", 1).bytes("missing"));
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("\r\nX-Test: 1\r\n"));
+        assert!(response.contains("\r\nContent-Length: 7\r\n"));
+        assert!(response.ends_with("\r\n\r\nmissing"));
+        let response: String = serialized(HTTPResponse::new_401_unauthorized("shared folder"));
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(response.contains("\r\nWWW-Authenticate: Basic realm=\"shared folder\"\r\n"));
+        assert!(response.ends_with("\r\nContent-Length: 0\r\n\r\n"));
+    }
+
+
+    #[test]
+    fn partial_file_ranges() {
+        let path = std::env::temp_dir().join(format!("http_share_test_range_{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let content_range = |range: (u64, Option<u64>)| -> (u16, String) {
+            let response = HTTPResponse::new_206_partial_file(&path, range).unwrap();
+            let header = response.headers.iter().find(|(name, _)| name == "Content-Range").map(|(_, value)| value.clone());
+            return (response.status_code, header.unwrap_or_default());
+        };
+        assert_eq!(content_range((2, Some(5))), (206, "bytes 2-5/10".to_string()));
+        assert_eq!(content_range((7, None)), (206, "bytes 7-9/10".to_string()));
+        assert_eq!(content_range((7, Some(100))), (206, "bytes 7-9/10".to_string()));
+        assert_eq!(content_range((10, None)), (416, "bytes */10".to_string()));
+        assert_eq!(content_range((5, Some(2))), (416, "bytes */10".to_string()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn chunked_writer() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"Wiki").unwrap();
+        writer.flush().unwrap();
+        writer.flush().unwrap(); // (doesn't send an empty chunk, which would end the body)
+        writer.write_all(b"pe").unwrap();
+        writer.write_all(b"dia").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n");
+        assert_eq!(ChunkedWriter::new(Vec::new()).finish().unwrap(), b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn chunked_responses() {
+        let chunks = vec![Ok(b"ab".to_vec()), Ok(Vec::new()), Ok(b"c".to_vec())].into_iter();
+        let response: String = serialized(HTTPResponse::builder().chunked(chunks));
+        assert!(response.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n2\r\nab\r\n1\r\nc\r\n0\r\n\r\n"));
+    }
+
+    /// Decodes the chunked `body` completely.
+    fn decode_chunked(body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded: Vec<u8> = Vec::new();
+        ChunkedReader::new(body).read_to_end(&mut decoded)?;
+        return Ok(decoded);
+    }
+
+    #[test]
+    fn chunked_reader() {
+        assert_eq!(decode_chunked(b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n").unwrap(), b"Wikipedia");
+        // Chunk extensions and trailers are ignored, sizes are hexadecimal in any case:
+        assert_eq!(decode_chunked(b"a;name=value\r\n0123456789\r\n0\r\nExpires: never\r\n\r\n").unwrap(), b"0123456789");
+        assert_eq!(decode_chunked(b"B\r\nhello world\r\n0\r\n\r\n").unwrap(), b"hello world");
+        assert_eq!(decode_chunked(b"0\r\n\r\n").unwrap(), b"");
+    }
+
+    #[test]
+    fn chunked_reader_stops_at_the_terminating_chunk() {
+        let mut body: &[u8] = b"3\r\nabc\r\n0\r\n\r\nGET / HTTP/1.1\r\n";
+        let mut decoded: Vec<u8> = Vec::new();
+        ChunkedReader::new(&mut body).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"abc");
+        assert_eq!(body, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn chunked_reader_errors() {
+        assert_eq!(decode_chunked(b"zz\r\nabc\r\n0\r\n\r\n").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode_chunked(b"").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(decode_chunked(b"5\r\nabc").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(decode_chunked(b"3\r\nabc\r\n").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn chunked_round_trip() {
+        let contents: Vec<u8> = (0..3 * ChunkedWriter::<Vec<u8>>::CHUNK_SIZE + 123).map(|i| (i % 251) as u8).collect();
+        let mut writer = ChunkedWriter::new(Vec::new());
+        for piece in contents.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+        let encoded: Vec<u8> = writer.finish().unwrap();
+        assert!(encoded.ends_with(b"\r\n0\r\n\r\n"));
+        assert_eq!(decode_chunked(&encoded).unwrap(), contents);
     }
 }
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+#![allow(clippy::needless_return)] // Explicit `return`s are used throughout this project for readability.
+
 use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
 use std::env;
-use std::fs;
-use std::io::{self, Error, ErrorKind, Write};
+use std::fs::{self, File};
+use std::io::{self, Error, Write};
 use std::net::{TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 use http_share::{HTTPRequest, HTTPResponse};
+use http_share::compress::ContentEncoding;
+use http_share::throttle::Throttle;
 use chrono::Local;
 use chrono::format::{StrftimeItems, DelayedFormat};
 use std::process::Command;
@@ -14,12 +19,45 @@ use separator::Separatable;
 use chrono::{DateTime, Utc};
 use std::time::SystemTime;
 use ansi_term::Colour::Red;
-use lazy_static::lazy_static;
-use rand::thread_rng;
-use rand::seq::SliceRandom;
-use std::sync::RwLock;
+use settings::FolderSettings;
+use sort::{SortKey, SortOrder};
+use thumbnail::Thumbnail;
+use config::{BandwidthLimits, Config, Permissions, UserAccount, CONFIG};
+
+mod bandwidth;
+mod browse;
+mod cache;
+mod config;
+mod download;
+mod fulltext;
+mod hidden;
+mod json;
+mod markdown;
+mod sort;
+mod manage;
+mod preview;
+mod search;
+mod settings;
+mod site;
+mod thumbnail;
+mod tus;
+mod upload;
+mod webdav;
 
 fn main() {
+    // Maintenance commands instead of starting the server, e.g. `http_share cache prune`:
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("cache") {
+        match (args.get(2).map(String::as_str), Config::from_args(args.iter().skip(3).cloned())) {
+            (Some("prune"), Ok(config)) => cache::prune(&config),
+            (Some("prune"), Err(err)) => eprintln!("Error: {}\r\n\r\n{}", err, Config::USAGE),
+            _ => eprintln!("Usage: http_share cache prune [OPTIONS]"),
+        }
+        return;
+    }
+
+    lazy_static::initialize(&CONFIG); // Parse the command line options right away, so that invalid ones are reported before anything else happens.
+
     println!(); // separator
     
     println!("Please provide credentials or hit ENTER two times to not use any authorization:");
@@ -33,7 +71,7 @@ fn main() {
     let mut password = String::new();
     io::stdin().read_line(&mut password).unwrap();
     password = password.trim().to_string();
-    if username != "" || password != "" {
+    if !username.is_empty() || !password.is_empty() {
         println!("Credentials set to: Username: \"{}\" & Password: \"{}\"", username, password);
     } else {
         println!("No credentials set.");
@@ -47,7 +85,7 @@ fn main() {
     let mut custom_port_str = String::new();
     io::stdin().read_line(&mut custom_port_str).unwrap();
     custom_port_str = custom_port_str.trim().to_string();
-    let custom_port: Option<i32> = if custom_port_str == "" {None} else {custom_port_str.parse::<i32>().ok()};
+    let custom_port: Option<i32> = if custom_port_str.is_empty() {None} else {custom_port_str.parse::<i32>().ok()};
 
     println!(); // separator
     
@@ -90,6 +128,53 @@ fn main() {
      */
 
     println!("[{}] Server started on {}.", date_time_str(), listener.local_addr().map_or("???".to_string(), |addr| addr.to_string()));
+    if CONFIG.uploads_enabled {
+        println!("[{}] Uploads are enabled (maximum size: {}, on collision: {:?}).",
+                 date_time_str(),
+                 CONFIG.max_upload_size.map_or("unlimited".to_string(), |size| size.separated_string() + "B"),
+                 CONFIG.upload_collision);
+
+        // Regularly delete the partial resumable uploads that were not completed in time:
+        thread::spawn(|| {
+            let binary_path: &String = &env::args().next().expect("Name of binary missing as 0th command line argument");
+            let root_dir: &Path = Path::new(binary_path).parent().expect("Binary has no parent");
+            loop {
+                tus::remove_expired_uploads(root_dir);
+                thread::sleep(std::time::Duration::from_secs(60 * 60));
+            }
+        });
+    }
+
+    if CONFIG.file_management_enabled {
+        if username.is_empty() && password.is_empty() && CONFIG.users.is_empty() {
+            eprintln!("{}", Red.paint(format!("[{}] Warning: File management is only possible for logged in users but no credentials were set!", date_time_str())));
+        } else {
+            println!("[{}] File management is enabled, deleted files are moved to {}.", date_time_str(), CONFIG.trash_dir.display());
+        }
+    }
+    if CONFIG.webdav_enabled {
+        println!("[{}] WebDAV is enabled, the shared folder can be mounted as a network drive.", date_time_str());
+    }
+    if CONFIG.search_index_enabled {
+        println!("[{}] Building the search index...", date_time_str());
+        let binary_path: &String = &env::args().next().expect("Name of binary missing as 0th command line argument");
+        search::start_index(Path::new(binary_path).parent().expect("Binary has no parent").to_path_buf());
+    }
+    if CONFIG.fulltext_index_enabled {
+        let binary_path: &String = &env::args().next().expect("Name of binary missing as 0th command line argument");
+        fulltext::start_index(Path::new(binary_path).parent().expect("Binary has no parent").to_path_buf());
+    }
+    let limits: BandwidthLimits = CONFIG.bandwidth_limits;
+    if limits != BandwidthLimits::default() {
+        let format_rate = |rate: Option<u64>| rate.map_or("unlimited".to_string(), |rate| rate.separated_string() + "B/s");
+        println!("[{}] Bandwidth is limited (total: {}, per IP: {}, per user: {}, per connection: {}){}.",
+                 date_time_str(),
+                 format_rate(limits.global),
+                 format_rate(limits.per_ip),
+                 format_rate(limits.per_user),
+                 format_rate(limits.per_connection),
+                 if limits.prioritize_ranges { ", range requests are prioritized" } else { "" });
+    }
 
     // Listen for incoming TCP/HTTP connections and handle each of them in a separate thread:
     for stream in listener.incoming() {
@@ -107,39 +192,25 @@ fn main() {
 }
 
 /// Handles a connection coming from `stream`.
-/// When `username != "" || password != ""` it also checks whether the correct `username` and
-/// `password` were provided – if not, it responds with a '401 Unauthorized'.
+/// When `!username.is_empty() || !password.is_empty()` (or additional users were configured using `--user`)
+/// it also checks whether correct credentials were provided – if not, it responds with a '401 Unauthorized'.
+/// The user entered on startup (`username` and `password`) has all permissions.
 fn handle_connection(mut stream: TcpStream, username: String, password: String) -> std::io::Result<()> {
     // Read and parse the HTTP request:
     let http_request: HTTPRequest = match HTTPRequest::read_from_tcp_stream(&mut stream) {
         Ok(http_request) => http_request,
         Err(_err) => {
             HTTPResponse::new_500_server_error("Could not read HTTP request").send_to_tcp_stream(&mut stream)?;
-            return Err(Error::new(ErrorKind::Other, "TCP stream could not be read!"));
+            return Err(Error::other("TCP stream could not be read!"));
         }
     };
     let get_path: &str = http_request.get_get_path();
-
-    // Do the HTTP Auth check:
-    if username != "" || password != "" { // A username and password are necessary, i.e. auth protection is turned on:
-        match http_request.get_authorization() {
-            Some((provided_uname, provided_pw))
-              if provided_uname == username && provided_pw == password => {}, // Uname & PW ok, do nothing and continue...
-            Some((provided_uname, provided_pw)) => { // An invalid authorization was provided:
-                HTTPResponse::new_401_unauthorized("").send_to_tcp_stream(&mut stream)?;
-                return Err(Error::new(ErrorKind::Other, format!("requested {} with incorrect credentials: {}:{}", get_path, provided_uname, provided_pw)));
-            }
-            None => { // No authorization was provided:
-                HTTPResponse::new_401_unauthorized("").send_to_tcp_stream(&mut stream)?;
-                return Err(Error::new(ErrorKind::Other, format!("requested {} without giving credentials!", get_path)));
-            }
-        }
-    }
+    let request_target: &str = get_path; // (including the query string)
 
     // Sanity check the requested GET path for security reasons:
     if !get_path.starts_with('/') {
         HTTPResponse::new_500_server_error("GET path does not start with a '/'!").send_to_tcp_stream(&mut stream)?;
-        return Err(Error::new(ErrorKind::Other, format!("requested {} which does not start with a '/'!", get_path)));
+        return Err(Error::other(format!("requested {} which does not start with a '/'!", get_path)));
     }
 
     // Log the HTTP request to console:
@@ -165,7 +236,7 @@ fn handle_connection(mut stream: TcpStream, username: String, password: String)
         None
     };
     // Now remove the query string from the GET path, if there is one
-    let get_path: &str = get_path.split('?').nth(0).unwrap();
+    let get_path: &str = get_path.split('?').next().unwrap();
 
     // Turn the path from the URL/GET request into the path for the file system:
     //   1) Always use the parent directory of the binary as the root directory
@@ -176,73 +247,245 @@ fn handle_connection(mut stream: TcpStream, username: String, password: String)
     let fs_path_buffer: PathBuf = root_dir.join(&decoded_get_path[1..]); // The join function does not like when the path to adjoin starts with a '/'
     let fs_path: &Path = fs_path_buffer.as_path();
 
+    // The settings of the requested folder (or of the folder the requested file is in), cf. settings.rs:
+    let folder_settings: FolderSettings = FolderSettings::of_path(fs_path, root_dir);
+
+    // Do the HTTP Auth check (the password of a protected folder is accepted, too):
+    let owner_account_set: bool = !username.is_empty() || !password.is_empty();
+    let user: UserAccount = if owner_account_set || !CONFIG.users.is_empty() { // A username and password are necessary, i.e. auth protection is turned on:
+        match http_request.get_authorization() {
+            Some((provided_uname, provided_pw))
+              if owner_account_set && provided_uname == username && provided_pw == password => { // Uname & PW ok, continue...
+                UserAccount { name: provided_uname, password: provided_pw, permissions: Permissions::ALL }
+            },
+            Some((provided_uname, provided_pw))
+              if CONFIG.users.iter().any(|user| user.name == provided_uname && user.password == provided_pw) => {
+                CONFIG.users.iter().find(|user| user.name == provided_uname).unwrap().clone()
+            },
+            Some((_, provided_pw)) if folder_settings.password.as_deref() == Some(provided_pw.as_str()) => {
+                folder_settings.guest()
+            },
+            Some((provided_uname, provided_pw)) => { // An invalid authorization was provided:
+                HTTPResponse::new_401_unauthorized("").send_to_tcp_stream(&mut stream)?;
+                return Err(Error::other(format!("requested {} with incorrect credentials: {}:{}", get_path, provided_uname, provided_pw)));
+            }
+            None => { // No authorization was provided:
+                HTTPResponse::new_401_unauthorized("").send_to_tcp_stream(&mut stream)?;
+                return Err(Error::other(format!("requested {} without giving credentials!", get_path)));
+            }
+        }
+    } else {
+        UserAccount::anonymous()
+    };
+    // Protected folders additionally need their password, except for users who may see everything:
+    if let Some(folder_password) = &folder_settings.password {
+        if !user.can_see_hidden() && http_request.get_authorization().is_none_or(|(_, provided_pw)| provided_pw != *folder_password) {
+            HTTPResponse::new_401_unauthorized("protected folder").send_to_tcp_stream(&mut stream)?;
+            return Err(Error::other(format!("requested {} (in a protected folder) without giving its password", get_path)));
+        }
+    }
+    // The folder may allow less than the user is allowed otherwise:
+    let user: UserAccount = folder_settings.restrict(user);
+
+    // The bandwidth limits applying to the response (cf. bandwidth.rs), range requests might be prioritized:
+    let throttle: Throttle = bandwidth::throttle(stream.peer_addr().ok().map(|addr| addr.ip()), &user, http_request.contains_range_header());
+
+    // The hidden folder of partial resumable uploads is never served, only requests to the uploads in it are
+    // handled (by the tus module):
+    let staging_dir_prefix: String = format!("/{}", tus::STAGING_DIR_NAME);
+    if decoded_get_path == staging_dir_prefix || decoded_get_path.starts_with(&(staging_dir_prefix.clone() + "/")) {
+        return match decoded_get_path.strip_prefix(&(staging_dir_prefix + "/")) {
+            Some(upload_id) if http_request.get_method() != "GET" => tus::upload_response(&http_request, upload_id, root_dir, &user, &mut stream),
+            _ => HTTPResponse::new_404_not_found(&decoded_get_path[1..]).send_to_tcp_stream(&mut stream),
+        };
+    }
+
+    // Neither is the trash folder nor anything else hidden from the user (cf. hidden.rs):
+    if hidden::is_hidden(fs_path, root_dir, &user) {
+        return HTTPResponse::new_404_not_found(&decoded_get_path[1..]).send_to_tcp_stream(&mut stream);
+    }
+
+    // WebDAV requests (cf. webdav.rs), which may also concern files and folders that don't exist yet:
+    if webdav::WEBDAV_METHODS.contains(&http_request.get_method()) {
+        return webdav::webdav_response(&http_request, fs_path, root_dir, &user, &mut stream);
+    }
+
+    // Files and folders inside of archives (cf. browse.rs), e.g. "/bundle.zip/photos/a.jpg":
+    if http_request.get_method() == "GET" {
+        if let Some((archive_path, inner_path)) = browse::split_archive_path(decoded_get_path, root_dir) {
+            return browse::archive_response(&http_request, &archive_path, &inner_path, root_dir, user, throttle, &mut stream);
+        }
+    }
+
+    // Websites (cf. site.rs), e.g. "/about" standing for "about.html" or a folder being answered with its "index.html":
+    let site_path: PathBuf = match site::resolve(&http_request, fs_path, get_path, query_string, root_dir, &user) {
+        site::Resolved::Path(path) => path,
+        site::Resolved::Redirect(location) => {
+            return HTTPResponse::builder().status(301).header("Location", location).empty().send_to_tcp_stream(&mut stream);
+        }
+    };
+    let fs_path: &Path = &site_path;
+
     // Create the HTTP response body/content:
     let path_metadata = match fs::metadata(fs_path) {
         Ok(metadata) => metadata,
-        Err(_) => {
-            HTTPResponse::new_404_not_found(fs_path.strip_prefix(root_dir).unwrap().to_string_lossy()).send_to_tcp_stream(&mut stream)?;
-            // The '.strip_prefix' is important for not leaking the folder structure of the server to the web user!
-            return Err(Error::new(ErrorKind::Other, format!("Could not find file {}", fs_path.display())));
-        }
+        Err(_) => return site::not_found_response(&http_request, fs_path, root_dir, &user, throttle, &mut stream),
     };
-    if path_metadata.is_dir() {
-        if let Err(err) = dir_response(fs_path, root_dir, &mut stream, query_string) {
-            HTTPResponse::new_500_server_error(err.to_string());
-            return Err(Error::new(ErrorKind::Other, format!("Directory Response error: {}", err)));
+    // The defaults of the folder (e.g. "view=grid") apply unless the URL says otherwise (cf. settings.rs):
+    let merged_query_string: Option<String> = if path_metadata.is_dir() {
+        folder_settings.merge_query(query_string)
+    } else {
+        query_string.map(|query_str| query_str.to_string())
+    };
+    let query_string: Option<&str> = merged_query_string.as_deref();
+    if http_request.get_method() == "OPTIONS" {
+        tus::options_response(&mut stream)?;
+    } else if http_request.get_method() == "HEAD" {
+        head_response(fs_path, &path_metadata, &mut stream)?;
+    } else if let (true, Some(format)) = (http_request.get_method() == "POST", query_param(query_string, "download")) {
+        if !path_metadata.is_dir() {
+            HTTPResponse::builder().status(405).header("Allow", "GET").bytes("Items can only be selected for downloading in folders.").send_to_tcp_stream(&mut stream)?;
+            return Err(Error::other(format!("tried to download a selection of items in {} which is not a folder", get_path)));
+        }
+        download::selection_download_response(&http_request, fs_path, root_dir, &user, format, throttle, &mut stream)?; // "Download selected"
+    } else if http_request.get_method() == "POST"
+        && http_request.get_header("Content-Type").is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded")) {
+        manage::manage_response(&http_request, fs_path, root_dir, &user, &mut stream)?; // Creating, renaming, moving or deleting files/folders.
+    } else if http_request.get_method() == "POST" {
+        if !path_metadata.is_dir() {
+            HTTPResponse::builder().status(405).header("Allow", "GET").bytes("Files can only be uploaded into folders.").send_to_tcp_stream(&mut stream)?;
+            return Err(Error::other(format!("tried to upload to {} which is not a folder", get_path)));
         }
+        if http_request.get_header("Tus-Resumable").is_some() { // A resumable upload (cf. tus.rs):
+            tus::creation_response(&http_request, fs_path, root_dir, &user, &mut stream)?;
+        } else { // A regular upload from an HTML form:
+            upload::upload_response(&http_request, fs_path, root_dir, request_target, &user, &mut stream)?;
+        }
+    } else if let (true, Some(format)) = (path_metadata.is_dir(), query_param(query_string, "download")) {
+        download::download_response(fs_path, root_dir, &user, format, throttle, &mut stream)?; // Downloading the whole folder as an archive.
+    } else if path_metadata.is_dir() && query_param(query_string, "q").is_some_and(|query| !query.is_empty()) {
+        fulltext::search_response(&http_request, fs_path, root_dir, query_string, &user, &mut stream)?; // Searching in the contents of files.
+    } else if path_metadata.is_dir() && query_param(query_string, "search").is_some_and(|term| !term.is_empty()) {
+        search::search_response(&http_request, fs_path, root_dir, query_string, user, &mut stream)?; // Searching for files and folders by name.
+    } else if path_metadata.is_dir() {
+        if let Err(err) = dir_response(&http_request, fs_path, root_dir, &mut stream, query_string, user) {
+            HTTPResponse::new_500_server_error(err.to_string()).send_to_tcp_stream(&mut stream)?;
+            return Err(Error::other(format!("Directory Response error: {}", err)));
+        }
+    } else if preview::wants_preview(&http_request, fs_path, query_string) {
+        preview::preview_response(&http_request, fs_path, get_path, query_string, &mut stream)?; // Markdown, source code and text files.
     } else {
-        if let Err(err) = file_response(&http_request, fs_path, &mut stream, query_string) {
-            HTTPResponse::new_500_server_error(err.to_string());
-            return Err(Error::new(ErrorKind::Other, format!("File Response error: {}", err)));
+        if let Err(err) = file_response(&http_request, fs_path, &mut stream, query_string, throttle) {
+            HTTPResponse::new_500_server_error(err.to_string()).send_to_tcp_stream(&mut stream)?;
+            return Err(Error::other(format!("File Response error: {}", err)));
         }
     };
     Ok(())
 }
 
-lazy_static! {
-    /// Cached JPEG thumbnails of video files whose thumbnail was already requested before.
-    static ref CACHED_THUMBNAILS: RwLock<HashMap<PathBuf, Vec<u8>>> = RwLock::new(HashMap::new());
+/// Responds to a `HEAD` request with the headers a `GET` request for `fs_path` would be answered with
+/// (WebDAV clients, for example, check whether files exist and how large they are this way).
+fn head_response(fs_path: &Path, metadata: &fs::Metadata, stream: &mut TcpStream) -> io::Result<()> {
+    let response = if metadata.is_dir() {
+        HTTPResponse::builder().header("Content-Type", "text/html; charset=utf-8")
+    } else {
+        HTTPResponse::builder()
+            .header("Content-Type", http_share::mime::mime_type(fs_path))
+            .header("Content-Length", metadata.len())
+            .header("Accept-Ranges", "bytes")
+    };
+    response.empty().send_to_tcp_stream(stream)
 }
 
-/// Responds to `stream` with the file contents queried by `filepath`.
-fn file_response(http_request: &HTTPRequest, filepath: &Path, stream: &mut TcpStream, query_string: Option<&str>) -> io::Result<()> {
-    // Check if a thumbnail of a video was requested:
-    if let Some("thumbnail") = query_string { // A thumbnail request:
-        HTTPResponse::new_200_ok(
-            &mut if CACHED_THUMBNAILS.read().unwrap().contains_key(&PathBuf::from(filepath)) {
-                CACHED_THUMBNAILS.read().unwrap()[&PathBuf::from(filepath)].clone()
+/// Responds to `stream` with the file contents queried by `filepath`, only as fast as the `throttle` allows.
+fn file_response(http_request: &HTTPRequest, filepath: &Path, stream: &mut TcpStream, query_string: Option<&str>, throttle: Throttle) -> io::Result<()> {
+    // Check if a thumbnail of an image or video was requested (cf. thumbnail.rs):
+    if let Some(size) = thumbnail::requested_size(query_string) { // A thumbnail request, answered from the cache if possible (cf. cache.rs):
+        let generated: Result<Thumbnail, String> = cache::thumbnail(filepath, size, || {
+            if thumbnail::is_image(filepath) {
+                thumbnail::generate(filepath, size)
             } else {
-                CACHED_THUMBNAILS.write().unwrap()
-                    .entry(PathBuf::from(filepath))
-                    .or_insert_with(|| generate_jpeg_thumbnail(filepath))
-                    .clone() // Cloning is necessary because `new_200_ok` mutates the Vec it's given, emptying it!!
+                thumbnail::resize(&generate_jpeg_thumbnail(filepath), size)
+            }
+        });
+        let thumbnail: Thumbnail = match generated {
+            Ok(thumbnail) => thumbnail,
+            Err(err) => {
+                HTTPResponse::new_500_server_error("Could not generate a thumbnail").send_to_tcp_stream(stream)?;
+                return Err(Error::other(format!("could not generate a thumbnail of {}: {}", filepath.display(), err)));
             }
-        ).send_to_tcp_stream(stream)?;
+        };
+        HTTPResponse::builder()
+            .header("Content-Type", thumbnail.content_type)
+            .bytes(thumbnail.data)
+            .send_to_tcp_stream(stream)?;
     } else { // No thumbnail request, respond with a regular file response:
         // Because of iOS we have to differentiate between 2 cases, a normal "full response" and a "range response" (for videos):
         if http_request.contains_range_header() {
             // iOS always requests ranges of video files and expects an according response!:
             // Parse the requested range from the request, so we can create the response for the iOS device:
-            HTTPResponse::write_206_partial_file_to_stream(filepath, http_request.get_requested_range(), stream)?;
+            HTTPResponse::new_206_partial_file(filepath, http_request.get_requested_range())?.throttled(throttle).send_to_tcp_stream(stream)?;
         } else {
-            // The "normal" (either non-video or non-iOS) case, i.e. just return the entire content directly:
-            HTTPResponse::write_200_ok_file_to_stream(filepath, stream)?;
+            // The "normal" (either non-video or non-iOS) case, i.e. just return the entire content directly,
+            // compressed if the client supports it (range responses are never compressed):
+            let content_type: &str = http_share::mime::mime_type(filepath);
+            let accept_encoding: Option<&str> = http_request.get_header("Accept-Encoding");
+            if let Some((encoding, precompressed_file)) = precompressed_file(filepath, content_type, accept_encoding) {
+                return HTTPResponse::builder()
+                    .header("Content-Type", content_type)
+                    .header("Content-Encoding", encoding.name())
+                    .header("Vary", "Accept-Encoding")
+                    .file(precompressed_file, None)
+                    .throttled(throttle)
+                    .send_to_tcp_stream(stream);
+            }
+            // Try to open the file before writing `200 OK`, so that the HTTP status code can still be changed in case of an error:
+            let file = File::open(filepath)?;
+            HTTPResponse::builder()
+                .header("Content-Type", content_type)
+                .header("Accept-Ranges", "bytes")
+                .file(file, None)
+                .compressed(accept_encoding)
+                .throttled(throttle)
+                .send_to_tcp_stream(stream)?;
         }
     }
     return Ok(());
 }
 
+/// A helper function for `file_response`.
+/// Looks for a precompressed version of the file at `filepath` next to it (e.g. "app.js.br" or
+/// "app.js.gz" for "app.js") in one of the encodings accepted according to `accept_encoding`, the most
+/// preferred one first. Only files with a compressible `content_type` are looked for.
+fn precompressed_file(filepath: &Path, content_type: &str, accept_encoding: Option<&str>) -> Option<(ContentEncoding, File)> {
+    if !http_share::compress::is_compressible(content_type) {
+        return None;
+    }
+    ContentEncoding::accepted(accept_encoding).into_iter().find_map(|encoding| {
+        let mut path = filepath.as_os_str().to_owned();
+        path.push(format!(".{}", encoding.extension()));
+        let path = PathBuf::from(path);
+        if path.is_file() { File::open(path).ok().map(|file| (encoding, file)) } else { None }
+    })
+}
+
 /// A helper function for `file_response`.
 /// Takes a path to a video file and returns a JPEG thumbnail preview of it.
 /// It generates such a thumbnail by executing the "ffmpeg" command in console.
+/// Only one ffmpeg runs at a time (Grid View requests several thumbnails of every video at once).
 fn generate_jpeg_thumbnail(video_file_path: &Path) -> Vec<u8> {
-    // 0.) The name/location of the temporary JPEG thumbnail file:
-    let thumbnail_file_name: &str =
-        &format!("http_share_temp_thumbnail_{}.jpg",
-                 video_file_path.file_name().unwrap_or("".as_ref()).to_str().unwrap_or(""));
+    static FFMPEG_LOCK: Mutex<()> = Mutex::new(());
+    static TEMPORARY_FILES: AtomicU64 = AtomicU64::new(0);
+    let _ffmpeg_lock = FFMPEG_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    // 0.) The name/location of the temporary JPEG thumbnail file (unique, so that no other request uses it as well):
+    let thumbnail_file_path: PathBuf = env::temp_dir().join(format!("http_share_temp_thumbnail_{}_{}.jpg",
+                                                                     std::process::id(), TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)));
+    let thumbnail_file_name: &str = &thumbnail_file_path.to_string_lossy();
 
     // 1.) Execute the 'ffmpeg' command to generate a JPEG thumbnail to said location:
     if let Err(err) = Command::new("ffmpeg")
+        .arg("-y") // (stdin is no terminal, so ffmpeg couldn't ask whether to overwrite the file)
         .arg("-ss")
         .arg("00:00:01.000")
         .arg("-i")
@@ -283,182 +526,547 @@ fn generate_jpeg_thumbnail(video_file_path: &Path) -> Vec<u8> {
 /// The `root_dir` is given to know which prefix to strip from the file paths.
 /// The optional `query_string` (what comes after the '?' in the URL) is given because it might
 /// contain information on how to display the contents of the directory.
-fn dir_response(dir_path: &Path, root_dir: &Path, stream: &mut TcpStream, query_string: Option<&str>) -> io::Result<()> {
-    let mut folder_items: Vec<String> = fs::read_dir(dir_path)?
-        .map(|path| { path.unwrap().path().strip_prefix(root_dir).unwrap().display().to_string() }) // turn a path ("ReadDir") iterator into a String iterator
+///
+/// The listing is sent using 'Transfer-Encoding: chunked', i.e. the HTML is streamed to the client
+/// while it is being generated, so the time-to-first-byte doesn't depend on the size of the folder.
+/// The logged in `user` decides which items are listed (cf. hidden.rs) and which actions (uploading, managing files) are offered.
+fn dir_response(http_request: &HTTPRequest, dir_path: &Path, root_dir: &Path, stream: &mut TcpStream, query_string: Option<&str>, user: UserAccount) -> io::Result<()> {
+    let hidden_items = hidden::FolderRules::of(dir_path, root_dir, &user);
+    let folder_items: Vec<FolderItem> = fs::read_dir(dir_path)?
+        .filter(|path| path.as_ref().map_or(true, |entry| {
+            let is_dir: bool = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            !hidden_items.hides(&entry.path(), is_dir)
+        }))
+        .map(|path| path.unwrap())
+        .map(|entry| FolderItem {
+            path: entry.path().strip_prefix(root_dir).unwrap().display().to_string(),
+            archive_member: None,
+            // (Symbolic links have to be followed to know, which is left for when it's needed:)
+            is_dir: entry.file_type().ok().filter(|file_type| !file_type.is_symlink()).map(|file_type| file_type.is_dir()),
+        })
         .collect(); // The only reason we collect into a Vector is so that we can sort the folder items alphabetically!
-    let html_body: String = if !folder_items.is_empty() {
-        // Take care of the sorting preference / the "sort=..." URL GET parameter:
-        match query_string
-            .map(|query_str| query_str.split("&").find(|param| param.starts_with("sort=")))
-            .flatten()
-        {
-            // Sort Descending: Display the folder items in reverse alphabetical order (but case-insensitive!):
-            Some("sort=desc") => folder_items.sort_by(|a,b| b.to_lowercase().cmp(&a.to_lowercase())),
-            // Sort Randomly:
-            Some("sort=rand") => folder_items.shuffle(&mut thread_rng()),
-            // Default = Sort Ascending: Display the folder items in alphabetical order (but case-insensitive!):
-            _ => folder_items.sort_by(|a,b| a.to_lowercase().cmp(&b.to_lowercase()))
+    let settings: FolderSettings = FolderSettings::of_folder(dir_path, root_dir);
+    let readme: Option<PathBuf> = markdown::readme_name(folder_items.iter().map(FolderItem::name))
+        .map(|name| dir_path.join(name))
+        .filter(|path| path.is_file());
+    let kind = ListingKind::Folder { title: settings.title, description: settings.description, readme };
+    let dir_path: String = dir_path.strip_prefix(root_dir).unwrap().display().to_string();
+    listing_response(http_request, folder_items, query_string, dir_path, user, kind, stream)
+}
+
+/// An item listed on a directory page.
+pub struct FolderItem {
+    /// The path of the item relative to the root directory, e.g. "Photos/2021/a.jpg", which may also
+    /// lead into an archive, e.g. "bundle.zip/a.jpg" (cf. browse.rs).
+    pub path: String,
+    /// The details of an item inside of an archive, `None` for items on disk (whose metadata is only
+    /// read from the file system when it's needed).
+    pub archive_member: Option<browse::MemberDetails>,
+    /// Whether an item on disk is a folder, if that's known without reading its metadata (e.g. from the
+    /// directory entry), cf. `is_dir`.
+    pub is_dir: Option<bool>,
+}
+
+/// One page of a listing: big folders are split into pages of `per_page` items ("page=..." and
+/// "per_page=..." URL GET parameters), so that the metadata of only that many items has to be read (except
+/// when sorting by size or time, which needs the metadata of all items, cf. sort.rs).
+pub struct Page {
+    /// The number of this page, starting at 1.
+    pub number: usize,
+    /// The number of pages.
+    pub count: usize,
+    /// The number of items in the whole folder.
+    pub item_count: usize,
+}
+
+impl Page {
+    /// The number of items per page when no "per_page=..." is given, a multiple of 3 because of the rows of Grid View.
+    pub const DEFAULT_SIZE: usize = 300;
+    /// The maximum number of items per page.
+    pub const MAX_SIZE: usize = 5000;
+
+    /// The page of a folder with `item_count` items chosen using the URL GET parameters in the `query_string`,
+    /// and the range of the indices of its items.
+    pub fn from_query(query_string: Option<&str>, item_count: usize) -> (Self, std::ops::Range<usize>) {
+        let per_page: usize = query_param(query_string, "per_page").and_then(|per_page| per_page.parse().ok())
+            .unwrap_or(Self::DEFAULT_SIZE).clamp(1, Self::MAX_SIZE);
+        let count: usize = item_count.div_ceil(per_page).max(1);
+        let number: usize = query_param(query_string, "page").and_then(|page| page.parse().ok()).unwrap_or(1).clamp(1, count);
+        let start: usize = (number - 1) * per_page;
+        (Self { number, count, item_count }, start..(start + per_page).min(item_count))
+    }
+}
+
+/// The details of a `FolderItem` shown in Table View and in JSON listings (cf. json.rs), `None` where unknown.
+/// The items in folders are counted up to `ItemDetails::MAX_ITEM_COUNT` only.
+pub struct ItemDetails {
+    pub is_dir: bool,
+    /// The size of a file in bytes.
+    pub size: Option<u64>,
+    /// The number of items in a folder.
+    pub item_count: Option<usize>,
+    pub created: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+}
+
+impl ItemDetails {
+    /// The maximum number of items counted in a folder, so that listing a folder containing huge folders
+    /// stays fast.
+    pub const MAX_ITEM_COUNT: usize = 10_000;
+}
+
+impl FolderItem {
+    /// The name of this item, i.e. the last component of its path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap()
+    }
+
+    /// Whether this item is a folder, its metadata only being read from the file system if that's not known.
+    pub fn is_dir(&self) -> bool {
+        if let Some(member) = &self.archive_member {
+            return member.is_dir;
         }
+        return self.is_dir.unwrap_or_else(|| self.details(false).is_dir);
+    }
 
-        format_body(folder_items, query_string, dir_path.strip_prefix(root_dir).unwrap().display().to_string())
-    } else {
-        "This folder is empty.".to_string() // Tell the user when a folder is empty instead of just giving him an empty page.
-    };
-    let http_response = HTTPResponse::new_200_ok(
-        &mut format!(
-            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"/></head><body>\r\n{}</body></html>\r\n", // important because of the UTF-8!!
-            html_body
-        ).into()
-    );
-    http_response.send_to_tcp_stream(stream)
+    /// Reads the details of this item, from the file system for items on disk. The number of items in
+    /// folders on disk is only counted when `with_item_count` is set, as that's expensive for big folders.
+    pub fn details(&self, with_item_count: bool) -> ItemDetails {
+        if let Some(member) = &self.archive_member {
+            return ItemDetails {
+                is_dir: member.is_dir,
+                size: Some(member.size).filter(|_| !member.is_dir),
+                item_count: Some(member.item_count).filter(|_| member.is_dir),
+                created: None,
+                modified: member.modified,
+                accessed: None,
+            };
+        }
+        // Cf. code in handle_connection()!:
+        let binary_path: &String = &env::args().next().expect("Name of binary missing as 0th command line argument");
+        let root_dir: &Path = Path::new(binary_path).parent().expect("Binary has no parent");
+        let fs_path: PathBuf = root_dir.join(&self.path);
+        match fs::metadata(&fs_path) {
+            Ok(metadata) => ItemDetails {
+                is_dir: metadata.is_dir(),
+                size: Some(metadata.len()).filter(|_| metadata.is_file()),
+                item_count: if metadata.is_dir() && with_item_count { fs::read_dir(&fs_path).ok().map(|dir| dir.take(ItemDetails::MAX_ITEM_COUNT).count()) } else { None },
+                created: metadata.created().ok(),
+                modified: metadata.modified().ok(),
+                accessed: metadata.accessed().ok(),
+            },
+            Err(_) => ItemDetails { is_dir: false, size: None, item_count: None, created: None, modified: None, accessed: None },
+        }
+    }
+}
+
+/// What a listing (cf. `listing_response`) shows, which decides the actions offered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListingKind {
+    /// The items of a folder on disk (cf. `dir_response`), with the title and description shown above them
+    /// (cf. settings.rs) and the README file shown below them (cf. markdown.rs).
+    Folder { title: Option<String>, description: Option<String>, readme: Option<PathBuf> },
+    /// The items of a folder inside of an archive (cf. browse.rs), no actions are offered.
+    Archive,
+    /// The files and folders found by a search (cf. search.rs), anywhere below the folder. `truncated`
+    /// if more were found than are listed.
+    SearchResults { truncated: bool },
+}
+
+/// Responds to `stream` with the listing of the `folder_items` of the folder `dir_path` (relative to
+/// the root directory), cf. `ListingKind`.
+/// The listing is compressed if the `http_request` allows it and sent as JSON if it asks for it (cf. json.rs).
+pub fn listing_response(http_request: &HTTPRequest, mut folder_items: Vec<FolderItem>, query_string: Option<&str>, dir_path: String, user: UserAccount, kind: ListingKind, stream: &mut TcpStream) -> io::Result<()> {
+    // Take care of the sorting preference / the "sort=..." URL GET parameters (cf. sort.rs):
+    SortOrder::from_query(query_string).sort(&mut folder_items);
+    if json::wants_json(http_request, query_string) { // A JSON listing for scripts instead of an HTML page:
+        return json::listing_response(http_request, folder_items, query_string, dir_path, stream);
+    }
+
+    // Only the items on the requested page are listed:
+    let (page, page_items) = Page::from_query(query_string, folder_items.len());
+    let folder_items: Vec<FolderItem> = folder_items.drain(page_items).collect();
+
+    // The body is only generated while the response is being written, so everything it needs has to be owned:
+    let query_string: Option<String> = query_string.map(|query_str| query_str.to_string());
+    let dir_url: String = if dir_path.is_empty() { "/".to_string() } else { format!("/{}/", utf8_percent_encode(&dir_path, NON_ALPHANUMERIC)) };
+    HTTPResponse::builder()
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Vary", "Accept") // cf. json.rs
+        .stream(move |writer| {
+            writer.write_all(b"<!DOCTYPE html><html><head><meta charset=\"utf-8\"/></head><body>\r\n")?; // important because of the UTF-8!!
+            if page.item_count > 0 {
+                format_body(writer, folder_items, &page, query_string.as_deref(), dir_path, &user, &kind)?;
+            } else if let ListingKind::SearchResults { .. } = kind {
+                write_search_header(writer, query_string.as_deref(), &dir_path, 0, false)?;
+                writer.write_all(b"<hr>\r\nNothing found.")?;
+            } else {
+                if let ListingKind::Folder { title, description, .. } = &kind {
+                    write_folder_title(writer, title.as_deref(), description.as_deref())?;
+                }
+                writer.write_all(b"This folder is empty.")?; // Tell the user when a folder is empty instead of just giving him an empty page.
+                if (user.can_upload() || user.can_manage()) && matches!(kind, ListingKind::Folder { .. }) {
+                    writer.write_all(b"<hr>\r\n")?;
+                    write_folder_actions(writer, &user)?;
+                }
+            }
+            if let ListingKind::Folder { readme: Some(readme), .. } = &kind {
+                markdown::write_readme(writer, readme, &dir_url)?; // Below the listing, like code hosting sites do.
+            }
+            writer.write_all(b"</body></html>\r\n")
+        })
+        .compressed(http_request.get_header("Accept-Encoding"))
+        .send_to_tcp_stream(stream)
+}
+
+/// A helper function for `listing_response` and `format_body`: writes the `title` and `description` of a folder
+/// (cf. settings.rs) to `writer`, if it has them.
+fn write_folder_title(writer: &mut dyn Write, title: Option<&str>, description: Option<&str>) -> io::Result<()> {
+    if let Some(title) = title {
+        write!(writer, "<h2>{}</h2>\r\n", html_escape(title))?;
+    }
+    if let Some(description) = description {
+        write!(writer, "<p>{}</p>\r\n", html_escape(description))?;
+    }
+    return Ok(());
 }
 
-/// A helper function for `dir_response`.
-/// Takes a Vec of the relative file paths in a folder as Strings (`folder_items`) and
-/// returns the HTML body. The layout may differ depending on the `query_string` (the stuff that comes
-/// after the '?' in the URL) given by the user.
+/// A helper function for `listing_response`.
+/// Takes a Vec of the items on a `page` of a folder (`folder_items`) and
+/// writes the HTML body to `writer`, item by item. The layout may differ depending on the `query_string`
+/// (the stuff that comes after the '?' in the URL) given by the user.
 /// The path of the current directory is given in `dir_path` as a String to let the user know where
 /// he currently is.
-fn format_body(folder_items: Vec<String>, query_string: Option<&str>, dir_path: String) -> String {
-    // Save the number of items (files/directories) in the folder:
-    let folder_size: usize = folder_items.len();
+/// The logged in `user` decides which actions (uploading, managing files) are offered, they are only
+/// offered for folders on disk though (cf. `ListingKind`).
+fn format_body(writer: &mut dyn Write, folder_items: Vec<FolderItem>, page: &Page, query_string: Option<&str>, dir_path: String, user: &UserAccount, kind: &ListingKind) -> io::Result<()> {
+    // Save the number of items (files/directories) in the folder (not just on this page):
+    let folder_size: usize = page.item_count;
+
+    // First, write the "header" (including links that let the user change the layout and the sorting):
+    let sort_order = SortOrder::from_query(query_string);
+    let current_view: &str = query_param(query_string, "view").unwrap_or("list");
+    match kind {
+        ListingKind::SearchResults { truncated } => write_search_header(writer, query_string, &dir_path, folder_size, *truncated)?,
+        ListingKind::Folder { title, description, .. } => {
+            write_folder_title(writer, title.as_deref(), description.as_deref())?;
+            write!(writer, "/{} <i>({} items)</i><br>\r\n", html_escape(&dir_path), folder_size)?;
+        },
+        ListingKind::Archive => write!(writer, "/{} <i>({} items)</i><br>\r\n", html_escape(&dir_path), folder_size)?, // The leading slash ('/') of the path is added manually, cf. `format_path`.
+    }
+    let view_links: Vec<String> = [("list", "List View"), ("table", "Table View"), ("grid", "Grid View")].iter()
+        .map(|(view, label)| option_link(query_string, &[("view", view)], label, *view == current_view))
+        .collect();
+    write!(writer, "{}<br>\r\n", view_links.join("  |  \r\n"))?;
+    let key_links: Vec<String> = SortKey::ALL.iter()
+        .map(|key| option_link(query_string, &[("sort", key.param())], key.label(), *key == sort_order.key))
+        .collect();
+    write!(writer, "Sort by: {}  –  {}  |  {}  –  {}<br>\r\n",
+        key_links.join("  |  \r\n"),
+        option_link(query_string, &[("order", "asc")], "Ascending", !sort_order.descending),
+        option_link(query_string, &[("order", "desc")], "Descending", sort_order.descending),
+        if sort_order.dirs_first {
+            option_link(query_string, &[("dirs", "mixed")], "Folders mixed with files", false)
+        } else {
+            option_link(query_string, &[("dirs", "first")], "Folders first", false)
+        },
+    )?;
+    if matches!(kind, ListingKind::Folder { .. }) {
+        write_search_form(writer, query_string)?;
+        writer.write_all(
+            b"Download as: <a href=\"?download=zip\">ZIP</a>  |  \r\n\
+             <a href=\"?download=tar\">tar</a>  |  \r\n\
+             <a href=\"?download=tar.gz\">tar.gz</a>  |  \r\n\
+             <a href=\"?download=tar.zst\">tar.zst</a><br>\r\n\
+             <form id=\"selectionForm\" method=\"post\" action=\"?download=zip\" style=\"display:inline;\" \
+                   onsubmit=\"if (!document.querySelector('input[form=selectionForm]:checked')) { alert('No items selected.'); return false; }\">\
+                 <button type=\"submit\">Download selected</button>\
+             </form><br>\r\n"
+        )?;
+        write_folder_actions(writer, user)?;
+    }
+    writer.write_all(b"<hr><br>\r\n")?;
+    writer.flush()?; // Send the header right away, before (possibly slowly) formatting the folder items.
 
     let folder_items = folder_items.iter()
-        .map(|path| { format_path(path, query_string) }); // turn the path Strings into HTML links, possibly within a <td>-tag
+        .map(|item| { format_path(item, query_string, user, kind) }); // turn the folder items into HTML links, possibly within a <td>-tag
 
-    let lower_body = match query_string
-            .map(|query_str| query_str.split("&").find(|param| param.starts_with("view=")))
-            .flatten()
+    match query_string
+            .and_then(|query_str| query_str.split("&").find(|param| param.starts_with("view=")))
     {
         // Grid View (previously called Table View!):
-        Some("view=grid") => format!(
-            "<table style=\"table-layout:fixed;width:100%;\">\r\n{}</table>\r\n",
-            folder_items
-                .enumerate()
-                .map(|(i, hyperlink)| {
-                    match i % 3 {
-                        0 => format!("<tr>\r\n{}", &hyperlink),
-                        1 => hyperlink,
-                        _ => format!("{}</tr>\r\n", &hyperlink)
-                    }
-                })
-                .fold(String::from(""), |str1, str2| str1 + &str2)
-        ),
+        Some("view=grid") => {
+            writer.write_all(b"<table id=\"gridViewTable\" style=\"table-layout:fixed;width:100%;\">\r\n")?;
+            for (i, hyperlink) in folder_items.enumerate() {
+                match i % 3 {
+                    0 => write!(writer, "<tr>\r\n{}", &hyperlink)?,
+                    1 => writer.write_all(hyperlink.as_bytes())?,
+                    _ => write!(writer, "{}</tr>\r\n", &hyperlink)?
+                }
+            }
+            writer.write_all(b"</table>\r\n")?;
+            // Infinite scrolling: the items of the next pages are loaded (instead of showing the page links) when scrolling down:
+            write_page_links(writer, page, query_string)?;
+            writer.write_all(INFINITE_SCROLL_JAVASCRIPT.as_bytes())?;
+            return Ok(());
+        },
         // Table View:
-        Some("view=table") => format!(
-            "<table id=\"tableViewTable\" cellpadding=\"5\"  style=\"border-collapse: collapse\">\r\n\
-            <tr>\
-                <th onclick=\"sortTable(0, x => x)\" style=\"border: 1px solid black;\">Name</th>\
-                <th onclick=\"sortTable(1, x => parseInt(x.replaceAll(',','')) || 0)\" style=\"border: 1px solid black;\">Size</th>\
-                <th onclick=\"sortTable(2, x => x)\" style=\"border: 1px solid black;\">Created</th>\
-                <th onclick=\"sortTable(3, x => x)\" style=\"border: 1px solid black;\">Modified</th>\
-                <th onclick=\"sortTable(4, x => x)\" style=\"border: 1px solid black;\">Accessed</th>\
-            </tr>\
-            {}\
-            </table>\r\n{}\r\n",
-            folder_items.fold(String::from(""), |str1, str2| str1 + &str2),
-            SORT_TABLE_JAVASCRIPT
-        ),
+        Some("view=table") => {
+            // Clicking a column header sorts by that column, clicking it again reverses the order:
+            let column_header = |key: SortKey| {
+                let order: &str = if sort_order.key == key && !sort_order.descending { "desc" } else { "asc" };
+                let arrow: &str = match (sort_order.key == key, sort_order.descending) {
+                    (true, false) => " ▲",
+                    (true, true) => " ▼",
+                    (false, _) => "",
+                };
+                format!("<th style=\"border: 1px solid black;\"><a href=\"{}\">{}</a>{}</th>",
+                        url_with(query_string, &[("sort", key.param()), ("order", order)]), key.label(), arrow)
+            };
+            write!(writer,
+                "<table id=\"tableViewTable\" cellpadding=\"5\"  style=\"border-collapse: collapse\">\r\n\
+                <tr>\
+                    <th style=\"border: 1px solid black;\"></th>\
+                    {}{}{}{}\
+                    <th style=\"border: 1px solid black;\">Accessed</th>",
+                column_header(SortKey::Name), column_header(SortKey::Size), column_header(SortKey::Created), column_header(SortKey::Modified)
+            )?;
+            if user.can_manage() && matches!(kind, ListingKind::Folder { .. }) {
+                writer.write_all(b"<th style=\"border: 1px solid black;\">Actions</th>")?;
+            }
+            writer.write_all(b"</tr>")?;
+            for table_row in folder_items {
+                writer.write_all(table_row.as_bytes())?;
+            }
+            writer.write_all(b"</table>\r\n")?;
+        },
         // Default = List View:
-        _ => folder_items.fold(String::from(""), |str1, str2| str1 + &str2) // concatenate all the Strings of the iterator together into 1 single String
-    };
+        _ => for hyperlink in folder_items {
+            writer.write_all(hyperlink.as_bytes())?;
+        }
+    }
+    return write_page_links(writer, page, query_string);
+}
 
-    // At last, add the "header" (including links/buttons that let the user change the layout):
-    return format!( // The leading slash ('/') of the path is added manually, cf. `format_path`.
-        "/{} <i>({} items)</i><br>\r\n\
-         <script>\
-             function setURLSearchParams(view, sort) {{ \
-                 if (view == null) {{ /* ...then use current value... */
-                     view = window.location.search.split('&').filter(param => param.includes('view='))[0]?.split('=')[1];
-                 }}
-                 if (view == null) {{ /* ...or else the default value: */
-                     view = 'list';
-                 }}
-                 if (sort == null) {{ /* ...then use current value... */
-                     sort = window.location.search.split('&').filter(param => param.includes('sort='))[0]?.split('=')[1];
-                 }}
-                 if (sort == null) {{ /* ...or else the default value: */
-                     sort = 'asc';
-                 }}
-                 window.location.search = '?view=' + view + '&sort=' + sort;\
-             }}\
-         </script>\
-         <a href=\"javascript:setURLSearchParams('list', null);\">List View</a>  |  \r\n\
-         <a href=\"javascript:setURLSearchParams('table', null);\">Table View</a>  |  \r\n\
-         <a href=\"javascript:setURLSearchParams('grid', null);\">Grid View</a><br>\r\n\
-         Sort: <a href=\"javascript:setURLSearchParams(null, 'asc');\">Ascending</a>  |  \r\n\
-         <a href=\"javascript:setURLSearchParams(null, 'desc');\">Descending</a>  |  \r\n\
-         <a href=\"javascript:setURLSearchParams(null, 'rand');\">Randomly</a><br>\r\n\
-         <hr><br>\r\n\
-         {}",
-        dir_path, folder_size, lower_body
-    );
+/// A helper function for `format_body`.
+/// Writes the links to the previous and the next page (if the folder has more than one `page`), in an
+/// element with the id "nextPage", whose "data-next" attribute is the URL of the next page (cf. `INFINITE_SCROLL_JAVASCRIPT`).
+fn write_page_links(writer: &mut dyn Write, page: &Page, query_string: Option<&str>) -> io::Result<()> {
+    if page.count <= 1 {
+        return Ok(());
+    }
+    let page_url = |number: usize| url_with(query_string, &[("page", &number.to_string())]);
+    write!(writer, "<p id=\"nextPage\" data-next=\"{}\">Page {} of {}:  {}  |  {}</p>\r\n",
+        if page.number < page.count { page_url(page.number + 1) } else { String::new() },
+        page.number, page.count,
+        if page.number > 1 { format!("<a href=\"{}\">« Previous</a>", page_url(page.number - 1)) } else { "« Previous".to_string() },
+        if page.number < page.count { format!("<a href=\"{}\">Next »</a>", page_url(page.number + 1)) } else { "Next »".to_string() },
+    )
+}
+
+/// A helper function for `listing_response` and `format_body`.
+/// Writes the header of the results of a search (cf. search.rs) for the term in the `query_string` within the
+/// folder `dir_path`: what was searched for, the `result_count` (and whether it was `truncated`) and the
+/// search form for refining the search.
+fn write_search_header(writer: &mut dyn Write, query_string: Option<&str>, dir_path: &str, result_count: usize, truncated: bool) -> io::Result<()> {
+    let term: String = decoded_query_param(query_string, "search");
+    write!(writer, "Search results for \"{}\" in <a href=\"?view={}\">/{}</a> <i>({} items{})</i><br>\r\n",
+        html_escape(&term),
+        html_escape(query_param(query_string, "view").unwrap_or("list")),
+        html_escape(dir_path),
+        result_count,
+        if truncated { ", more were found" } else { "" },
+    )?;
+    return write_search_form(writer, query_string);
+}
+
+/// A helper function for `format_body`, `write_search_header` and `fulltext::search_response`.
+/// Writes the form for searching for files and folders by name within the current folder (cf. search.rs)
+/// and, if enabled, the one for searching in the contents of files (cf. fulltext.rs), filled in with the
+/// current search in the `query_string` (if any) and keeping the current view.
+fn write_search_form(writer: &mut dyn Write, query_string: Option<&str>) -> io::Result<()> {
+    let term: String = decoded_query_param(query_string, "search");
+    let current_match: &str = query_param(query_string, "match").unwrap_or("substring");
+    let match_options: String = [("substring", "Name contains"), ("glob", "Glob"), ("regex", "Regex")].iter()
+        .map(|(value, label)| format!("<option value=\"{}\"{}>{}</option>", value, if *value == current_match { " selected" } else { "" }, label))
+        .collect();
+    write!(writer,
+        "<form method=\"get\" style=\"display:inline;\">\
+            <input type=\"search\" name=\"search\" value=\"{}\" placeholder=\"Search in this folder\" required> \
+            <select name=\"match\">{}</select> \
+            <input type=\"hidden\" name=\"view\" value=\"{}\">\
+            <button type=\"submit\">Search</button>\
+        </form><br>\r\n",
+        html_escape(&term), match_options, html_escape(query_param(query_string, "view").unwrap_or("list")))?;
+    if CONFIG.fulltext_index_enabled {
+        write!(writer,
+            "<form method=\"get\" style=\"display:inline;\">\
+                <input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"Search in file contents\" required> \
+                <button type=\"submit\">Search contents</button>\
+            </form><br>\r\n",
+            html_escape(&decoded_query_param(query_string, "q")))?;
+    }
+    return Ok(());
+}
+
+/// `text` with the characters that have a special meaning in HTML escaped.
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A helper function for `dir_response` and `format_body`.
+/// Writes the actions concerning the current folder the `user` may perform: the upload form and
+/// the "New Folder" button (including the JavaScript needed for managing files).
+fn write_folder_actions(writer: &mut dyn Write, user: &UserAccount) -> io::Result<()> {
+    if user.can_upload() {
+        writer.write_all(upload::UPLOAD_FORM_HTML.as_bytes())?;
+    }
+    if user.can_manage() {
+        write!(writer, "{}<button onclick=\"newFolder()\">New Folder</button> \
+                        <i>(rename, move and delete files in Table View)</i><br>\r\n", manage::manage_javascript(user))?;
+    }
+    return Ok(());
+}
+
+/// Returns the (relative) URL of the current page with the URL GET parameters in `params` set to the given
+/// values, keeping all other parameters of the `query_string` except for one-off ones like "download=..." and
+/// the page (which is only kept when it's given in `params`). The URL is HTML-escaped, for use in attributes.
+fn url_with(query_string: Option<&str>, params: &[(&str, &str)]) -> String {
+    let kept_params = query_string.unwrap_or("").split('&')
+        .filter(|param| !param.is_empty())
+        .filter(|param| {
+            let name: &str = param.split('=').next().unwrap();
+            !params.iter().any(|(new_name, _)| *new_name == name) && !["download", "cursor", "page"].contains(&name)
+        })
+        .map(|param| param.to_string());
+    let new_params = params.iter().map(|(name, value)| format!("{}={}", name, value));
+    html_escape(&format!("?{}", kept_params.chain(new_params).collect::<Vec<String>>().join("&")))
+}
+
+/// A helper function for `format_body`: a link to the current page with the URL GET parameters in `params`
+/// set to the given values, shown as `label`, which is bold if that option is `selected` already.
+fn option_link(query_string: Option<&str>, params: &[(&str, &str)], label: &str, selected: bool) -> String {
+    if selected {
+        format!("<b>{}</b>", label)
+    } else {
+        format!("<a href=\"{}\">{}</a>", url_with(query_string, params), label)
+    }
+}
+
+/// Returns the value of the URL GET parameter called `name` in the `query_string`, if there is one,
+/// e.g. "zip" for the name "download" and the query string "view=table&download=zip".
+fn query_param<'a>(query_string: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query_string?.split('&').find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Returns the percent-decoded value of the URL GET parameter called `name` in the `query_string` ('+' being
+/// a space, like in the URLs of submitted forms), or an empty string if there is none.
+fn decoded_query_param(query_string: Option<&str>, name: &str) -> String {
+    manage::form_urlencoded_pairs(query_string.unwrap_or(""))
+        .find(|(key, _)| key == name)
+        .map_or(String::new(), |(_, value)| value)
+}
+
+/// Whether `fs_path` is within one of the folders this server uses internally (the partial resumable
+/// uploads, the trash folder and the full-text index), which are neither listed nor served.
+fn is_internal_path(fs_path: &Path, root_dir: &Path) -> bool {
+    fs_path.starts_with(root_dir.join(tus::STAGING_DIR_NAME))
+        || fs_path.starts_with(manage::trash_dir(root_dir))
+        || fs_path.starts_with(root_dir.join(fulltext::INDEX_DIR_NAME))
 }
 
 /// A helper function for `format_body`.
 /// Formats just the <a>-hyperlinks depending on the layout specified in the `query_string`
 /// (either List, Table or Grid View).
-fn format_path(path: &String, query_string: Option<&str>) -> String {
+/// Every item on disk gets a checkbox for selecting it for the "Download selected" button, archives
+/// get an additional link for browsing their contents (cf. browse.rs).
+/// In Table View, buttons for renaming, moving and deleting are added when the `user` may manage files.
+/// Search results (cf. `ListingKind`) are shown with their path within the shared folder and get neither.
+fn format_path(item: &FolderItem, query_string: Option<&str>, user: &UserAccount, kind: &ListingKind) -> String {
+    let path: &String = &item.path;
     // <a href="hyperlink">display_name</a>
     let hyperlink = utf8_percent_encode(path, NON_ALPHANUMERIC).to_string();
-    let display_name = path.split('/').last().unwrap(); // only display the file name to the user
+    let display_name = item.name(); // only display the file name to the user
+    // (Escaped, as names may come from anyone who may upload, e.g. the members of an uploaded archive:)
+    let shown_name: String = html_escape(if let ListingKind::SearchResults { .. } = kind { path } else { display_name });
+    let on_disk: bool = item.archive_member.is_none();
+    let in_folder: bool = matches!(kind, ListingKind::Folder { .. });
+    // The checkbox for selecting the item for the "Download selected" button (its value being the name):
+    let checkbox = if on_disk && in_folder {
+        format!("<input type=\"checkbox\" name=\"item\" value=\"{}\" form=\"selectionForm\">",
+                html_escape(display_name))
+    } else {
+        String::new()
+    };
+    let browse_link = if on_disk && browse::is_browsable_archive(display_name) {
+        format!(" <a href=\"/{}/\"><i>(browse)</i></a>", hyperlink) // The trailing "/" makes it a folder, cf. `browse::split_archive_path`.
+    } else {
+        String::new()
+    };
 
     match query_string
-        .map(|query_str| query_str.split("&").find(|param| param.starts_with("view=")))
-        .flatten()
+        .and_then(|query_str| query_str.split("&").find(|param| param.starts_with("view=")))
     {
         // Grid View (previously called Table View!):
         Some("view=grid") => {
-            if path.ends_with(".mp4") { // Display ffmpeg generated thumbnails for .mp4 files:
-                format!("<td style=\"border: 1px solid black;\"><a href=\"/{}\"><img src=\"/{}?thumbnail\" alt=\"{}\" width=\"100%\"></a></td>\r\n", hyperlink, hyperlink, display_name)
+            if (path.ends_with(".mp4") || thumbnail::is_image(Path::new(path))) && on_disk { // Display thumbnails of images and (ffmpeg generated ones of) .mp4 files:
+                // The browser picks the size fitting the column (a third of the width), cf. thumbnail.rs:
+                let srcset: Vec<String> = CONFIG.thumbnail_sizes.iter().map(|size| format!("/{}?thumbnail={} {}w", hyperlink, size, size)).collect();
+                format!("<td style=\"border: 1px solid black;\">{}<a href=\"/{}\"><img src=\"/{}?thumbnail\" srcset=\"{}\" sizes=\"33vw\" alt=\"{}\" width=\"100%\" loading=\"lazy\"></a></td>\r\n",
+                        checkbox, hyperlink, hyperlink, srcset.join(", "), shown_name)
                 // Old approach was to show videos in a <video> tag but that was way too computationally expensive:
                 // format!("<td style=\"border: 1px solid black;\"><video width=\"100%\" preload=\"metadata\" controls src=\"{}\">{}</video></td>\r\n", hyperlink, display_name)
             } else { // Display all other file types in an HTML <img> Tag with the file name as the alt text:
-                format!("<td style=\"border: 1px solid black;\"><a href=\"/{}\"><img src=\"/{}\" alt=\"{}\" width=\"100%\"></a></td>\r\n", hyperlink, hyperlink, display_name)
+                format!("<td style=\"border: 1px solid black;\">{}<a href=\"/{}\"><img src=\"/{}\" alt=\"{}\" width=\"100%\"></a></td>\r\n", checkbox, hyperlink, hyperlink, shown_name)
             }
         },
         // Table View:
         Some("view=table") => {
-            // Cf. code in handle_connection()!:
-            let binary_path: &String = &env::args().next().expect("Name of binary missing as 0th command line argument");
-            let root_dir: &Path = Path::new(binary_path).parent().expect("Binary has no parent");
-            let fs_path_buffer: PathBuf = root_dir.join(&path);
-            let fs_path: &Path = fs_path_buffer.as_path();
-
-            let metadata = &fs::metadata(fs_path); //File::open(fs_path).unwrap().metadata(); //&fs::metadata(fs_path);
-            let metadata = metadata.as_ref();
+            // The size (or number of items), creation, modification and access time:
+            let details: ItemDetails = item.details(true);
+            let size: String = if details.is_dir {
+                format!("<i>({} items)</i>", details.item_count.map_or("?".to_string(), |item_count|
+                    if item_count >= ItemDetails::MAX_ITEM_COUNT { item_count.separated_string() + "+" } else { item_count.to_string() }))
+            } else {
+                details.size.map_or("?".to_string(), |size| size.separated_string() + "B")
+            };
+            let details: [String; 4] = [
+                size,
+                system_time_to_string(details.created),
+                system_time_to_string(details.modified),
+                system_time_to_string(details.accessed),
+            ];
             format!(
                 "<tr>\
-                <td style=\"border: 1px solid black;\"><a href=\"/{}\">{}</a></td>\
+                <td style=\"border: 1px solid black;\">{}</td>\
+                <td style=\"border: 1px solid black;\"><a href=\"/{}\">{}</a>{}</td>\
                 <td style=\"border: 1px solid black;\">{}</td>\
                 <td style=\"border: 1px solid black;\">{}</td>\
                 <td style=\"border: 1px solid black;\">{}</td>\
                 <td style=\"border: 1px solid black;\">{}</td>\
+                {}\
                 </tr>\r\n",
-                hyperlink, display_name,
-                metadata.map_or("?".to_string(), |meta|
-                    if meta.is_file() {
-                        meta.len().separated_string() + "B"
-                    } else {
-                        format!("<i>({} items)</i>", fs::read_dir(fs_path).map_or("?".to_string(), |dir| dir.count().to_string()))
-                    }),
-                metadata.map_or("?".to_string(), |meta| system_time_to_string(meta.created())),
-                metadata.map_or("?".to_string(), |meta| system_time_to_string(meta.modified())),
-                metadata.map_or("?".to_string(), |meta| system_time_to_string(meta.accessed())),
+                checkbox, hyperlink, shown_name, browse_link,
+                details[0], details[1], details[2], details[3],
+                if user.can_manage() && on_disk && in_folder {
+                    format!("<td style=\"border: 1px solid black;\">{}</td>", manage::row_actions_html(&hyperlink))
+                } else {
+                    String::new()
+                },
             )
         },
         // Default = List View:
-        _ => format!("<a href=\"/{}\">{}</a><br>\r\n", hyperlink, display_name) // The "/" is important!
+        _ => format!("{} <a href=\"/{}\">{}</a>{}<br>\r\n", checkbox, hyperlink, shown_name, browse_link) // The "/" is important!
     }
 }
 
 /// Helper function for `format_path`.
-fn system_time_to_string(system_time: io::Result<SystemTime>) -> String {
+fn system_time_to_string(system_time: Option<SystemTime>) -> String {
     return match system_time {
-        Ok(system_time) =>
+        Some(system_time) =>
             DateTime::<Utc>::from(system_time).format("%Y-%m-%d %H:%M:%S").to_string(),
-        Err(_) => "?".to_string()
+        None => "?".to_string()
     };
 }
 
@@ -467,62 +1075,37 @@ fn date_time_str<'a>() -> DelayedFormat<StrftimeItems<'a>> {
     Local::now().format("%Y-%m-%d %H:%M:%S")
 }
 
-// Source: https://www.w3schools.com/howto/howto_js_sort_table.asp
-const SORT_TABLE_JAVASCRIPT: &str =
-"<!-- Script below taken (and slightly adapted) from: https://www.w3schools.com/howto/howto_js_sort_table.asp -->
-<script>
-function sortTable(n, apply_before) {
-  var table, rows, switching, i, x, y, shouldSwitch, dir, switchcount = 0;
-  table = document.getElementById(\"tableViewTable\");
-  switching = true;
-  // Set the sorting direction to ascending:
-  dir = \"asc\";
-  /* Make a loop that will continue until
-  no switching has been done: */
-  while (switching) {
-    // Start by saying: no switching is done:
-    switching = false;
-    rows = table.rows;
-    /* Loop through all table rows (except the
-    first, which contains table headers): */
-    for (i = 1; i < (rows.length - 1); i++) {
-      // Start by saying there should be no switching:
-      shouldSwitch = false;
-      /* Get the two elements you want to compare,
-      one from current row and one from the next: */
-      x = rows[i].getElementsByTagName(\"TD\")[n];
-      y = rows[i + 1].getElementsByTagName(\"TD\")[n];
-      /* Check if the two rows should switch place,
-      based on the direction, asc or desc: */
-      if (dir == \"asc\") {
-        if (apply_before(x.innerHTML.toLowerCase()) > apply_before(y.innerHTML.toLowerCase())) {
-          // If so, mark as a switch and break the loop:
-          shouldSwitch = true;
-          break;
-        }
-      } else if (dir == \"desc\") {
-        if (apply_before(x.innerHTML.toLowerCase()) < apply_before(y.innerHTML.toLowerCase())) {
-          // If so, mark as a switch and break the loop:
-          shouldSwitch = true;
-          break;
-        }
-      }
-    }
-    if (shouldSwitch) {
-      /* If a switch has been marked, make the switch
-      and mark that a switch has been done: */
-      rows[i].parentNode.insertBefore(rows[i + 1], rows[i]);
-      switching = true;
-      // Each time a switch is done, increase this count by 1:
-      switchcount ++;
+/// The JavaScript loading the next pages of Grid View while scrolling down ("infinite scrolling"): once the
+/// element with the id "nextPage" (cf. `write_page_links`) comes near, the next page is fetched and its rows
+/// are appended to the table, until there are no more pages.
+const INFINITE_SCROLL_JAVASCRIPT: &str =
+"<script>
+const nextPage = document.getElementById('nextPage');
+if (nextPage && nextPage.dataset.next) {
+  nextPage.textContent = 'Loading more items...';
+  const observer = new IntersectionObserver(async entries => {
+    const next = nextPage.dataset.next;
+    if (!entries[0].isIntersecting || !next) return;
+    nextPage.dataset.next = ''; // Only one page at a time.
+    try {
+      const response = await fetch(next);
+      const page = new DOMParser().parseFromString(await response.text(), 'text/html');
+      const table = document.getElementById('gridViewTable');
+      for (const row of page.querySelectorAll('#gridViewTable tr')) table.appendChild(row);
+      nextPage.dataset.next = page.getElementById('nextPage')?.dataset.next || '';
+    } catch (err) {
+      observer.disconnect();
+      nextPage.textContent = 'Loading more items failed: ' + err;
+      return;
+    }
+    observer.unobserve(nextPage);
+    if (nextPage.dataset.next) {
+      observer.observe(nextPage); // Check again whether it's still near (then no new intersection would be reported).
     } else {
-      /* If no switching has been done AND the direction is \"asc\",
-      set the direction to \"desc\" and run the while loop again. */
-      if (switchcount == 0 && dir == \"asc\") {
-        dir = \"desc\";
-        switching = true;
-      }
-    }
-  }
+      nextPage.remove();
+    }
+  }, { rootMargin: '1000px' });
+  observer.observe(nextPage);
 }
-</script>";
+</script>
+";
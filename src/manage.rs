@@ -0,0 +1,239 @@
+//! File management from the browser: creating folders and renaming, moving and deleting files and
+//! folders (when enabled using the `--file-management` option, for users with the 'manage' permission).
+//!
+//! Every operation is a `POST` request to the file or folder in question with an
+//! 'application/x-www-form-urlencoded' body containing the `action` ("mkdir", "rename", "move" or
+//! "delete"), its parameters and the `csrf_token` of the user. Deleted items are moved into the
+//! trash folder instead of being deleted for good.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Error, Read};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use http_share::{HTTPRequest, HTTPResponse};
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use crate::config::{UserAccount, CONFIG};
+use crate::date_time_str;
+use crate::hidden;
+use crate::settings::FolderSettings;
+use crate::upload::sanitize_filename;
+
+/// The maximum size of the body of a file management request, in bytes.
+const MAX_BODY_SIZE: u64 = 16 * 1024;
+
+lazy_static! {
+    /// A random key generated on startup, from which the CSRF token of every user is derived (cf. `csrf_token`).
+    static ref CSRF_KEY: [u8; 32] = rand::thread_rng().gen();
+}
+
+/// The token of the `user` that has to be sent along with every file management request. As other websites
+/// cannot read the pages of this server, they cannot know it, which protects against cross-site request
+/// forgery (CSRF): otherwise, any website could make the browser of a logged-in user delete files, as
+/// browsers send the Basic Authentication credentials along automatically. It's derived from the name of the
+/// user (HMAC-SHA256 using `CSRF_KEY`), so that the token shown to one user is of no use for another one.
+fn csrf_token(user: &UserAccount) -> String {
+    const BLOCK_SIZE: usize = 64; // of SHA-256
+    let key_block = |padding: u8| -> Vec<u8> {
+        let mut block: Vec<u8> = CSRF_KEY.iter().map(|byte| byte ^ padding).collect();
+        block.resize(BLOCK_SIZE, padding);
+        block
+    };
+    let inner = Sha256::new().chain_update(key_block(0x36)).chain_update(user.name.as_bytes()).finalize();
+    let outer = Sha256::new().chain_update(key_block(0x5c)).chain_update(inner).finalize();
+    return outer.iter().map(|byte| format!("{:02x}", byte)).collect();
+}
+
+/// The JavaScript functions behind the file management buttons on directory pages (for the `user`).
+pub fn manage_javascript(user: &UserAccount) -> String {
+    format!("<script>
+const CSRF_TOKEN = '{}';
+async function manageItem(path, params) {{
+    params.csrf_token = CSRF_TOKEN;
+    const response = await fetch(path, {{method: 'POST', headers: {{'Content-Type': 'application/x-www-form-urlencoded'}}, body: new URLSearchParams(params)}});
+    if (response.ok) {{
+        location.reload();
+    }} else {{
+        alert(await response.text());
+    }}
+}}
+function itemName(path) {{
+    return decodeURIComponent(path.split('/').pop());
+}}
+function newFolder() {{
+    const name = prompt('Name of the new folder:');
+    if (name) {{ manageItem(location.pathname, {{action: 'mkdir', name: name}}); }}
+}}
+function renameItem(path) {{
+    const name = prompt('Rename \"' + itemName(path) + '\" to:', itemName(path));
+    if (name && name != itemName(path)) {{ manageItem(path, {{action: 'rename', name: name}}); }}
+}}
+function moveItem(path) {{
+    const destination = prompt('Move \"' + itemName(path) + '\" into the folder:', decodeURIComponent(location.pathname));
+    if (destination) {{ manageItem(path, {{action: 'move', destination: destination}}); }}
+}}
+function deleteItem(path) {{
+    if (confirm('Do you really want to delete \"' + itemName(path) + '\"?')) {{ manageItem(path, {{action: 'delete'}}); }}
+}}
+</script>\r\n", csrf_token(user))
+}
+
+/// The buttons for renaming, moving and deleting the item at `hyperlink` (an already percent-encoded
+/// path, relative to the root directory), shown in the table view.
+pub fn row_actions_html(hyperlink: &str) -> String {
+    format!("<button onclick=\"renameItem('/{0}')\">Rename</button> \
+             <button onclick=\"moveItem('/{0}')\">Move</button> \
+             <button onclick=\"deleteItem('/{0}')\">Delete</button>", hyperlink)
+}
+
+/// The folder deleted files and folders are moved into.
+pub fn trash_dir(root_dir: &Path) -> PathBuf {
+    root_dir.join(&CONFIG.trash_dir) // (if the configured trash folder is absolute, `join` just returns it)
+}
+
+/// Handles a file management request concerning `fs_path` (a file or folder).
+pub fn manage_response(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    let relative_path: String = fs_path.strip_prefix(root_dir).unwrap().display().to_string();
+    if !user.can_manage() {
+        return send_error(stream, 403, format!("you are not allowed to manage files (requested for /{})", relative_path));
+    }
+    let mut body = String::new();
+    http_request.body_reader(stream).take(MAX_BODY_SIZE).read_to_string(&mut body)?;
+    let params: HashMap<String, String> = parse_form_urlencoded(&body);
+    if params.get("csrf_token") != Some(&csrf_token(user)) {
+        return send_error(stream, 403, format!("missing or invalid CSRF token (requested for /{})", relative_path));
+    }
+    // Only ever touch things within the shared folder, and never the shared folder itself:
+    let canonical_root: PathBuf = fs::canonicalize(root_dir)?;
+    let canonical_path: PathBuf = fs::canonicalize(fs_path)?;
+    if !canonical_path.starts_with(&canonical_root) {
+        return send_error(stream, 403, format!("/{} is outside of the shared folder", relative_path));
+    }
+    let action: &str = params.get("action").map_or("", |action| action.as_str());
+    if canonical_path == canonical_root && action != "mkdir" {
+        return send_error(stream, 403, "the shared folder itself cannot be renamed, moved or deleted".to_string());
+    }
+    let new_name = || params.get("name").and_then(|name| sanitize_filename(name));
+
+    let result: Result<String, (u16, String)> = match action {
+        "mkdir" => match new_name() {
+            Some(_) if !fs_path.is_dir() => Err((400, "new folders can only be created inside of folders".to_string())),
+            Some(name) => create_new(&fs_path.join(&name), |new_path| fs::create_dir(new_path))
+                .map(|_| format!("created folder /{}", Path::new(&relative_path).join(&name).display())),
+            None => Err((400, "invalid folder name".to_string())),
+        },
+        "rename" => match new_name() {
+            Some(name) => {
+                let new_path: PathBuf = fs_path.with_file_name(&name);
+                create_new(&new_path, |new_path| fs::rename(fs_path, new_path))
+                    .map(|_| format!("renamed /{} to {}", relative_path, name))
+            },
+            None => Err((400, "invalid name".to_string())),
+        },
+        "move" => {
+            // The destination is a URL path like "/Photos/2021" (not percent-encoded):
+            let destination: String = params.get("destination").map_or("", |destination| destination.as_str()).trim_matches('/').to_string();
+            let destination_path: PathBuf = root_dir.join(&destination);
+            match fs::canonicalize(&destination_path) {
+                Ok(destination_dir) if destination_dir.is_dir() && destination_dir.starts_with(&canonical_root)
+                                       && !crate::is_internal_path(&destination_path, root_dir)
+                                       && !hidden::is_hidden(&destination_path, root_dir, user) => {
+                    // The destination folder has to allow the same as the folder of the item (cf. settings.rs):
+                    let destination_settings: FolderSettings = FolderSettings::of_folder(&destination_path, root_dir);
+                    let provided_password: Option<String> = http_request.get_authorization().map(|(_, password)| password);
+                    if destination_dir.starts_with(&canonical_path) {
+                        Err((400, "a folder cannot be moved into itself".to_string()))
+                    } else if !destination_settings.restrict(user.clone()).can_manage() {
+                        Err((403, format!("/{} is read-only", destination)))
+                    } else if destination_settings.password.is_some() && !user.can_see_hidden() && destination_settings.password != provided_password {
+                        Err((403, format!("/{} is protected by a password", destination)))
+                    } else {
+                        create_new(&destination_dir.join(fs_path.file_name().unwrap()), |new_path| move_item(fs_path, new_path))
+                            .map(|_| format!("moved /{} to /{}", relative_path, destination))
+                    }
+                },
+                _ => Err((404, format!("no such folder: /{}", destination))),
+            }
+        },
+        "delete" => {
+            let trash_dir: PathBuf = trash_dir(root_dir);
+            if canonical_path.starts_with(fs::canonicalize(&trash_dir).unwrap_or(trash_dir.clone())) {
+                Err((403, "the trash folder cannot be managed from the browser".to_string()))
+            } else {
+                move_to_trash(fs_path, &trash_dir).map(|_| format!("deleted /{} (moved it to the trash folder)", relative_path))
+            }
+        },
+        _ => Err((400, format!("invalid action: {}", action))),
+    };
+
+    match result {
+        Ok(message) => {
+            println!("[{}] {} ({}) {}", date_time_str(), stream.peer_addr().map_or("???".to_string(), |addr| addr.to_string()), user.name, message);
+            HTTPResponse::new_200_ok(message).send_to_tcp_stream(stream)
+        },
+        Err((status_code, message)) => send_error(stream, status_code, message),
+    }
+}
+
+/// Moves `fs_path` into the `trash_dir` (creating it if necessary), prefixing its name with the current
+/// time (in nanoseconds) and a counter so that deleting multiple items with the same name doesn't cause
+/// collisions, not even within the same instant.
+pub fn move_to_trash(fs_path: &Path, trash_dir: &Path) -> Result<(), (u16, String)> {
+    static TRASHED_ITEMS: AtomicU64 = AtomicU64::new(0);
+    fs::create_dir_all(trash_dir).map_err(|err| (500, format!("could not create the trash folder: {}", err)))?;
+    let timestamp: u128 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos());
+    let trash_name: String = format!("{}_{}_{}", timestamp, TRASHED_ITEMS.fetch_add(1, Ordering::Relaxed),
+                                     fs_path.file_name().unwrap().to_string_lossy());
+    return create_new(&trash_dir.join(trash_name), |trash_path| move_item(fs_path, trash_path));
+}
+
+/// Moves the file or folder at `from` to `to`, by copying and deleting it if they are on different
+/// file systems (e.g. with a trash folder on another drive).
+fn move_item(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            // Everything is copied, hidden items too, as the original is deleted afterwards:
+            crate::webdav::copy_recursively(from, to, true, None)?;
+            if from.is_dir() { fs::remove_dir_all(from) } else { fs::remove_file(from) }
+        },
+        result => result,
+    }
+}
+
+/// Calls `create` to create `new_path` unless something already exists at `new_path`.
+fn create_new(new_path: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> Result<(), (u16, String)> {
+    if new_path.exists() {
+        return Err((409, format!("{} already exists", new_path.file_name().unwrap_or_default().to_string_lossy())));
+    }
+    return create(new_path).map_err(|err| match err.kind() {
+        io::ErrorKind::PermissionDenied => (403, format!("permission denied: {}", err)),
+        _ => (500, err.to_string()),
+    });
+}
+
+/// Parses an 'application/x-www-form-urlencoded' body like "action=rename&name=My+Photo.jpg".
+fn parse_form_urlencoded(body: &str) -> HashMap<String, String> {
+    form_urlencoded_pairs(body).collect()
+}
+
+/// Returns the decoded (key, value) pairs of an 'application/x-www-form-urlencoded' body in order,
+/// including all values of keys that occur multiple times (like the checkboxes of a form).
+pub fn form_urlencoded_pairs(body: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            let decode = |s: &str| percent_decode_str(&s.replace('+', " ")).decode_utf8_lossy().to_string();
+            (decode(key), decode(value))
+        })
+}
+
+/// Responds with the given error and returns it as an `Err`, so that it gets logged.
+fn send_error(stream: &mut TcpStream, status_code: u16, message: String) -> io::Result<()> {
+    HTTPResponse::builder().status(status_code).bytes(format!("Error: {}", message)).send_to_tcp_stream(stream)?;
+    return Err(Error::other(format!("file management request failed: {}", message)));
+}
@@ -0,0 +1,127 @@
+//! Rendering Markdown (CommonMark, plus tables, strikethrough, task lists and footnotes) as HTML, e.g. the
+//! README of a folder below its listing (cf. `write_readme`).
+//!
+//! The HTML is sanitized, as the Markdown comes from files anyone who may upload could have written: raw
+//! HTML in the Markdown is shown as text and links and images using anything but harmless URL schemes (like
+//! `javascript:`) are removed.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use crate::html_escape;
+
+/// The names of the files shown below the listing of the folder they are in (ignoring case), the
+/// preferred one first.
+const README_NAMES: [&str; 4] = ["readme.md", "readme.markdown", "readme.txt", "readme"];
+
+/// How many bytes of a README file are shown at most.
+const MAX_README_SIZE: u64 = 1 << 20;
+
+/// Renders the `markdown` as (sanitized) HTML, cf. the module documentation. Relative links and images are
+/// relative to the URL `base_url` (ending with a '/').
+pub fn to_html(markdown: &str, base_url: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        // Raw HTML is shown as text, HTML blocks as paragraphs:
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::HtmlBlock) => Event::Start(Tag::Paragraph),
+        Event::End(TagEnd::HtmlBlock) => Event::End(TagEnd::Paragraph),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+            Event::Start(Tag::Link { link_type, dest_url: safe_url(dest_url, base_url), title, id })
+        },
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+            Event::Start(Tag::Image { link_type, dest_url: safe_url(dest_url, base_url), title, id })
+        },
+        event => event,
+    });
+    let mut html = String::new();
+    html::push_html(&mut html, events);
+    return html;
+}
+
+/// Helper function for `to_html`: the `url` if it uses a harmless scheme (like "https:"), an empty one otherwise.
+/// Relative URLs are made relative to the `base_url`.
+fn safe_url<'a>(url: CowStr<'a>, base_url: &str) -> CowStr<'a> {
+    let scheme: Option<String> = url.find(':')
+        .filter(|&colon| !url[..colon].contains(['/', '?', '#']))
+        .map(|colon| url[..colon].to_ascii_lowercase());
+    match scheme.as_deref() {
+        None if url.is_empty() || url.starts_with(['/', '#']) => url,
+        None => CowStr::from(format!("{}{}", base_url, url)),
+        Some("http") | Some("https") | Some("mailto") | Some("ftp") => url,
+        Some(_) => CowStr::Borrowed(""),
+    }
+}
+
+/// The name of the README file among the `names` of the items in a folder, if there is one.
+pub fn readme_name<'a>(names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    names
+        .filter_map(|name| README_NAMES.iter().position(|readme| name.eq_ignore_ascii_case(readme)).map(|rank| (rank, name)))
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Writes the README file at `path` to `writer` (at most the first `MAX_README_SIZE` bytes): rendered as
+/// HTML if it is a Markdown file, as plain text otherwise. `dir_url` is the URL of the folder it is in.
+pub fn write_readme(writer: &mut dyn Write, path: &Path, dir_url: &str) -> io::Result<()> {
+    let mut content: Vec<u8> = Vec::new();
+    File::open(path)?.take(MAX_README_SIZE).read_to_end(&mut content)?;
+    let content = String::from_utf8_lossy(&content);
+    let name: String = path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+
+    write!(writer, "<hr>\r\n<b>{}</b>\r\n<div id=\"readme\">\r\n", html_escape(&name))?;
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("md") || extension.eq_ignore_ascii_case("markdown")) {
+        writer.write_all(to_html(&content, dir_url).as_bytes())?;
+    } else {
+        write!(writer, "<pre style=\"white-space: pre-wrap;\">{}</pre>", html_escape(&content))?;
+    }
+    return writer.write_all(b"\r\n</div>\r\n");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `url` as made safe by `safe_url` for a README in the folder "/docs/".
+    fn safe(url: &str) -> String {
+        safe_url(CowStr::Borrowed(url), "/docs/").to_string()
+    }
+
+    #[test]
+    fn harmless_urls() {
+        assert_eq!(safe("https://example.com/a?b=c"), "https://example.com/a?b=c");
+        assert_eq!(safe("HTTP://example.com"), "HTTP://example.com");
+        assert_eq!(safe("mailto:someone@example.com"), "mailto:someone@example.com");
+        assert_eq!(safe("/absolute/path"), "/absolute/path");
+        assert_eq!(safe("#section"), "#section");
+        assert_eq!(safe(""), "");
+    }
+
+    #[test]
+    fn relative_urls() {
+        assert_eq!(safe("image.png"), "/docs/image.png");
+        assert_eq!(safe("sub/page.md"), "/docs/sub/page.md");
+        assert_eq!(safe("a/b:c"), "/docs/a/b:c"); // (not a scheme, as it follows a '/')
+        assert_eq!(safe("?x=a:b"), "/docs/?x=a:b");
+    }
+
+    #[test]
+    fn dangerous_urls() {
+        assert_eq!(safe("javascript:alert(1)"), "");
+        assert_eq!(safe("JavaScript:alert(1)"), "");
+        assert_eq!(safe("java\tscript:alert(1)"), "");
+        assert_eq!(safe("data:text/html,<script>alert(1)</script>"), "");
+        assert_eq!(safe("vbscript:msgbox"), "");
+    }
+
+    #[test]
+    fn sanitized_html() {
+        let html: String = to_html("[click](javascript:alert(1)) <script>alert(2)</script>\n\n![img](pic.png)", "/docs/");
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("src=\"/docs/pic.png\""));
+    }
+}
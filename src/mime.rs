@@ -0,0 +1,50 @@
+//! Guessing the MIME type of a file from its extension.
+
+use std::path::Path;
+
+/// Returns the MIME type of the file at `path` judging by its extension, e.g. "image/jpeg" for
+/// "photo.JPG". Unknown extensions result in "application/octet-stream".
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension: String = path.extension().map_or(String::new(), |extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_str() {
+        // Text:
+        "txt" | "log" | "ini" | "cfg" | "conf" => "text/plain; charset=utf-8",
+        "md" | "markdown" => "text/markdown; charset=utf-8",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "rs" | "c" | "h" | "cpp" | "hpp" | "py" | "java" | "go" | "sh" | "toml" | "yaml" | "yml" | "ts" => "text/plain; charset=utf-8",
+        // Images:
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "heic" => "image/heic",
+        // Audio & video:
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        // Documents & archives:
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,372 @@
+//! A streaming parser for 'multipart/form-data' request bodies (RFC 7578), as sent by HTML forms
+//! containing `<input type="file">` elements.
+//!
+//! The parser never holds more than a small buffer in memory, so that uploaded files can be written
+//! straight to disk, no matter how large they are.
+
+use std::io::{self, Read};
+
+/// The number of bytes read from the underlying reader at once.
+const READ_SIZE: usize = 64 * 1024;
+
+/// The maximum size of the headers of a single part, in bytes.
+const MAX_PART_HEADER_SIZE: usize = 16 * 1024;
+
+/// The headers of one part of a 'multipart/form-data' body.
+#[derive(Debug, Clone, Default)]
+pub struct PartHeaders {
+    /// The name of the form field, from the 'Content-Disposition' header.
+    pub name: Option<String>,
+    /// The name of the uploaded file as given by the client (unsanitized!), from the
+    /// 'Content-Disposition' header. `None` for regular (non-file) form fields.
+    pub filename: Option<String>,
+    /// The value of the 'Content-Type' header of this part, if there is one.
+    pub content_type: Option<String>,
+}
+
+/// Reads a 'multipart/form-data' body part by part.
+///
+/// Use `next_part` to advance to the next part and then read its content using the `Read`
+/// implementation, which returns `Ok(0)` once the end of the current part is reached.
+pub struct MultipartReader<R: Read> {
+    reader: R,
+    /// The delimiter between two parts: "\r\n--" followed by the boundary.
+    delimiter: Vec<u8>,
+    /// Bytes read from `reader` but not yet consumed.
+    buffer: Vec<u8>,
+    /// Whether the preamble (everything up to and including the first delimiter) was skipped.
+    preamble_skipped: bool,
+    /// Whether the end of the current part (i.e. the next delimiter) was reached.
+    part_finished: bool,
+    /// Whether the closing delimiter ("--" after the boundary) was reached.
+    done: bool,
+}
+
+impl<R: Read> MultipartReader<R> {
+    /// Create a new `MultipartReader` reading a body separated by `boundary` from `reader`.
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buffer: Vec::new(),
+            preamble_skipped: false,
+            part_finished: true,
+            done: false,
+        }
+    }
+
+    /// Extracts the boundary from the value of a 'Content-Type' header like
+    /// "multipart/form-data; boundary=----WebKitFormBoundary7MA4YWxkTrZu0gW".
+    /// Returns `None` when the content type isn't 'multipart/form-data' or contains no boundary.
+    pub fn boundary_from_content_type(content_type: &str) -> Option<String> {
+        let mut params = content_type.split(';');
+        if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+            return None;
+        }
+        return params
+            .filter_map(|param| param.trim().split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty());
+    }
+
+    /// Skips the rest of the current part and advances to the next one, returning its headers.
+    /// Returns `Ok(None)` when there are no more parts.
+    pub fn next_part(&mut self) -> io::Result<Option<PartHeaders>> {
+        if self.done {
+            return Ok(None);
+        }
+        // Skip the preamble or whatever is left of the current part:
+        if !self.preamble_skipped {
+            self.skip_preamble()?;
+        }
+        io::copy(self, &mut io::sink())?;
+
+        // Right after a delimiter there is either "--" (closing delimiter, e.g. right after the first one for an
+        // empty form) or "\r\n" (followed by the headers):
+        while self.buffer.len() < 2 {
+            if !self.fill_buffer()? {
+                return Err(unexpected_eof());
+            }
+        }
+        if self.buffer.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+
+        // Read the headers of the next part, which end with an empty line:
+        let headers_end: usize = loop {
+            if let Some(index) = find(&self.buffer, b"\r\n\r\n") {
+                break index;
+            } else if self.buffer.len() > MAX_PART_HEADER_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "multipart headers too large"));
+            } else if !self.fill_buffer()? {
+                return Err(unexpected_eof());
+            }
+        };
+        let headers: String = String::from_utf8_lossy(&self.buffer[..headers_end]).to_string();
+        self.buffer.drain(..headers_end + 4);
+        self.part_finished = false;
+
+        let mut part_headers = PartHeaders::default();
+        for line in headers.split("\r\n") {
+            let (name, value) = match line.split_once(':') {
+                Some(header) => header,
+                None => continue, // (also skips the line break that followed the delimiter)
+            };
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                for (key, value) in parse_header_params(value) {
+                    match key.to_ascii_lowercase().as_str() {
+                        "name" => part_headers.name = Some(value),
+                        "filename" => part_headers.filename = Some(value),
+                        _ => {}
+                    }
+                }
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                part_headers.content_type = Some(value.trim().to_string());
+            }
+        }
+        return Ok(Some(part_headers));
+    }
+
+    /// Helper function for `next_part`: skips everything up to and including the first delimiter, which
+    /// (unlike all others) isn't preceded by a line break if the body has no preamble.
+    fn skip_preamble(&mut self) -> io::Result<()> {
+        let mut at_start: bool = true;
+        loop {
+            if at_start && self.buffer.starts_with(&self.delimiter[2..]) { // (the delimiter without the "\r\n")
+                self.buffer.drain(..self.delimiter.len() - 2);
+                break;
+            } else if let Some(index) = find(&self.buffer, &self.delimiter) {
+                self.buffer.drain(..index + self.delimiter.len());
+                break;
+            } else if self.buffer.len() >= self.delimiter.len() {
+                // The end of the buffer might contain the beginning of the delimiter, so it has to stay in the buffer:
+                self.buffer.drain(..self.buffer.len() + 1 - self.delimiter.len());
+                at_start = false;
+            }
+            if !self.fill_buffer()? {
+                return Err(unexpected_eof());
+            }
+        }
+        self.preamble_skipped = true;
+        return Ok(());
+    }
+
+    /// Reads more data from the underlying reader into the buffer.
+    /// Returns `false` when the underlying reader is exhausted.
+    fn fill_buffer(&mut self) -> io::Result<bool> {
+        let old_len = self.buffer.len();
+        self.buffer.resize(old_len + READ_SIZE, 0);
+        let bytes_read = loop {
+            match self.reader.read(&mut self.buffer[old_len..]) {
+                Ok(bytes_read) => break bytes_read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.buffer.truncate(old_len);
+                    return Err(err);
+                }
+            }
+        };
+        self.buffer.truncate(old_len + bytes_read);
+        return Ok(bytes_read > 0);
+    }
+}
+
+impl<R: Read> Read for MultipartReader<R> {
+    /// Reads the content of the current part. Returns `Ok(0)` at the end of the part.
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.part_finished || out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // Everything before the next delimiter belongs to the current part:
+            let available: usize = match find(&self.buffer, &self.delimiter) {
+                Some(0) => {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.part_finished = true;
+                    return Ok(0);
+                },
+                Some(index) => index,
+                // The end of the buffer might contain the beginning of a delimiter, so it has to stay in the buffer:
+                None => self.buffer.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if available > 0 {
+                let n = available.min(out.len());
+                out[..n].copy_from_slice(&self.buffer[..n]);
+                self.buffer.drain(..n);
+                return Ok(n);
+            }
+            if !self.fill_buffer()? {
+                return Err(unexpected_eof());
+            }
+        }
+    }
+}
+
+/// Returns the index of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body ended before the closing boundary")
+}
+
+/// Parses the parameters of a header value like `form-data; name="files"; filename="a;b.jpg"`
+/// into (key, value) pairs, respecting quoted values (which may contain ';').
+fn parse_header_params(header_value: &str) -> Vec<(String, String)> {
+    let mut params: Vec<(String, String)> = Vec::new();
+    let mut chars = header_value.chars().peekable();
+    loop {
+        // Skip to the start of the next parameter:
+        while let Some(&c) = chars.peek() {
+            if c == ';' || c.is_whitespace() { chars.next(); } else { break; }
+        }
+        if chars.peek().is_none() {
+            return params;
+        }
+        let mut key = String::new();
+        let mut has_value = false;
+        for c in chars.by_ref() {
+            match c {
+                '=' => { has_value = true; break; },
+                ';' => break,
+                _ => key.push(c),
+            }
+        }
+        let mut value = String::new();
+        if !has_value {
+            // A parameter without a value, like the "form-data" in `form-data; name="files"`.
+        } else if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => if let Some(escaped) = chars.next() { value.push(escaped) },
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect();
+        }
+        params.push((key.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader returning one byte at a time, so that delimiters are split across reads.
+    struct ByteByByte<'a>(&'a [u8]);
+
+    impl Read for ByteByByte<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || out.is_empty() {
+                return Ok(0);
+            }
+            out[0] = self.0[0];
+            self.0 = &self.0[1..];
+            return Ok(1);
+        }
+    }
+
+    /// Reads all parts of the `body`, returning their headers and contents.
+    fn parts<R: Read>(reader: R, boundary: &str) -> io::Result<Vec<(PartHeaders, String)>> {
+        let mut multipart = MultipartReader::new(reader, boundary);
+        let mut parts = Vec::new();
+        while let Some(headers) = multipart.next_part()? {
+            let mut contents = String::new();
+            multipart.read_to_string(&mut contents)?;
+            parts.push((headers, contents));
+        }
+        return Ok(parts);
+    }
+
+    const BODY: &[u8] = b"--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Holiday\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"files\"; filename=\"a;b.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line 1\r\n--Xy --XyZ\r\nline 2\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn reads_all_parts() {
+        let parts = parts(BODY, "XyZ").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0.name.as_deref(), Some("title"));
+        assert_eq!(parts[0].0.filename, None);
+        assert_eq!(parts[0].1, "Holiday");
+        assert_eq!(parts[1].0.name.as_deref(), Some("files"));
+        assert_eq!(parts[1].0.filename.as_deref(), Some("a;b.txt"));
+        assert_eq!(parts[1].0.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(parts[1].1, "line 1\r\n--Xy --XyZ\r\nline 2");
+    }
+
+    #[test]
+    fn reads_parts_split_across_reads() {
+        let parts = parts(ByteByByte(BODY), "XyZ").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].1, "Holiday");
+        assert_eq!(parts[1].1, "line 1\r\n--Xy --XyZ\r\nline 2");
+    }
+
+    #[test]
+    fn skips_the_preamble() {
+        let body = b"This is the preamble, --XyZ isn't a delimiter here.\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--XyZ--";
+        let parts = parts(&body[..], "XyZ").unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].0.name.as_deref(), Some("a"));
+        assert_eq!(parts[0].1, "1");
+        assert_eq!(self::parts(ByteByByte(body), "XyZ").unwrap()[0].1, "1");
+    }
+
+    #[test]
+    fn empty_form() {
+        assert!(parts(&b"--XyZ--\r\n"[..], "XyZ").unwrap().is_empty());
+        assert!(parts(&b"preamble\r\n--XyZ--"[..], "XyZ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated_body() {
+        let error = parts(&BODY[..BODY.len() - 12], "XyZ").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(parts(&b""[..], "XyZ").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(parts(&b"no delimiter at all"[..], "XyZ").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn too_large_headers() {
+        let mut body: Vec<u8> = b"--XyZ\r\nX-Padding: ".to_vec();
+        body.extend(std::iter::repeat_n(b'a', 2 * MAX_PART_HEADER_SIZE));
+        assert_eq!(parts(&body[..], "XyZ").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn boundary_from_content_type() {
+        let boundary = |content_type: &str| MultipartReader::<&[u8]>::boundary_from_content_type(content_type);
+        assert_eq!(boundary("multipart/form-data; boundary=----abc123").as_deref(), Some("----abc123"));
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b\"").as_deref(), Some("a b"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+    }
+
+    #[test]
+    fn header_params() {
+        assert_eq!(parse_header_params(" form-data; name=\"files\"; filename=\"say \\\"hi\\\";.txt\""), vec![
+            ("form-data".to_string(), String::new()),
+            ("name".to_string(), "files".to_string()),
+            ("filename".to_string(), "say \"hi\";.txt".to_string()),
+        ]);
+        assert_eq!(parse_header_params("form-data; name=plain ; x"), vec![
+            ("form-data".to_string(), String::new()),
+            ("name".to_string(), "plain".to_string()),
+            ("x".to_string(), String::new()),
+        ]);
+    }
+}
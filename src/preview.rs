@@ -0,0 +1,413 @@
+//! Preview pages for text files ("preview" URL GET parameter, e.g. `/notes.md?preview`): Markdown is rendered
+//! as HTML (cf. markdown.rs), source code is shown with syntax highlighting and line numbers and other text
+//! files with line numbers.
+//!
+//! Browsers navigating to a Markdown, source code or text file get its preview page by default (except with
+//! `--static-site`), the file itself is always available using "raw" (e.g. `/notes.md?raw`).
+//! The encoding of the file is detected (UTF-8, UTF-16 with or without byte order mark or else Latin-1) and
+//! only the first `MAX_PREVIEW_SIZE` bytes are shown.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::path::Path;
+use http_share::{HTTPRequest, HTTPResponse};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use separator::Separatable;
+use crate::config::CONFIG;
+use crate::{html_escape, markdown, query_param};
+
+/// How many bytes of a file are shown at most in its preview.
+const MAX_PREVIEW_SIZE: u64 = 1 << 20;
+
+/// The inline CSS of the kinds of tokens highlighted in source code.
+const COMMENT_STYLE: &str = "color: #6a737d; font-style: italic;";
+const STRING_STYLE: &str = "color: #032f62;";
+const NUMBER_STYLE: &str = "color: #005cc5;";
+const KEYWORD_STYLE: &str = "color: #d73a49; font-weight: bold;";
+
+/// How the source code of a programming (or configuration) language is highlighted.
+struct Language {
+    /// The file extensions of the language (lowercase).
+    extensions: &'static [&'static str],
+    /// What starts a comment until the end of the line.
+    line_comments: &'static [&'static str],
+    /// What starts and ends a comment that may span multiple lines.
+    block_comment: Option<(&'static str, &'static str)>,
+    /// The characters strings are enclosed in.
+    quotes: &'static [char],
+    keywords: &'static [&'static str],
+}
+
+/// The languages whose source code is highlighted.
+const LANGUAGES: &[Language] = &[
+    Language {
+        extensions: &["rs"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        keywords: &["as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+            "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+            "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while"],
+    },
+    Language {
+        extensions: &["c", "h", "cpp", "hpp", "cc", "cxx", "cs", "java", "kt", "swift", "scala", "dart"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        keywords: &["abstract", "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue", "default",
+            "delete", "do", "double", "else", "enum", "extends", "extern", "false", "final", "float", "for", "fun", "func",
+            "goto", "if", "implements", "import", "include", "int", "interface", "let", "long", "namespace", "new", "null",
+            "nullptr", "override", "package", "private", "protected", "public", "return", "short", "signed", "sizeof",
+            "static", "struct", "switch", "template", "this", "throw", "throws", "true", "try", "typedef", "union",
+            "unsigned", "using", "val", "var", "virtual", "void", "volatile", "while"],
+    },
+    Language {
+        extensions: &["js", "mjs", "cjs", "jsx", "ts", "tsx"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        keywords: &["async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do",
+            "else", "export", "extends", "false", "finally", "for", "from", "function", "if", "import", "in", "instanceof",
+            "interface", "let", "new", "null", "of", "return", "static", "super", "switch", "this", "throw", "true", "try",
+            "type", "typeof", "undefined", "var", "void", "while", "yield"],
+    },
+    Language {
+        extensions: &["go"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        keywords: &["break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "false", "for",
+            "func", "go", "goto", "if", "import", "interface", "map", "nil", "package", "range", "return", "select",
+            "struct", "switch", "true", "type", "var"],
+    },
+    Language {
+        extensions: &["py", "pyw"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        keywords: &["False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+            "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+            "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield"],
+    },
+    Language {
+        extensions: &["rb"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        keywords: &["begin", "break", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "for", "if",
+            "in", "module", "next", "nil", "not", "or", "and", "require", "rescue", "return", "self", "super", "then",
+            "true", "unless", "until", "when", "while", "yield"],
+    },
+    Language {
+        extensions: &["sh", "bash", "zsh", "fish"],
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        keywords: &["case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local",
+            "return", "then", "until", "while"],
+    },
+    Language {
+        extensions: &["toml", "yaml", "yml", "ini", "cfg", "conf", "properties", "dockerfile", "makefile", "mk"],
+        line_comments: &["#", ";"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        keywords: &["true", "false", "null", "yes", "no", "on", "off"],
+    },
+    Language {
+        extensions: &["json"],
+        line_comments: &[],
+        block_comment: None,
+        quotes: &['"'],
+        keywords: &["true", "false", "null"],
+    },
+    Language {
+        extensions: &["css", "scss", "less"],
+        line_comments: &[],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        keywords: &["important", "inherit", "initial", "none", "auto"],
+    },
+    Language {
+        extensions: &["sql"],
+        line_comments: &["--"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['\''],
+        keywords: &["select", "from", "where", "insert", "into", "values", "update", "set", "delete", "create", "table",
+            "drop", "alter", "join", "left", "right", "inner", "outer", "on", "group", "by", "order", "having", "limit",
+            "and", "or", "not", "null", "as", "distinct", "union", "primary", "key", "SELECT", "FROM", "WHERE", "INSERT",
+            "INTO", "VALUES", "UPDATE", "SET", "DELETE", "CREATE", "TABLE", "DROP", "ALTER", "JOIN", "LEFT", "RIGHT",
+            "INNER", "OUTER", "ON", "GROUP", "BY", "ORDER", "HAVING", "LIMIT", "AND", "OR", "NOT", "NULL", "AS",
+            "DISTINCT", "UNION", "PRIMARY", "KEY"],
+    },
+    Language {
+        extensions: &["html", "htm", "xml", "xhtml", "plist"],
+        line_comments: &[],
+        block_comment: Some(("<!--", "-->")),
+        quotes: &[],
+        keywords: &[],
+    },
+];
+
+/// The file extensions of text files that are previewed without highlighting.
+const TEXT_EXTENSIONS: [&str; 5] = ["txt", "log", "csv", "tsv", "text"];
+
+/// The file extensions of Markdown files.
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// The language of the file at `path` (by its extension or, for files like "Makefile", its name).
+fn language(path: &Path) -> Option<&'static Language> {
+    let extension: String = path.extension().or(path.file_name())?.to_string_lossy().to_lowercase();
+    LANGUAGES.iter().find(|language| language.extensions.contains(&extension.as_str()))
+}
+
+/// Whether the file at `path` is a Markdown file.
+fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| MARKDOWN_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Whether `http_request` for the file at `fs_path` is answered with its preview page: when asked for using
+/// "preview" or, for Markdown, source code and text files, when a browser navigates to it (unless "raw" is given).
+pub fn wants_preview(http_request: &HTTPRequest, fs_path: &Path, query_string: Option<&str>) -> bool {
+    let has_param = |name: &str| query_string.is_some_and(|query_str| query_str.split('&')
+        .any(|param| param == name || param.starts_with(&format!("{}=", name))));
+    if http_request.get_method() != "GET" || has_param("raw") || http_request.contains_range_header() {
+        return false;
+    }
+    if has_param("preview") {
+        return true;
+    }
+    let is_html_file: bool = http_share::mime::mime_type(fs_path).starts_with("text/html");
+    let is_text_file: bool = fs_path.extension()
+        .is_some_and(|extension| TEXT_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()));
+    let navigating: bool = http_request.get_header("Accept").is_some_and(|accept| accept.contains("text/html"));
+    return !CONFIG.static_site && navigating && !is_html_file && (is_markdown(fs_path) || is_text_file || language(fs_path).is_some());
+}
+
+/// Responds to `stream` with the preview page of the file at `fs_path`, `url_path` being its (percent-encoded)
+/// URL path, cf. the module documentation. Markdown files are shown as source code with "preview=source".
+pub fn preview_response(http_request: &HTTPRequest, fs_path: &Path, url_path: &str, query_string: Option<&str>, stream: &mut TcpStream) -> io::Result<()> {
+    let file = File::open(fs_path)?;
+    let size: u64 = file.metadata()?.len();
+    let mut bytes: Vec<u8> = Vec::new();
+    file.take(MAX_PREVIEW_SIZE).read_to_end(&mut bytes)?;
+    let truncated: bool = size > bytes.len() as u64;
+    let text: Option<(String, &str)> = decode(&bytes);
+    let rendered: bool = is_markdown(fs_path) && query_param(query_string, "preview") != Some("source");
+
+    let name: String = fs_path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let dir_url: &str = &url_path[..url_path.rfind('/').map_or(0, |slash| slash + 1)];
+    let raw_url: String = format!("{}{}?raw", dir_url, utf8_percent_encode(&name, NON_ALPHANUMERIC));
+    let mut page: String = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"/><title>{name}</title></head><body>\r\n\
+         <a href=\"{dir_url}\">Back to the folder</a><br>\r\n<b>{name}</b> <i>({size} bytes{encoding})</i>  –  \
+         {source_link}<a href=\"{raw_url}\">Raw</a>  |  <a href=\"{raw_url}\" download>Download</a><br>\r\n",
+        name = html_escape(&name),
+        dir_url = if dir_url.is_empty() { "/" } else { dir_url },
+        size = size.separated_string(),
+        encoding = text.as_ref().map_or(String::new(), |(_, encoding)| format!(", {}", encoding)),
+        source_link = match (is_markdown(fs_path), rendered) {
+            (true, true) => "<a href=\"?preview=source\">Source</a>  |  ",
+            (true, false) => "<a href=\"?preview\">Rendered</a>  |  ",
+            (false, _) => "",
+        },
+        raw_url = raw_url,
+    );
+    if truncated {
+        page += &format!("Only the first {} bytes are shown. <a href=\"{}\" download>Download the full file</a><br>\r\n",
+            MAX_PREVIEW_SIZE.separated_string(), raw_url);
+    }
+    page += "<hr>\r\n";
+    match text {
+        None => page += &format!("This is not a text file. <a href=\"{}\">Open it</a>", raw_url),
+        Some((text, _)) if rendered => page += &markdown::to_html(&text, dir_url),
+        Some((text, _)) => page += &highlight(&text, language(fs_path)),
+    }
+    page += "\r\n</body></html>\r\n";
+
+    HTTPResponse::builder()
+        .header("Content-Type", "text/html; charset=utf-8")
+        .bytes(page)
+        .compressed(http_request.get_header("Accept-Encoding"))
+        .send_to_tcp_stream(stream)
+}
+
+/// Decodes the text in `bytes` (possibly cut off at the end), detecting its encoding: UTF-8 or UTF-16 (with a
+/// byte order mark or, without one, when every other byte is zero), Latin-1 otherwise. Returns the text and the
+/// name of the encoding, `None` for binary files.
+fn decode(bytes: &[u8]) -> Option<(String, &'static str)> {
+    let utf16 = |bytes: &[u8], big_endian: bool| -> String {
+        let units = bytes.chunks_exact(2).map(|unit| if big_endian { u16::from_be_bytes([unit[0], unit[1]]) } else { u16::from_le_bytes([unit[0], unit[1]]) });
+        char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+    };
+    if let Some(bytes) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return Some((String::from_utf8_lossy(bytes).to_string(), "UTF-8"));
+    }
+    if let Some(bytes) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return Some((utf16(bytes, false), "UTF-16LE"));
+    }
+    if let Some(bytes) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return Some((utf16(bytes, true), "UTF-16BE"));
+    }
+    // Without a byte order mark, UTF-16 (of mostly ASCII text) is recognized by the zero bytes (and by not
+    // containing control characters, unlike binary files):
+    let zeros = |parity: usize| bytes.iter().skip(parity).step_by(2).filter(|&&byte| byte == 0).count();
+    let is_text = |text: &str| !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0C'));
+    let half: usize = bytes.len() / 2;
+    for (big_endian, encoding) in [(false, "UTF-16LE"), (true, "UTF-16BE")] {
+        let (high_bytes, low_bytes) = if big_endian { (zeros(0), zeros(1)) } else { (zeros(1), zeros(0)) };
+        if half > 0 && high_bytes * 3 > half && low_bytes * 20 < half {
+            let text: String = utf16(bytes, big_endian);
+            if is_text(&text) {
+                return Some((text, encoding));
+            }
+        }
+    }
+    if bytes.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some((text.to_string(), "UTF-8")),
+        // A character might have been cut off at the end:
+        Err(err) if err.error_len().is_none() => Some((String::from_utf8_lossy(&bytes[..err.valid_up_to()]).to_string(), "UTF-8")),
+        Err(_) => Some((bytes.iter().map(|&byte| byte as char).collect(), "Latin-1")),
+    }
+}
+
+/// Renders the `text` as HTML with line numbers, highlighting its comments, strings, numbers and keywords if
+/// the `language` is known.
+fn highlight(text: &str, language: Option<&Language>) -> String {
+    let tokens: Vec<(Option<&str>, String)> = match language {
+        Some(language) => tokenize(text, language),
+        None => vec![(None, text.to_string())],
+    };
+    // Tokens may span multiple lines (e.g. block comments), their spans are closed at the end of every line:
+    let mut lines: Vec<String> = vec![String::new()];
+    for (style, token) in tokens {
+        for (i, part) in token.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(String::new());
+            }
+            let part: &str = part.strip_suffix('\r').unwrap_or(part);
+            if part.is_empty() {
+                continue;
+            }
+            let line: &mut String = lines.last_mut().unwrap();
+            match style {
+                Some(style) => *line += &format!("<span style=\"{}\">{}</span>", style, html_escape(part)),
+                None => *line += &html_escape(part),
+            }
+        }
+    }
+    if lines.len() > 1 && lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop(); // (The newline at the end of the file.)
+    }
+    let width: usize = lines.len().to_string().len();
+    let numbered_lines: Vec<String> = lines.iter().enumerate()
+        .map(|(i, line)| format!("<span style=\"color: #999; user-select: none;\">{:>width$}  </span>{}", i + 1, line, width = width))
+        .collect();
+    return format!("<pre style=\"tab-size: 4;\">{}</pre>", numbered_lines.join("\n"));
+}
+
+/// Helper function for `highlight`: splits the `text` into tokens, the highlighted ones with their style.
+fn tokenize(text: &str, language: &Language) -> Vec<(Option<&'static str>, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let starts_with = |i: usize, prefix: &str| prefix.chars().enumerate().all(|(j, c)| chars.get(i + j) == Some(&c));
+    let mut tokens: Vec<(Option<&'static str>, String)> = Vec::new();
+    let mut plain = String::new();
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let start: usize = i;
+        let style: &'static str = if let Some((begin, end)) = language.block_comment.filter(|(begin, _)| starts_with(i, begin)) {
+            i += begin.chars().count();
+            while i < chars.len() && !starts_with(i, end) {
+                i += 1;
+            }
+            i = (i + end.chars().count()).min(chars.len());
+            COMMENT_STYLE
+        } else if language.line_comments.iter().any(|comment| starts_with(i, comment)) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            COMMENT_STYLE
+        } else if language.quotes.contains(&chars[i]) {
+            let quote: char = chars[i];
+            i += 1;
+            // Only strings in backquotes may span multiple lines:
+            while i < chars.len() && chars[i] != quote && (quote == '`' || chars[i] != '\n') {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(chars.len());
+            STRING_STYLE
+        } else if chars[i].is_ascii_digit() && (i == 0 || !(chars[i - 1].is_alphanumeric() || chars[i - 1] == '_')) {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.' || chars[i] == '_') {
+                i += 1;
+            }
+            NUMBER_STYLE
+        } else if chars[i].is_alphabetic() || chars[i] == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if !language.keywords.contains(&word.as_str()) {
+                plain += &word;
+                continue;
+            }
+            KEYWORD_STYLE
+        } else {
+            plain.push(chars[i]);
+            i += 1;
+            continue;
+        };
+        if !plain.is_empty() {
+            tokens.push((None, std::mem::take(&mut plain)));
+        }
+        tokens.push((Some(style), chars[start..i].iter().collect()));
+    }
+    if !plain.is_empty() {
+        tokens.push((None, plain));
+    }
+    return tokens;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` in UTF-16, little endian or big endian.
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16().flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() }).collect()
+    }
+
+    #[test]
+    fn utf8() {
+        assert_eq!(decode("Grüße\n".as_bytes()), Some(("Grüße\n".to_string(), "UTF-8")));
+        assert_eq!(decode(b"\xEF\xBB\xBFwith BOM"), Some(("with BOM".to_string(), "UTF-8")));
+        assert_eq!(decode(b""), Some((String::new(), "UTF-8")));
+        // A character cut off at the end is left out:
+        assert_eq!(decode(b"caf\xC3"), Some(("caf".to_string(), "UTF-8")));
+    }
+
+    #[test]
+    fn utf16_text() {
+        let text: &str = "Hello, wörld!\r\n";
+        assert_eq!(decode(&[&[0xFF, 0xFE][..], &utf16(text, false)].concat()), Some((text.to_string(), "UTF-16LE")));
+        assert_eq!(decode(&[&[0xFE, 0xFF][..], &utf16(text, true)].concat()), Some((text.to_string(), "UTF-16BE")));
+        // Without a byte order mark:
+        assert_eq!(decode(&utf16(text, false)), Some((text.to_string(), "UTF-16LE")));
+        assert_eq!(decode(&utf16(text, true)), Some((text.to_string(), "UTF-16BE")));
+    }
+
+    #[test]
+    fn latin1() {
+        assert_eq!(decode(b"caf\xE9 cr\xE8me"), Some(("café crème".to_string(), "Latin-1")));
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(decode(b"\x7FELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x03\x00>\x00"), None);
+        assert_eq!(decode(&[0u8; 64]), None);
+        assert_eq!(decode(b"PK\x03\x04\x14\x00\x00\x00\x08\x00"), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! Searching for files and folders by name, recursively within the current folder
+//! ("search=..." URL GET parameter, e.g. `/Photos?search=*.jpg&match=glob`).
+//!
+//! The results are listed just like the items of a folder (cf. `listing_response`), i.e. in any of the
+//! three views or as JSON (`format=json`). How deep the search goes and how many results are listed is
+//! limited ("depth=..." and "max_results=...").
+//!
+//! Without an index, every search walks the folder tree. With `--search-index`, the names of all files
+//! and folders are kept in memory instead, updated by a file system watcher, so that searching large
+//! trees is instant. Either way, hidden files and folders (cf. hidden.rs) are not found, neither is anything
+//! in protected folders below the current folder (cf. settings.rs).
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, Error};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use ansi_term::Colour::Red;
+use http_share::{HTTPRequest, HTTPResponse};
+use lazy_static::lazy_static;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::event::ModifyKind;
+use regex::{Regex, RegexBuilder};
+use crate::config::UserAccount;
+use crate::{date_time_str, decoded_query_param, hidden, is_internal_path, listing_response, query_param, settings, FolderItem, ListingKind};
+
+/// How many folders deep a search goes when no "depth=..." is given.
+const DEFAULT_DEPTH: usize = 32;
+
+/// How many results are listed at most when no "max_results=..." is given.
+const DEFAULT_MAX_RESULTS: usize = 1000;
+
+/// How many results may be listed at most (even when a larger "max_results=..." is given).
+const MAX_RESULTS: usize = 10_000;
+
+lazy_static! {
+    /// The paths (relative to the root directory, separated by '/') of all files and folders in the shared
+    /// folder, if the index is enabled (`--search-index`) and was built already.
+    static ref INDEX: RwLock<Option<BTreeSet<String>>> = RwLock::new(None);
+}
+
+/// How names are matched against the search term ("match=..." URL GET parameter).
+pub enum NamePattern {
+    /// The name contains the (lowercase) term, ignoring case (the default).
+    Substring(String),
+    /// The whole name matches a glob ("match=glob", e.g. "*.jpg" or "IMG_????.*") or a regular expression
+    /// ("match=regex"), ignoring case.
+    Regex(Regex),
+}
+
+impl NamePattern {
+    /// Parses the search `term` as the given kind of pattern ("substring", "glob" or "regex").
+    pub fn parse(term: &str, kind: &str) -> Result<Self, String> {
+        let regex = match kind {
+            "substring" => return Ok(NamePattern::Substring(term.to_lowercase())),
+            "glob" => glob_to_regex(term),
+            "regex" => term.to_string(),
+            _ => return Err(format!("Unknown kind of search: {}", kind)),
+        };
+        RegexBuilder::new(&regex).case_insensitive(true).size_limit(1 << 20).build()
+            .map(NamePattern::Regex)
+            .map_err(|err| format!("Invalid search pattern: {}", err))
+    }
+
+    /// Whether the file or folder `name` matches this pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Substring(term) => name.to_lowercase().contains(term.as_str()),
+            NamePattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Helper function for `NamePattern::parse`: turns a glob ('*' matching any characters, '?' matching one)
+/// into a regular expression matching whole names.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    return regex;
+}
+
+/// Responds to `stream` with the files and folders within `dir_path` whose names match the search given
+/// in the `query_string` (cf. the module documentation).
+pub fn search_response(http_request: &HTTPRequest, dir_path: &Path, root_dir: &Path, query_string: Option<&str>, user: UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    let term: String = decoded_query_param(query_string, "search");
+    let pattern: NamePattern = match NamePattern::parse(&term, query_param(query_string, "match").unwrap_or("substring")) {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            HTTPResponse::builder().status(400).bytes(err.clone()).send_to_tcp_stream(stream)?;
+            return Err(Error::other(format!("searched for {}: {}", term, err)));
+        }
+    };
+    let max_depth: usize = query_param(query_string, "depth").and_then(|depth| depth.parse().ok()).unwrap_or(DEFAULT_DEPTH);
+    let max_results: usize = query_param(query_string, "max_results").and_then(|max_results| max_results.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RESULTS).min(MAX_RESULTS);
+
+    let (paths, truncated): (Vec<String>, bool) = search(dir_path, root_dir, &pattern, max_depth, max_results, &user);
+    let folder_items: Vec<FolderItem> = paths.into_iter().map(|path| FolderItem { path, archive_member: None, is_dir: None }).collect();
+    let dir_path: String = dir_path.strip_prefix(root_dir).unwrap().display().to_string();
+    listing_response(http_request, folder_items, query_string, dir_path, user, ListingKind::SearchResults { truncated }, stream)
+}
+
+/// Searches for files and folders matching the `pattern` within `dir_path` and at most `max_depth` folders
+/// deep (and visible to the `user`), using the index if there is one. Returns the paths of (at most `max_results`
+/// of) them relative to the `root_dir` and whether there were more.
+fn search(dir_path: &Path, root_dir: &Path, pattern: &NamePattern, max_depth: usize, max_results: usize, user: &UserAccount) -> (Vec<String>, bool) {
+    let mut results: Vec<String> = Vec::new();
+    let mut truncated: bool = false;
+    let mut add_if_matching = |relative_path: &str, name: &str| -> bool { // Returns whether to continue.
+        if pattern.matches(name) {
+            if results.len() == max_results {
+                truncated = true;
+                return false;
+            }
+            results.push(relative_path.to_string());
+        }
+        true
+    };
+
+    let relative_dir_path: String = relative_path(dir_path, root_dir);
+    if let Some(index) = INDEX.read().unwrap().as_ref() {
+        let prefix: String = if relative_dir_path.is_empty() { String::new() } else { relative_dir_path + "/" };
+        for path in index.range(prefix.clone()..).take_while(|path| path.starts_with(&prefix)) {
+            let inner_path: &str = &path[prefix.len()..];
+            if inner_path.is_empty() || inner_path.matches('/').count() >= max_depth {
+                continue;
+            }
+            let name: &str = inner_path.rsplit('/').next().unwrap();
+            // The index contains hidden files, too (whether they are hidden depends on the user):
+            if !pattern.matches(name) || hidden::is_hidden(&root_dir.join(path), root_dir, user)
+                || settings::is_in_locked_folder(&root_dir.join(path), dir_path, user) {
+                continue;
+            }
+            if !add_if_matching(path, name) {
+                break;
+            }
+        }
+    } else {
+        walk(dir_path, root_dir, max_depth, Some(user), &mut |path, name| add_if_matching(&relative_path(path, root_dir), name));
+    }
+    return (results, truncated);
+}
+
+/// Visits all files and folders within `dir_path` (at most `max_depth` folders deep), the shallower ones
+/// first, calling `visit` with their path and name until it returns `false`. Symbolic links to folders
+/// are not followed (they could form loops) and the folders used internally are skipped, just like
+/// everything hidden from the `user` and the protected folders they need the password of if a `user` is
+/// given (cf. hidden.rs and settings.rs).
+pub fn walk(dir_path: &Path, root_dir: &Path, max_depth: usize, user: Option<&UserAccount>, visit: &mut dyn FnMut(&Path, &str) -> bool) {
+    let mut folders: VecDeque<(PathBuf, usize)> = VecDeque::from([(dir_path.to_path_buf(), 0)]);
+    while let Some((folder, depth)) = folders.pop_front() {
+        if depth >= max_depth {
+            continue;
+        }
+        let entries = match fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(_) => continue, // e.g. missing permissions
+        };
+        let hidden_items: Option<hidden::FolderRules> = user.map(|user| hidden::FolderRules::of(&folder, root_dir, user));
+        for entry in entries.flatten() {
+            let path: PathBuf = entry.path();
+            let is_dir: bool = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            let hidden: bool = match (user, &hidden_items) {
+                (Some(user), Some(hidden_items)) => hidden_items.hides(&path, is_dir) || (is_dir && settings::is_locked(&path, user)),
+                _ => is_internal_path(&path, root_dir),
+            };
+            if hidden {
+                continue;
+            }
+            if !visit(&path, &entry.file_name().to_string_lossy()) {
+                return;
+            }
+            if is_dir {
+                folders.push_back((path, depth + 1));
+            }
+        }
+    }
+}
+
+/// The path of `path` relative to the `root_dir`, its components separated by '/'.
+pub fn relative_path(path: &Path, root_dir: &Path) -> String {
+    path.strip_prefix(root_dir).unwrap_or(path).iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Starts watching the `root_dir` (recursively) for an index (named by its `purpose`, e.g. "search index"),
+/// shared by `start_index` and `fulltext::start_index`. Returns the `root_dir` as an absolute path (as the
+/// watcher reports absolute paths), the watcher (which stops when it's dropped) and the receiver of its
+/// events, `None` after logging the error if the folder can't be watched.
+pub fn watch(root_dir: PathBuf, purpose: &str) -> Option<(PathBuf, RecommendedWatcher, Receiver<notify::Result<Event>>)> {
+    let root_dir: PathBuf = fs::canonicalize(&root_dir).unwrap_or(root_dir);
+    let (sender, receiver) = mpsc::channel();
+    let watcher = notify::recommended_watcher(sender)
+        .and_then(|mut watcher| watcher.watch(&root_dir, RecursiveMode::Recursive).map(|_| watcher));
+    match watcher {
+        Ok(watcher) => return Some((root_dir, watcher, receiver)),
+        Err(err) => {
+            eprintln!("{}", Red.paint(format!("[{}] Error: Could not watch the shared folder for the {}: {}", date_time_str(), purpose, err)));
+            return None;
+        }
+    }
+}
+
+/// Builds the index of all files and folders in the `root_dir` in the background and keeps it up to date
+/// using a file system watcher (`--search-index`).
+pub fn start_index(root_dir: PathBuf) {
+    thread::spawn(move || {
+        // Watch before building the index, so that no changes made in the meantime are missed:
+        let Some((root_dir, _watcher, receiver)) = watch(root_dir, "search index") else { return };
+
+        let mut index: BTreeSet<String> = BTreeSet::new();
+        walk(&root_dir, &root_dir, usize::MAX, None, &mut |path, _| { index.insert(relative_path(path, &root_dir)); true });
+        println!("[{}] Search index built ({} files and folders).", date_time_str(), index.len());
+        *INDEX.write().unwrap() = Some(index);
+
+        for event in receiver {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    eprintln!("{}", Red.paint(format!("[{}] Error while watching the shared folder for the search index: {}", date_time_str(), err)));
+                    continue;
+                }
+            };
+            let added_or_renamed: bool = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)));
+            if !added_or_renamed && !matches!(event.kind, EventKind::Remove(_)) {
+                continue; // Changed contents don't matter.
+            }
+            let mut index = INDEX.write().unwrap();
+            let index = index.as_mut().unwrap();
+            for path in event.paths.iter().filter(|path| !is_internal_path(path, &root_dir)) {
+                let relative: String = relative_path(path, &root_dir);
+                if relative.is_empty() {
+                    continue;
+                }
+                // Renames are reported as removals and additions depending on the platform, so it's checked
+                // what actually exists now:
+                if fs::symlink_metadata(path).is_ok() {
+                    if added_or_renamed {
+                        index.insert(relative);
+                        walk(path, &root_dir, usize::MAX, None, &mut |path, _| { index.insert(relative_path(path, &root_dir)); true });
+                    }
+                } else {
+                    let subtree_prefix: String = relative.clone() + "/";
+                    index.remove(&relative);
+                    let subtree: Vec<String> = index.range(subtree_prefix.clone()..)
+                        .take_while(|inner_path| inner_path.starts_with(&subtree_prefix))
+                        .cloned()
+                        .collect();
+                    for inner_path in subtree {
+                        index.remove(&inner_path);
+                    }
+                }
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the `name` matches the search `term` of the given kind.
+    fn matches(term: &str, kind: &str, name: &str) -> bool {
+        NamePattern::parse(term, kind).unwrap().matches(name)
+    }
+
+    #[test]
+    fn globs() {
+        assert_eq!(glob_to_regex("*.jpg"), "^.*\\.jpg$");
+        assert_eq!(glob_to_regex("IMG_????"), "^IMG_....$");
+        assert!(matches("*.JPG", "glob", "photo.jpg"));
+        assert!(!matches("*.jpg", "glob", "photo.jpg.txt"));
+        assert!(matches("IMG_????.*", "glob", "IMG_1234.png"));
+        assert!(!matches("IMG_????.*", "glob", "IMG_12.png"));
+        assert!(matches("a+b (1)[x].txt", "glob", "A+B (1)[X].txt")); // (special characters of regexes are literals)
+        assert!(!matches("a+b", "glob", "aab"));
+    }
+
+    #[test]
+    fn substrings_and_regexes() {
+        assert!(matches("Holiday", "substring", "2023 holiday photos"));
+        assert!(!matches("holidays", "substring", "holiday"));
+        assert!(matches("^ep\\d+\\.mkv$", "regex", "EP12.mkv"));
+        assert!(!matches("^ep\\d+\\.mkv$", "regex", "ep12.mkv.part"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(NamePattern::parse("(", "regex").is_err());
+        assert!(NamePattern::parse("x", "fuzzy").is_err());
+        assert!(NamePattern::parse("(", "glob").is_ok());
+    }
+}
@@ -0,0 +1,236 @@
+//! Per-folder settings, given in a `.httpshare` file (cf. `SETTINGS_FILE_NAME`) in the folder, e.g.:
+//!
+//! ```text
+//! # The Photos folder:
+//! title = Holiday Photos
+//! description = All photos of our trips, newest first.
+//! view = grid
+//! sort = mtime
+//! order = desc
+//! read_only = true
+//! ```
+//!
+//! * `view`, `sort`, `order` and `dirs` are the defaults for the URL GET parameters of the same names
+//!   (cf. `format_body` and sort.rs), i.e. they are used unless the URL says otherwise,
+//! * `title` and `description` are shown above the listing,
+//! * `read_only = true` disallows uploading and managing files, `uploads = false` only disallows uploading
+//!   (neither allows anything the server or the user isn't allowed otherwise),
+//! * `password` protects the folder: it has to be given (with any username) using HTTP Basic Authentication,
+//!   except by users with the 'hidden' permission. Protected folders are left out of the searches and
+//!   archive downloads of the folders above them. Visitors who log in with nothing but the password may only
+//!   download, unless the folder says `uploads = true`.
+//!
+//! All settings but `title` and `description` apply to the subfolders as well, unless they set them
+//! differently. The settings files are hidden from all users but those with the 'hidden' permission.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use lazy_static::lazy_static;
+use crate::config::UserAccount;
+
+/// The name of the files containing the settings of the folder they are in.
+pub const SETTINGS_FILE_NAME: &str = ".httpshare";
+
+/// The URL GET parameters that can be given defaults, cf. the module documentation.
+const QUERY_DEFAULTS: [&str; 4] = ["view", "sort", "order", "dirs"];
+
+lazy_static! {
+    /// The parsed settings files by their path, with their modification time when they were parsed.
+    static ref SETTINGS_FILES: Mutex<HashMap<PathBuf, (Option<SystemTime>, FolderSettings)>> = Mutex::new(HashMap::new());
+}
+
+/// The settings of a folder, cf. the module documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FolderSettings {
+    /// The defaults of URL GET parameters (`QUERY_DEFAULTS`) and their values.
+    pub query_defaults: Vec<(String, String)>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub read_only: Option<bool>,
+    pub uploads: Option<bool>,
+    pub password: Option<String>,
+}
+
+impl FolderSettings {
+    /// Parses the contents of a settings file: "key = value" lines, empty lines and comments (starting
+    /// with a '#') are ignored, just like unknown keys and invalid values.
+    fn parse(contents: &str) -> Self {
+        let mut settings = Self::default();
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let (key, value): (&str, &str) = (key.trim(), value.trim());
+            let flag: Option<bool> = match value {
+                "true" | "yes" | "on" => Some(true),
+                "false" | "no" | "off" => Some(false),
+                _ => None,
+            };
+            match key {
+                key if QUERY_DEFAULTS.contains(&key) && !value.is_empty() && !value.contains(['&', '=', '#', ' ']) => {
+                    settings.query_defaults.retain(|(name, _)| name != key);
+                    settings.query_defaults.push((key.to_string(), value.to_string()));
+                },
+                "title" => settings.title = Some(value.to_string()),
+                "description" => settings.description = Some(value.to_string()),
+                "read_only" => settings.read_only = flag,
+                "uploads" => settings.uploads = flag,
+                "password" if !value.is_empty() => settings.password = Some(value.to_string()),
+                _ => {},
+            }
+        }
+        return settings;
+    }
+
+    /// The settings of the folder `dir_path` (within the `root_dir`), including those inherited from the
+    /// folders it is in.
+    pub fn of_folder(dir_path: &Path, root_dir: &Path) -> Self {
+        let mut settings = Self::default();
+        let Ok(relative_path) = dir_path.strip_prefix(root_dir) else { return settings };
+        let mut folder: PathBuf = root_dir.to_path_buf();
+        let mut folders: Vec<PathBuf> = vec![folder.clone()];
+        for name in relative_path.iter() {
+            folder.push(name);
+            folders.push(folder.clone());
+        }
+        for folder in folders {
+            let own: FolderSettings = Self::own(&folder);
+            for (name, value) in own.query_defaults {
+                settings.query_defaults.retain(|(other_name, _)| *other_name != name);
+                settings.query_defaults.push((name, value));
+            }
+            settings.read_only = own.read_only.or(settings.read_only);
+            settings.uploads = own.uploads.or(settings.uploads);
+            settings.password = own.password.or(settings.password);
+            // Only the folder itself has the title and description:
+            settings.title = own.title;
+            settings.description = own.description;
+        }
+        return settings;
+    }
+
+    /// The settings of the folder `fs_path` or, if it is a file, of the folder it is in (cf. `of_folder`).
+    pub fn of_path(fs_path: &Path, root_dir: &Path) -> Self {
+        if fs_path.is_dir() {
+            Self::of_folder(fs_path, root_dir)
+        } else {
+            Self::of_folder(fs_path.parent().unwrap_or(root_dir), root_dir)
+        }
+    }
+
+    /// The settings given in the settings file of the folder `dir_path` itself (none if there is none).
+    /// Settings files are only read again when they changed.
+    fn own(dir_path: &Path) -> Self {
+        let settings_file: PathBuf = dir_path.join(SETTINGS_FILE_NAME);
+        let mut settings_files = SETTINGS_FILES.lock().unwrap();
+        let modified: Option<SystemTime> = match fs::metadata(&settings_file) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(_) => {
+                settings_files.remove(&settings_file);
+                return Self::default();
+            }
+        };
+        if let Some((parsed_modified, settings)) = settings_files.get(&settings_file) {
+            if *parsed_modified == modified && modified.is_some() {
+                return settings.clone();
+            }
+        }
+        let settings: FolderSettings = Self::parse(&fs::read_to_string(&settings_file).unwrap_or_default());
+        settings_files.insert(settings_file, (modified, settings.clone()));
+        return settings;
+    }
+
+    /// The `query_string` with the defaults for the URL GET parameters it doesn't contain added.
+    pub fn merge_query(&self, query_string: Option<&str>) -> Option<String> {
+        let defaults: Vec<String> = self.query_defaults.iter()
+            .filter(|(name, _)| crate::query_param(query_string, name).is_none())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        match (query_string, defaults.is_empty()) {
+            (query_string, true) => query_string.map(|query_str| query_str.to_string()),
+            (None, false) => Some(defaults.join("&")),
+            (Some(query_str), false) => Some(format!("{}&{}", defaults.join("&"), query_str)),
+        }
+    }
+
+    /// The user logging in with nothing but the `password` of the folder: read-only unless `uploads = true`.
+    pub fn guest(&self) -> UserAccount {
+        let mut guest: UserAccount = UserAccount::anonymous();
+        guest.permissions.upload = self.uploads == Some(true);
+        return guest;
+    }
+
+    /// The `user` with the permissions these settings take away (`read_only` and `uploads`).
+    pub fn restrict(&self, mut user: UserAccount) -> UserAccount {
+        if self.read_only == Some(true) {
+            user.permissions.upload = false;
+            user.permissions.manage = false;
+        }
+        if self.uploads == Some(false) {
+            user.permissions.upload = false;
+        }
+        return user;
+    }
+}
+
+/// Whether the folder `dir_path` is protected by a password of its own that the `user` needs, i.e. is
+/// left out of searches and archive downloads of the folders above it.
+pub fn is_locked(dir_path: &Path, user: &UserAccount) -> bool {
+    !user.can_see_hidden() && FolderSettings::own(dir_path).password.is_some()
+}
+
+/// Whether the file or folder at `fs_path` is in (or is) a protected folder below the folder `dir_path`
+/// that the `user` needs the password of (cf. `is_locked`), e.g. when searching within `dir_path`.
+pub fn is_in_locked_folder(fs_path: &Path, dir_path: &Path, user: &UserAccount) -> bool {
+    fs_path.ancestors()
+        .take_while(|folder| *folder != dir_path && folder.starts_with(dir_path))
+        .any(|folder| folder.is_dir() && is_locked(folder, user))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let settings = FolderSettings::parse("\
+            # The Photos folder:\n\
+            title =  Holiday Photos \n\
+            description=All photos = memories\n\
+            \n\
+            view = grid\n\
+            sort = mtime\n\
+            view = table\n\
+            read_only = yes\n\
+            uploads = off\n\
+            password = open sesame\n");
+        assert_eq!(settings.title.as_deref(), Some("Holiday Photos"));
+        assert_eq!(settings.description.as_deref(), Some("All photos = memories"));
+        assert_eq!(settings.query_defaults, vec![("sort".to_string(), "mtime".to_string()), ("view".to_string(), "table".to_string())]);
+        assert_eq!(settings.read_only, Some(true));
+        assert_eq!(settings.uploads, Some(false));
+        assert_eq!(settings.password.as_deref(), Some("open sesame"));
+    }
+
+    #[test]
+    fn invalid_lines_are_ignored() {
+        let settings = FolderSettings::parse("\
+            no equals sign\n\
+            color = red\n\
+            view = grid&search=x\n\
+            order = \n\
+            read_only = maybe\n\
+            password =\n");
+        assert_eq!(settings, FolderSettings::default());
+        assert_eq!(FolderSettings::parse(""), FolderSettings::default());
+    }
+
+    #[test]
+    fn guests_are_read_only() {
+        assert!(!FolderSettings::default().guest().permissions.upload);
+        assert!(FolderSettings::parse("uploads = true").guest().permissions.upload);
+        assert!(!FolderSettings::parse("uploads = true").guest().permissions.manage);
+    }
+}
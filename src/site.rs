@@ -0,0 +1,96 @@
+//! Serving the shared folder as a website, like classic static web servers do:
+//! * with `--index-html`, a folder containing an `index.html` (or `index.htm`) is answered with it instead
+//!   of its listing, unless a query string is given (e.g. `/docs/?view=list`),
+//! * with `--static-site`, additionally, "clean URLs" are supported ("/about" is answered with "about.html")
+//!   and `404.html` is shown for missing files (if there is one),
+//! * `--not-found-page=PATH` gives the page shown for missing files (with the status 404 Not Found),
+//! * `--spa-fallback=PATH` gives the file missing paths without a file extension are answered with (with
+//!   the status 200 OK), so that the client-side routers of single-page apps can handle them.
+//!
+//! Hidden files (cf. hidden.rs) are never served this way either.
+
+use std::fs;
+use std::io::{self, Error};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use http_share::{HTTPRequest, HTTPResponse};
+use http_share::throttle::Throttle;
+use crate::config::{UserAccount, CONFIG};
+use crate::{file_response, hidden};
+
+/// The names of the files a folder is answered with (cf. `--index-html`), the preferred one first.
+const INDEX_FILE_NAMES: [&str; 2] = ["index.html", "index.htm"];
+
+/// The name of the page shown for missing files with `--static-site` (unless `--not-found-page` is given).
+const DEFAULT_NOT_FOUND_PAGE: &str = "404.html";
+
+/// What a request is answered with, cf. `resolve`.
+pub enum Resolved {
+    /// The file or folder at the path.
+    Path(PathBuf),
+    /// A redirect to the URL (e.g. from "/docs" to "/docs/", so that relative links in its index file work).
+    Redirect(String),
+}
+
+/// Resolves the request for `fs_path` (the requested `get_path` without the query string) on a website, cf. the
+/// module documentation: the path of a clean URL's HTML file or of a folder's index file, or just `fs_path`.
+pub fn resolve(http_request: &HTTPRequest, fs_path: &Path, get_path: &str, query_string: Option<&str>, root_dir: &Path, user: &UserAccount) -> Resolved {
+    if !matches!(http_request.get_method(), "GET" | "HEAD") {
+        return Resolved::Path(fs_path.to_path_buf());
+    }
+    if CONFIG.static_site && fs::symlink_metadata(fs_path).is_err() && !get_path.ends_with('/') {
+        let mut html_file = fs_path.as_os_str().to_owned();
+        html_file.push(".html");
+        let html_file = PathBuf::from(html_file);
+        if html_file.is_file() && !hidden::is_hidden(&html_file, root_dir, user) {
+            return Resolved::Path(html_file);
+        }
+    }
+    if (CONFIG.index_html_enabled || CONFIG.static_site) && query_string.is_none() && fs_path.is_dir() {
+        let index_file: Option<PathBuf> = INDEX_FILE_NAMES.iter()
+            .map(|name| fs_path.join(name))
+            .find(|path| path.is_file() && !hidden::is_hidden(path, root_dir, user));
+        if let Some(index_file) = index_file {
+            if !get_path.ends_with('/') {
+                return Resolved::Redirect(format!("{}/", get_path));
+            }
+            return Resolved::Path(index_file);
+        }
+    }
+    return Resolved::Path(fs_path.to_path_buf());
+}
+
+/// Responds to `stream` that the requested `fs_path` doesn't exist: with the SPA fallback (if given and the path
+/// has no file extension), the custom 404 page (if there is one) or just the default '404 Not Found'.
+pub fn not_found_response(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount, throttle: Throttle, stream: &mut TcpStream) -> io::Result<()> {
+    // Only the path relative to the `root_dir` is shown, so that the folder structure of the server isn't leaked:
+    let relative_path: String = fs_path.strip_prefix(root_dir).unwrap_or(fs_path).to_string_lossy().to_string();
+    let page = |path: &Path| -> Option<PathBuf> {
+        let path: PathBuf = root_dir.join(path);
+        Some(path).filter(|path| path.is_file() && !hidden::is_hidden(path, root_dir, user))
+    };
+
+    let has_extension: bool = fs_path.extension().is_some();
+    if let Some(fallback) = CONFIG.spa_fallback.as_deref().and_then(page).filter(|_| !has_extension) {
+        if http_request.get_method() == "GET" {
+            return file_response(http_request, &fallback, stream, None, throttle);
+        }
+    }
+    let not_found_page: Option<PathBuf> = match &CONFIG.not_found_page {
+        Some(not_found_page) => page(not_found_page),
+        None if CONFIG.static_site => page(Path::new(DEFAULT_NOT_FOUND_PAGE)),
+        None => None,
+    };
+    match not_found_page.map(|path| (fs::read(&path), path)) {
+        Some((Ok(content), path)) => {
+            HTTPResponse::builder()
+                .status(404)
+                .header("Content-Type", http_share::mime::mime_type(&path))
+                .bytes(content)
+                .compressed(http_request.get_header("Accept-Encoding"))
+                .send_to_tcp_stream(stream)?;
+        },
+        _ => HTTPResponse::new_404_not_found(&relative_path).send_to_tcp_stream(stream)?,
+    }
+    return Err(Error::other(format!("Could not find file {}", fs_path.display())));
+}
@@ -0,0 +1,191 @@
+//! Sorting of directory listings on the server, chosen using URL GET parameters:
+//! * `sort=name|size|mtime|ctime|type|rand` – what to sort by (default: name),
+//! * `order=asc|desc` – the direction (default: asc),
+//! * `dirs=first` – list folders before files.
+//!
+//! Names are compared in natural order ("ep2" before "ep10") and case-insensitively. For compatibility
+//! with older links, `sort=asc` and `sort=desc` sort by name.
+//!
+//! Sorting by size or time needs the metadata of every item of the folder (read once per item), not just
+//! of those on the requested page, so it's slower for huge folders. `dirs=first` doesn't, as directory
+//! entries tell whether they are folders.
+
+use std::cmp::Ordering;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use crate::{query_param, FolderItem, ItemDetails};
+
+/// What the items of a listing are sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Created,
+    /// The file extension (then the name), folders having none.
+    Type,
+    Random,
+}
+
+impl SortKey {
+    /// All sort keys, in the order they are offered on directory pages.
+    pub const ALL: [SortKey; 6] = [SortKey::Name, SortKey::Size, SortKey::Modified, SortKey::Created, SortKey::Type, SortKey::Random];
+
+    /// The value of the "sort=..." URL GET parameter for this key.
+    pub fn param(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Created => "ctime",
+            SortKey::Type => "type",
+            SortKey::Random => "rand",
+        }
+    }
+
+    /// The name of this key shown to the user.
+    pub fn label(self) -> &'static str {
+        match self {
+            SortKey::Name => "Name",
+            SortKey::Size => "Size",
+            SortKey::Modified => "Modified",
+            SortKey::Created => "Created",
+            SortKey::Type => "Type",
+            SortKey::Random => "Randomly",
+        }
+    }
+}
+
+/// How the items of a listing are sorted, cf. the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+    pub dirs_first: bool,
+}
+
+impl SortOrder {
+    /// The sort order chosen using the URL GET parameters in the `query_string`.
+    pub fn from_query(query_string: Option<&str>) -> Self {
+        let (key, descending): (SortKey, bool) = match query_param(query_string, "sort") {
+            Some("desc") => (SortKey::Name, true), // Older links.
+            Some(sort) => (SortKey::ALL.iter().copied().find(|key| key.param() == sort).unwrap_or(SortKey::Name), false),
+            None => (SortKey::Name, false),
+        };
+        let descending: bool = match query_param(query_string, "order") {
+            Some(order) => order == "desc",
+            None => descending,
+        };
+        Self { key, descending, dirs_first: query_param(query_string, "dirs") == Some("first") }
+    }
+
+    /// Sorts the `items` in this order. Their details are only read from the file system if necessary.
+    pub fn sort(&self, items: &mut Vec<FolderItem>) {
+        let needs_details: bool = [SortKey::Size, SortKey::Modified, SortKey::Created].contains(&self.key);
+        let mut keyed_items: Vec<(Option<ItemDetails>, FolderItem)> = items.drain(..)
+            .map(|item| (if needs_details { Some(item.details(false)) } else { None }, item))
+            .collect();
+
+        if self.key == SortKey::Random {
+            keyed_items.shuffle(&mut thread_rng());
+        } else {
+            keyed_items.sort_by(|(a_details, a), (b_details, b)| {
+                let ordering: Ordering = match self.key {
+                    SortKey::Size => compare_details(a_details, b_details, |details| details.size),
+                    SortKey::Modified => compare_details(a_details, b_details, |details| details.modified),
+                    SortKey::Created => compare_details(a_details, b_details, |details| details.created),
+                    SortKey::Type => extension(a).cmp(&extension(b)),
+                    SortKey::Name | SortKey::Random => Ordering::Equal,
+                }.then_with(|| natural_cmp(a.name(), b.name()));
+                if self.descending { ordering.reverse() } else { ordering }
+            });
+        }
+        if self.dirs_first { // A stable sort, i.e. the order within folders and files is kept:
+            keyed_items.sort_by_cached_key(|(details, item)| !details.as_ref().map_or_else(|| item.is_dir(), |details| details.is_dir));
+        }
+        items.extend(keyed_items.into_iter().map(|(_, item)| item));
+    }
+}
+
+/// Helper function for `SortOrder::sort`: compares the values `get` returns for the details of two items.
+/// Unknown values come first.
+fn compare_details<T: Ord>(a: &Option<ItemDetails>, b: &Option<ItemDetails>, get: impl Fn(&ItemDetails) -> Option<T>) -> Ordering {
+    a.as_ref().and_then(&get).cmp(&b.as_ref().and_then(&get))
+}
+
+/// Helper function for `SortOrder::sort`: the lowercase extension of a file, `None` for folders and files without one.
+fn extension(item: &FolderItem) -> Option<String> {
+    let name: &str = item.name();
+    name.rfind('.').filter(|&dot| dot > 0).map(|dot| name[dot + 1..].to_lowercase())
+}
+
+/// Compares two names in natural order, i.e. case-insensitively and numbers by their value, so that "ep2"
+/// comes before "ep10". Names that only differ in case (or leading zeros) are ordered by their bytes.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let a_number: String = take_digits(&mut a_chars);
+                let b_number: String = take_digits(&mut b_chars);
+                let (a_number, b_number) = (a_number.trim_start_matches('0'), b_number.trim_start_matches('0'));
+                // Without leading zeros, the longer number is the larger one:
+                let ordering = a_number.len().cmp(&b_number.len()).then_with(|| a_number.cmp(b_number));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (Some(a_char), Some(b_char)) => {
+                let ordering = a_char.to_lowercase().cmp(b_char.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            },
+        }
+    }
+}
+
+/// Helper function for `natural_cmp`: takes the digits at the front of `chars`.
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(digit);
+    }
+    return digits;
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec!["ep10", "Ep2", "ep1", "ep02", "a", "ep", "episode"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["a", "ep", "ep1", "Ep2", "ep02", "ep10", "episode"]);
+    }
+
+    #[test]
+    fn natural_order_of_numbers() {
+        assert_eq!(natural_cmp("file9.txt", "file10.txt"), Ordering::Less);
+        assert_eq!(natural_cmp("file.txt", "file1.txt"), Ordering::Less);
+        assert_eq!(natural_cmp("v1.10", "v1.9"), Ordering::Greater);
+        assert_eq!(natural_cmp("99999999999999999999999", "100000000000000000000000"), Ordering::Less); // (beyond u64)
+        assert_eq!(natural_cmp("a007", "a7"), Ordering::Less); // (only differing in leading zeros)
+    }
+
+    #[test]
+    fn natural_order_ignores_case() {
+        assert_eq!(natural_cmp("Banana", "apple"), Ordering::Greater);
+        assert_eq!(natural_cmp("Äpfel", "äpfel"), Ordering::Less); // (only differing in case)
+        assert_eq!(natural_cmp("same", "same"), Ordering::Equal);
+    }
+}
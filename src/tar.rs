@@ -0,0 +1,525 @@
+//! Writing tar archives on the fly, the counterpart of `zip` for Unix users: the archive can be piped
+//! straight into `tar x` and preserves file modes and modification times.
+//!
+//! The archive uses the POSIX "ustar" format, with "pax" extended headers for everything that doesn't
+//! fit into it (long or non-ASCII names, files of 8 GB and more).
+//! The format is described in https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html
+//!
+//! Existing tar archives can be read entry by entry using a `TarReader`, which also understands
+//! the long names written by GNU tar.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tar archives consist of blocks of this size.
+const BLOCK_SIZE: usize = 512;
+
+/// The largest size that fits into the (octal) size field of a ustar header.
+const MAX_USTAR_SIZE: u64 = 0o77777777777;
+
+/// The largest entry size accepted by `TarReader`, so that the contents and their padding can
+/// always be skipped by seeking (which takes an `i64`).
+const MAX_ENTRY_SIZE: u64 = i64::MAX as u64 - BLOCK_SIZE as u64;
+
+/// The type flags of the header blocks written by `TarWriter`.
+const TYPE_FILE: u8 = b'0';
+const TYPE_DIRECTORY: u8 = b'5';
+const TYPE_PAX_HEADER: u8 = b'x';
+
+/// The type flags of header blocks only read by `TarReader`.
+const TYPE_OLD_FILE: u8 = 0;
+const TYPE_PAX_GLOBAL_HEADER: u8 = b'g';
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+
+/// Writes a tar archive to a (non-seekable) writer, entry by entry.
+/// `finish` has to be called at the end to write the end-of-archive marker!
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Create a new `TarWriter` writing the archive to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Adds an (empty) directory called `name` (using '/' as the separator, without a trailing '/'),
+    /// `mode` being its Unix permissions, e.g. `0o755`.
+    pub fn add_directory(&mut self, name: &str, mode: u32, modified: SystemTime) -> io::Result<()> {
+        self.write_header(&format!("{}/", name), TYPE_DIRECTORY, mode, modified, 0)
+    }
+
+    /// Adds a file called `name` (using '/' as the separator), `mode` being its Unix permissions,
+    /// e.g. `0o644`. As the `size` has to be written before the contents, exactly `size` bytes are
+    /// archived: should `reader` return more, the rest is left out, should it return less, the file
+    /// is padded with zeros (just like GNU tar does for files that change while being archived).
+    pub fn add_file(&mut self, name: &str, mode: u32, modified: SystemTime, size: u64, reader: &mut dyn Read) -> io::Result<()> {
+        self.write_header(name, TYPE_FILE, mode, modified, size)?;
+        let bytes_copied: u64 = io::copy(&mut reader.take(size), &mut self.inner)?;
+        io::copy(&mut io::repeat(0).take(size - bytes_copied), &mut self.inner)?;
+        self.write_padding(size)
+    }
+
+    /// Helper function for `add_directory` and `add_file`: writes the header block(s) of an entry,
+    /// preceded by a pax extended header if necessary.
+    fn write_header(&mut self, name: &str, type_flag: u8, mode: u32, modified: SystemTime, size: u64) -> io::Result<()> {
+        let mut pax_records: String = String::new();
+        if name.len() > 100 || !name.is_ascii() {
+            pax_records += &pax_record("path", name);
+        }
+        if size > MAX_USTAR_SIZE {
+            pax_records += &pax_record("size", &size.to_string());
+        }
+        if !pax_records.is_empty() {
+            let pax_name: String = format!("PaxHeaders/{}", ascii_prefix(name, 89));
+            self.inner.write_all(&ustar_header(&pax_name, TYPE_PAX_HEADER, 0o644, modified, pax_records.len() as u64))?;
+            self.inner.write_all(pax_records.as_bytes())?;
+            self.write_padding(pax_records.len() as u64)?;
+        }
+        // (When the name is too long, the pax header contains the full name and a shortened one is used here.)
+        self.inner.write_all(&ustar_header(&ascii_prefix(name, 100), type_flag, mode, modified, size))
+    }
+
+    /// Fills up the last block of an entry whose contents are `size` bytes long with zeros.
+    fn write_padding(&mut self, size: u64) -> io::Result<()> {
+        let remainder = (size % BLOCK_SIZE as u64) as usize;
+        if remainder != 0 {
+            self.inner.write_all(&[0u8; BLOCK_SIZE][remainder..])?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the end-of-archive marker (two empty blocks), ending the archive.
+    /// Returns the `inner` writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; 2 * BLOCK_SIZE])?;
+        self.inner.flush()?;
+        return Ok(self.inner);
+    }
+}
+
+/// A file or directory in an existing tar archive, read by `TarReader::next_entry`.
+#[derive(Debug, Clone)]
+pub struct TarEntry {
+    /// The full name within the archive, using '/' as the separator (without a trailing '/').
+    pub name: String,
+    pub is_dir: bool,
+    /// The size of the contents in bytes (always 0 for directories).
+    pub size: u64,
+    /// The Unix permissions, e.g. `0o644`.
+    pub mode: u32,
+    pub modified: SystemTime,
+    /// The offset of the contents within the (uncompressed) archive.
+    pub data_offset: u64,
+}
+
+/// Reads an existing tar archive entry by entry, see `next_entry`.
+/// After an entry was returned, its contents can be read using the `Read` implementation.
+///
+/// Only files and directories are returned, all other entries (like symbolic links) are skipped.
+pub struct TarReader<R: Read> {
+    inner: R,
+    /// How many bytes were read (or skipped) from `inner` so far.
+    position: u64,
+    /// How many bytes of the contents of the current entry have not been read yet.
+    remaining_in_entry: u64,
+    /// How many bytes of padding follow the contents of the current entry.
+    padding_after_entry: u64,
+    /// How bytes are skipped: by reading them or, if possible, by seeking.
+    skip: fn(&mut R, u64) -> io::Result<()>,
+}
+
+impl<R: Read> TarReader<R> {
+    /// Create a new `TarReader` reading the (uncompressed) archive from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self { inner, position: 0, remaining_in_entry: 0, padding_after_entry: 0, skip: skip_by_reading::<R> }
+    }
+
+    /// Skips the rest of the current entry and returns the next one, or `None` at the end of the archive.
+    pub fn next_entry(&mut self) -> io::Result<Option<TarEntry>> {
+        let mut long_name: Option<String> = None; // from a pax or GNU long name header
+        let mut long_size: Option<u64> = None; // from a pax header
+        loop {
+            let rest_of_entry: u64 = self.remaining_in_entry.checked_add(self.padding_after_entry).ok_or_else(entry_too_large)?;
+            self.skip_bytes(rest_of_entry)?;
+            self.remaining_in_entry = 0;
+            self.padding_after_entry = 0;
+
+            let mut header = [0u8; BLOCK_SIZE];
+            match self.inner.read_exact(&mut header) {
+                Ok(()) => self.advance(BLOCK_SIZE as u64)?,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None), // (a missing end-of-archive marker is tolerated)
+                Err(err) => return Err(err),
+            }
+            if header.iter().all(|&byte| byte == 0) { // the end-of-archive marker
+                return Ok(None);
+            }
+            let checksum: u64 = header.iter().enumerate()
+                .map(|(index, &byte)| if (148..156).contains(&index) { b' ' as u64 } else { byte as u64 })
+                .sum();
+            if parse_number(&header[148..156]) != Some(checksum) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid tar archive: header checksum mismatch"));
+            }
+
+            let size: u64 = parse_number(&header[124..136]).unwrap_or(0);
+            if size > MAX_ENTRY_SIZE {
+                return Err(entry_too_large());
+            }
+            self.remaining_in_entry = size;
+            self.padding_after_entry = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+            match header[156] {
+                TYPE_PAX_HEADER | TYPE_GNU_LONG_NAME if size <= 1024 * 1024 => {
+                    let mut data: Vec<u8> = vec![0u8; size as usize];
+                    self.read_exact(&mut data)?;
+                    if header[156] == TYPE_GNU_LONG_NAME {
+                        long_name = Some(String::from_utf8_lossy(&data).trim_end_matches('\0').to_string());
+                    } else {
+                        for (key, value) in parse_pax_records(&data) {
+                            match key.as_str() {
+                                "path" => long_name = Some(value),
+                                "size" => long_size = value.parse().ok(),
+                                _ => {},
+                            }
+                        }
+                    }
+                },
+                type_flag @ (TYPE_FILE | TYPE_OLD_FILE | TYPE_DIRECTORY) => {
+                    let name: String = long_name.take().unwrap_or_else(|| {
+                        // The ustar name consists of the prefix and the name field:
+                        let name: String = null_terminated_string(&header[0..100]);
+                        match null_terminated_string(&header[345..500]) {
+                            prefix if !prefix.is_empty() && &header[257..262] == b"ustar" => format!("{}/{}", prefix, name),
+                            _ => name,
+                        }
+                    });
+                    let size: u64 = long_size.take().unwrap_or(size);
+                    if size > MAX_ENTRY_SIZE {
+                        return Err(entry_too_large());
+                    }
+                    self.remaining_in_entry = size;
+                    self.padding_after_entry = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+                    let is_dir: bool = type_flag == TYPE_DIRECTORY || name.ends_with('/');
+                    return Ok(Some(TarEntry {
+                        name: name.trim_start_matches("./").trim_end_matches('/').to_string(),
+                        is_dir,
+                        size: if is_dir { 0 } else { size },
+                        mode: parse_number(&header[100..108]).unwrap_or(0o644) as u32,
+                        modified: UNIX_EPOCH + Duration::from_secs(parse_number(&header[136..148]).unwrap_or(0)),
+                        data_offset: self.position,
+                    }));
+                },
+                TYPE_PAX_GLOBAL_HEADER => {},
+                _ => { // Any other kind of entry is skipped, forgetting about its extended headers:
+                    long_name = None;
+                    long_size = None;
+                },
+            }
+        }
+    }
+
+    /// Skips `count` bytes of `inner`.
+    fn skip_bytes(&mut self, count: u64) -> io::Result<()> {
+        (self.skip)(&mut self.inner, count)?;
+        return self.advance(count);
+    }
+
+    /// Adds `count` to `position`.
+    fn advance(&mut self, count: u64) -> io::Result<()> {
+        self.position = self.position.checked_add(count).ok_or_else(entry_too_large)?;
+        return Ok(());
+    }
+}
+
+impl<R: Read + Seek> TarReader<R> {
+    /// Create a new `TarReader` reading the (uncompressed) archive from `inner`, which skips the
+    /// contents of entries by seeking instead of reading them, i.e. it's much faster for files on disk.
+    pub fn new_seekable(inner: R) -> Self {
+        Self { skip: skip_by_seeking::<R>, ..Self::new(inner) }
+    }
+}
+
+impl<R: Read> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max_len: usize = buf.len().min(self.remaining_in_entry.min(usize::MAX as u64) as usize);
+        if max_len == 0 {
+            return Ok(0);
+        }
+        let bytes_read: usize = self.inner.read(&mut buf[..max_len])?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "tar archive ended in the middle of an entry"));
+        }
+        self.remaining_in_entry -= bytes_read as u64;
+        self.advance(bytes_read as u64)?;
+        return Ok(bytes_read);
+    }
+}
+
+/// Skips `count` bytes of `reader` by reading them, cf. `TarReader::new`.
+fn skip_by_reading<R: Read>(reader: &mut R, count: u64) -> io::Result<()> {
+    let skipped: u64 = io::copy(&mut reader.take(count), &mut io::sink())?;
+    if skipped < count {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "tar archive ended in the middle of an entry"));
+    }
+    return Ok(());
+}
+
+/// Skips `count` bytes of `reader` by seeking, cf. `TarReader::new_seekable`.
+fn skip_by_seeking<R: Read + Seek>(reader: &mut R, count: u64) -> io::Result<()> {
+    let count: i64 = i64::try_from(count).map_err(|_| entry_too_large())?;
+    reader.seek(SeekFrom::Current(count)).map(|_| ())
+}
+
+/// The error returned for entries whose size or offset doesn't fit, cf. `MAX_ENTRY_SIZE`.
+fn entry_too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid tar archive: entry too large")
+}
+
+/// Parses a numeric header field: an octal number terminated by a NUL byte or space, or a
+/// big-endian binary number if the highest bit of the first byte is set (base-256, cf. `ustar_header`).
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|&byte| byte & 0x80 != 0) {
+        return field[1..].iter().try_fold(0u64, |number, &byte| Some(number.checked_mul(256)? | byte as u64));
+    }
+    let octal: String = String::from_utf8_lossy(field).trim_matches(|c: char| c == '\0' || c == ' ').to_string();
+    return if octal.is_empty() { Some(0) } else { u64::from_str_radix(&octal, 8).ok() };
+}
+
+/// Returns the text in `field` up to the first NUL byte.
+fn null_terminated_string(field: &[u8]) -> String {
+    let end: usize = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// Parses the records of a pax extended header, cf. `pax_record`.
+fn parse_pax_records(data: &[u8]) -> Vec<(String, String)> {
+    let mut records: Vec<(String, String)> = Vec::new();
+    let mut rest: &[u8] = data;
+    while let Some(space) = rest.iter().position(|&byte| byte == b' ') {
+        let length: usize = match std::str::from_utf8(&rest[..space]).ok().and_then(|length| length.parse().ok()) {
+            Some(length) if length > space + 1 && length <= rest.len() => length,
+            _ => break,
+        };
+        let record: String = String::from_utf8_lossy(&rest[space + 1..length - 1]).to_string(); // (without the '\n')
+        if let Some((key, value)) = record.split_once('=') {
+            records.push((key.to_string(), value.to_string()));
+        }
+        rest = &rest[length..];
+    }
+    return records;
+}
+
+/// Returns a ustar header block for an entry called `name` (at most 100 bytes long).
+fn ustar_header(name: &str, type_flag: u8, mode: u32, modified: SystemTime, size: u64) -> [u8; BLOCK_SIZE] {
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut header[100..108], (mode & 0o7777) as u64); // mode
+    put_octal(&mut header[108..116], 0); // uid
+    put_octal(&mut header[116..124], 0); // gid
+    if size > MAX_USTAR_SIZE { // The size is in the pax header, but GNU tar also understands this base-256 encoding:
+        header[124] = 0x80;
+        header[124 + 4..136].copy_from_slice(&size.to_be_bytes());
+    } else {
+        put_octal(&mut header[124..136], size);
+    }
+    let mtime: u64 = modified.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    put_octal(&mut header[136..148], mtime);
+    header[156] = type_flag;
+    header[257..263].copy_from_slice(b"ustar\0"); // magic
+    header[263..265].copy_from_slice(b"00"); // version
+
+    // The checksum is calculated with the checksum field itself filled with spaces:
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+    put_octal(&mut header[148..155], checksum);
+    return header;
+}
+
+/// Writes `value` as a zero-padded octal number followed by a NUL byte into `field`, clamped to
+/// the largest number that fits (e.g. for modification times in the far future).
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits: usize = field.len() - 1;
+    let max_value: u64 = if digits >= 21 { u64::MAX } else { (1 << (3 * digits)) - 1 };
+    let octal: String = format!("{:0width$o}\0", value.min(max_value), width = digits);
+    field.copy_from_slice(octal.as_bytes());
+}
+
+/// Returns one record of a pax extended header: "<length> <key>=<value>\n", where the length
+/// is the length of the entire record, including the length itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let length_without_digits: usize = key.len() + value.len() + 3; // space, '=' and '\n'
+    let mut length: usize = length_without_digits + 1;
+    while length != length_without_digits + length.to_string().len() {
+        length = length_without_digits + length.to_string().len();
+    }
+    return format!("{} {}={}\n", length, key, value);
+}
+
+/// Returns the longest prefix of `name` consisting of at most `max_len` ASCII characters, with all
+/// non-ASCII characters replaced by '_'. Used for the ustar name field when the actual name is in a
+/// pax header.
+fn ascii_prefix(name: &str, max_len: usize) -> String {
+    name.chars().map(|c| if c.is_ascii() { c } else { '_' }).take(max_len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// The octal number in the header `field`.
+    fn octal(field: &[u8]) -> u64 {
+        u64::from_str_radix(std::str::from_utf8(field).unwrap().trim_end_matches('\0'), 8).unwrap()
+    }
+
+    /// The bytes of `field` up to the first NUL byte.
+    fn null_terminated(field: &[u8]) -> &[u8] {
+        &field[..field.iter().position(|&byte| byte == 0).unwrap_or(field.len())]
+    }
+
+    #[test]
+    fn header_layout() {
+        let modified: SystemTime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut writer = TarWriter::new(Vec::new());
+        writer.add_file("hello.txt", 0o644, modified, 6, &mut &b"hello\n"[..]).unwrap();
+        let archive: Vec<u8> = writer.finish().unwrap();
+        assert_eq!(archive.len(), 4 * BLOCK_SIZE); // (header, contents, end-of-archive marker)
+
+        let header: &[u8] = &archive[..BLOCK_SIZE];
+        assert_eq!(null_terminated(&header[..100]), b"hello.txt");
+        assert_eq!(&header[100..108], b"0000644\0");
+        assert_eq!(&header[124..136], b"00000000006\0");
+        assert_eq!(octal(&header[136..148]), 1_600_000_000);
+        assert_eq!(header[156], TYPE_FILE);
+        assert_eq!(&header[257..265], b"ustar\x0000");
+        // The checksum is the sum of all bytes, counting the checksum field as spaces:
+        let checksum: u64 = header.iter().enumerate().map(|(i, &byte)| if (148..156).contains(&i) { b' ' as u64 } else { byte as u64 }).sum();
+        assert_eq!(octal(&header[148..155]), checksum);
+
+        assert_eq!(&archive[BLOCK_SIZE..BLOCK_SIZE + 6], b"hello\n");
+        assert!(archive[BLOCK_SIZE + 6..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn long_names_get_a_pax_header() {
+        let name: String = format!("folder/{}.txt", "ä".repeat(80));
+        let mut writer = TarWriter::new(Vec::new());
+        writer.add_directory("folder", 0o755, UNIX_EPOCH).unwrap();
+        writer.add_file(&name, 0o644, UNIX_EPOCH, 0, &mut io::empty()).unwrap();
+        let archive: Vec<u8> = writer.finish().unwrap();
+
+        assert_eq!(null_terminated(&archive[..100]), b"folder/");
+        assert_eq!(archive[156], TYPE_DIRECTORY);
+        let pax_header: &[u8] = &archive[BLOCK_SIZE..2 * BLOCK_SIZE];
+        assert_eq!(pax_header[156], TYPE_PAX_HEADER);
+        let records: &[u8] = &archive[2 * BLOCK_SIZE..2 * BLOCK_SIZE + octal(&pax_header[124..136]) as usize];
+        assert_eq!(records, pax_record("path", &name).as_bytes());
+        let header: &[u8] = &archive[3 * BLOCK_SIZE..4 * BLOCK_SIZE];
+        assert_eq!(header[156], TYPE_FILE);
+        assert!(null_terminated(&header[..100]).is_ascii());
+    }
+
+    #[test]
+    fn files_are_archived_with_their_given_size() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.add_file("grown", 0o644, UNIX_EPOCH, 3, &mut &b"abcdef"[..]).unwrap();
+        writer.add_file("shrunk", 0o644, UNIX_EPOCH, 4, &mut &b"ab"[..]).unwrap();
+        let archive: Vec<u8> = writer.finish().unwrap();
+        assert_eq!(&archive[BLOCK_SIZE..BLOCK_SIZE + 4], b"abc\0");
+        assert_eq!(null_terminated(&archive[2 * BLOCK_SIZE..2 * BLOCK_SIZE + 100]), b"shrunk");
+        assert_eq!(&archive[3 * BLOCK_SIZE..3 * BLOCK_SIZE + 5], b"ab\0\0\0");
+    }
+
+    #[test]
+    fn octal_fields() {
+        let mut field = [0u8; 12];
+        put_octal(&mut field, 0o1234);
+        assert_eq!(&field, b"00000001234\0");
+        // Values that don't fit are clamped instead of overflowing the field:
+        put_octal(&mut field, u64::MAX);
+        assert_eq!(&field, b"77777777777\0");
+    }
+
+    #[test]
+    fn pax_record_lengths() {
+        assert_eq!(pax_record("path", "a/b.txt"), "16 path=a/b.txt\n");
+        // The length of a record includes its own digits, which may need one more digit:
+        assert_eq!(pax_record("k", &"v".repeat(93)).len(), 99);
+        assert_eq!(pax_record("k", &"v".repeat(94)).len(), 101);
+    }
+
+    /// Reads all entries of the `archive` with their contents.
+    fn entries<R: Read>(mut reader: TarReader<R>) -> io::Result<Vec<(TarEntry, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry()? {
+            let mut contents: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            entries.push((entry, contents));
+        }
+        return Ok(entries);
+    }
+
+    #[test]
+    fn round_trip() {
+        let modified: SystemTime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let long_name: String = format!("folder/{}.txt", "ä".repeat(80));
+        let mut writer = TarWriter::new(Vec::new());
+        writer.add_directory("folder", 0o755, modified).unwrap();
+        writer.add_file("folder/hello.txt", 0o644, modified, 6, &mut &b"hello\n"[..]).unwrap();
+        writer.add_file(&long_name, 0o600, modified, 3, &mut &b"abc"[..]).unwrap();
+        let archive: Vec<u8> = writer.finish().unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+
+        for entries in [entries(TarReader::new(&archive[..])).unwrap(), entries(TarReader::new_seekable(Cursor::new(&archive))).unwrap()] {
+            assert_eq!(entries.len(), 3);
+            assert_eq!(entries[0].0.name, "folder");
+            assert!(entries[0].0.is_dir);
+            assert_eq!(entries[0].0.mode, 0o755);
+            assert_eq!(entries[1].0.name, "folder/hello.txt");
+            assert_eq!(entries[1].0.modified, modified);
+            assert_eq!(entries[1].1, b"hello\n");
+            assert_eq!(&archive[entries[1].0.data_offset as usize..][..6], b"hello\n");
+            assert_eq!(entries[2].0.name, long_name); // (from the pax header)
+            assert_eq!(entries[2].0.mode, 0o600);
+            assert_eq!(entries[2].1, b"abc");
+        }
+    }
+
+
+    #[test]
+    fn cut_off_archive() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.add_file("file", 0o644, UNIX_EPOCH, 1000, &mut io::repeat(b'x')).unwrap();
+        let archive: Vec<u8> = writer.finish().unwrap();
+        let error = entries(TarReader::new(&archive[..BLOCK_SIZE + 100])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+
+    #[test]
+    fn parsed_numbers() {
+        assert_eq!(parse_number(b"00000001234\0"), Some(0o1234));
+        assert_eq!(parse_number(b"  644 \0\0"), Some(0o644));
+        assert_eq!(parse_number(b"\0\0\0\0"), Some(0));
+        assert_eq!(parse_number(b"12x\0"), None);
+        assert_eq!(parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x00]), Some(256)); // (base-256)
+    }
+
+    #[test]
+    fn parsed_pax_records() {
+        let records: String = pax_record("path", "a/b.txt") + &pax_record("size", &"9".repeat(20));
+        assert_eq!(parse_pax_records(records.as_bytes()), vec![
+            ("path".to_string(), "a/b.txt".to_string()),
+            ("size".to_string(), "9".repeat(20)),
+        ]);
+        assert!(parse_pax_records(b"99 path=cut off\n").is_empty());
+    }
+
+    #[test]
+    fn too_large_entries() {
+        let header: [u8; BLOCK_SIZE] = ustar_header("huge.bin", TYPE_FILE, 0o644, UNIX_EPOCH, u64::MAX);
+        let error: io::Error = TarReader::new(Cursor::new(header.to_vec())).next_entry().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(parse_number(&[0xff; 12]), None);
+    }
+}
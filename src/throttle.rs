@@ -0,0 +1,156 @@
+//! Bandwidth throttling of response bodies using token buckets.
+//!
+//! A `TokenBucket` limits the rate of everything sent using it, no matter how many responses share it,
+//! e.g. one bucket for the whole server and one per IP address. A `Throttle` combines the buckets that
+//! apply to one response; `HTTPResponse::throttled` attaches it to the response.
+//!
+//! Prioritized throttles (e.g. for range requests of video streams) are always served first: as long as
+//! a prioritized response is waiting for tokens of a bucket, the other responses using that bucket don't
+//! get any.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The minimum number of bytes a bucket can hold (its burst size), even for very low rates, so that
+/// data isn't sent in tiny pieces.
+const MIN_CAPACITY: u64 = 16 * 1024;
+
+/// The longest time waited for tokens at once, before checking again (e.g. whether a prioritized
+/// response is waiting now).
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// A token bucket: tokens (bytes) are added at a constant `rate` up to the `capacity` of the bucket and
+/// every byte sent takes one token out of it.
+pub struct TokenBucket {
+    /// The number of tokens (bytes) added per second.
+    rate: u64,
+    /// The maximum number of tokens in the bucket, i.e. how many bytes may be sent at once after a pause.
+    capacity: u64,
+    /// The current number of tokens and when they were last refilled.
+    state: Mutex<(f64, Instant)>,
+    /// The number of prioritized consumers currently waiting for tokens.
+    prioritized_waiting: AtomicUsize,
+}
+
+impl TokenBucket {
+    /// Create a new (full) bucket limiting the rate to `rate` bytes per second.
+    pub fn new(rate: u64) -> Self {
+        let rate: u64 = rate.max(1);
+        let capacity: u64 = (rate / 4).max(MIN_CAPACITY); // Bursts of a quarter of a second.
+        Self { rate, capacity, state: Mutex::new((capacity as f64, Instant::now())), prioritized_waiting: AtomicUsize::new(0) }
+    }
+
+    /// Takes up to `wanted` tokens out of the bucket, waiting until there are enough of them, and returns
+    /// how many were taken (at least 1 unless `wanted` is 0).
+    fn take(&self, wanted: u64, prioritized: bool) -> u64 {
+        if wanted == 0 {
+            return 0;
+        }
+        if prioritized {
+            self.prioritized_waiting.fetch_add(1, Ordering::SeqCst);
+        }
+        // Wait until a reasonable amount can be sent at once instead of sending every single refilled token:
+        let needed: u64 = wanted.min(self.capacity / 4).max(1);
+        let taken: u64 = loop {
+            if !prioritized && self.prioritized_waiting.load(Ordering::SeqCst) > 0 {
+                thread::sleep(Duration::from_millis(5));
+                continue;
+            }
+            let wait: Duration = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, last_refill) = &mut *state;
+                let now = Instant::now();
+                *tokens = (*tokens + now.duration_since(*last_refill).as_secs_f64() * self.rate as f64).min(self.capacity as f64);
+                *last_refill = now;
+                if *tokens >= needed as f64 {
+                    let taken: u64 = wanted.min(*tokens as u64);
+                    *tokens -= taken as f64;
+                    break taken;
+                }
+                Duration::from_secs_f64((needed as f64 - *tokens) / self.rate as f64)
+            };
+            thread::sleep(wait.min(MAX_WAIT));
+        };
+        if prioritized {
+            self.prioritized_waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+        return taken;
+    }
+
+    /// Puts `amount` tokens that were taken but not used back into the bucket.
+    fn give_back(&self, amount: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = (state.0 + amount as f64).min(self.capacity as f64);
+    }
+}
+
+/// The bandwidth limits applying to one response: a set of (possibly shared) token buckets, all of which
+/// have to allow sending a byte.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    buckets: Vec<Arc<TokenBucket>>,
+    prioritized: bool,
+}
+
+impl Throttle {
+    /// Create a throttle limited by all of the given `buckets`. Without any buckets it doesn't limit anything.
+    pub fn new(buckets: Vec<Arc<TokenBucket>>) -> Self {
+        Self { buckets, prioritized: false }
+    }
+
+    /// Whether responses using this throttle are served before the other (non-prioritized) ones using
+    /// the same buckets.
+    pub fn prioritized(mut self, prioritized: bool) -> Self {
+        self.prioritized = prioritized;
+        self
+    }
+
+    /// Whether this throttle doesn't limit anything.
+    pub fn is_unlimited(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Waits until at least one of the `wanted` bytes may be sent and returns how many of them may be
+    /// sent right now.
+    pub fn allow(&self, wanted: u64) -> u64 {
+        let mut allowed: u64 = wanted;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            let taken: u64 = bucket.take(allowed, self.prioritized);
+            if taken < allowed { // Less is allowed than the previous buckets gave, they get the rest back:
+                for previous_bucket in &self.buckets[..index] {
+                    previous_bucket.give_back(allowed - taken);
+                }
+            }
+            allowed = taken;
+        }
+        return allowed;
+    }
+}
+
+/// A writer passing everything written to it on to the `inner` writer, only as fast as the `throttle` allows.
+pub struct ThrottledWriter<W: Write> {
+    inner: W,
+    throttle: Throttle,
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    /// Create a new `ThrottledWriter` writing to `inner`.
+    pub fn new(inner: W, throttle: Throttle) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let allowed: usize = self.throttle.allow(buf.len() as u64) as usize;
+        self.inner.write_all(&buf[..allowed])?;
+        return Ok(allowed);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,82 @@
+//! Thumbnails of images ("thumbnail" URL GET parameter, e.g. `/photo.jpg?thumbnail=256`), so that Grid View
+//! doesn't have to load every image at full resolution.
+//!
+//! JPEG, PNG, GIF (the first frame), WebP and BMP images are decoded, turned according to their EXIF
+//! orientation and scaled down (in pure Rust, no external programs needed). Only the sizes given using
+//! `--thumbnail-sizes` are generated (so that not every size anyone asks for has to be), the smallest one at
+//! least as large as requested is used. Thumbnails of videos are generated by ffmpeg (cf. `file_response`)
+//! and scaled down the same way.
+
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use image::codecs::jpeg::JpegEncoder;
+use crate::config::CONFIG;
+
+/// The size (in pixels, of the longer side) of thumbnails requested without a size ("thumbnail").
+const DEFAULT_SIZE: u32 = 512;
+
+/// The quality of JPEG thumbnails (1 to 100).
+const JPEG_QUALITY: u8 = 80;
+
+/// The file extensions of the images thumbnails are generated of.
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+/// A generated thumbnail.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    /// "image/jpeg", or "image/png" for images with transparency.
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Whether thumbnails can be generated of the file at `path`, judging by its file extension.
+pub fn is_image(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// The size of the thumbnail requested in the `query_string` ("thumbnail" or "thumbnail=SIZE"), if one is
+/// requested: the smallest of the `--thumbnail-sizes` at least as large as the requested one (or the largest).
+pub fn requested_size(query_string: Option<&str>) -> Option<u32> {
+    let param: &str = query_string?.split('&').find(|param| *param == "thumbnail" || param.starts_with("thumbnail="))?;
+    let requested: u32 = param.strip_prefix("thumbnail=").and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_SIZE);
+    let sizes: &[u32] = &CONFIG.thumbnail_sizes;
+    return sizes.iter().copied().filter(|&size| size >= requested).min()
+        .or_else(|| sizes.iter().copied().max());
+}
+
+/// Generates the thumbnail of the image at `path`, at most `size` pixels wide and high.
+pub fn generate(path: &Path, size: u32) -> Result<Thumbnail, String> {
+    let reader = ImageReader::new(BufReader::new(File::open(path).map_err(|err| err.to_string())?))
+        .with_guessed_format()
+        .map_err(|err| err.to_string())?;
+    let mut decoder = reader.into_decoder().map_err(|err| err.to_string())?;
+    let orientation = decoder.orientation().map_err(|err| err.to_string())?;
+    let mut image: DynamicImage = DynamicImage::from_decoder(decoder).map_err(|err| err.to_string())?;
+    image.apply_orientation(orientation);
+    return encode(&image, size);
+}
+
+/// Scales the (already generated, e.g. by ffmpeg) JPEG thumbnail `jpeg` down to at most `size` pixels wide and high.
+pub fn resize(jpeg: &[u8], size: u32) -> Result<Thumbnail, String> {
+    let image: DynamicImage = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg).map_err(|err| err.to_string())?;
+    return encode(&image, size);
+}
+
+/// Helper function for `generate` and `resize`: scales the `image` down to at most `size` pixels wide and high
+/// (smaller images are kept as they are) and encodes it as a JPEG (or a PNG if it has transparency).
+fn encode(image: &DynamicImage, size: u32) -> Result<Thumbnail, String> {
+    let thumbnail: DynamicImage = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image.clone()
+    };
+    let mut data: Vec<u8> = Vec::new();
+    if thumbnail.color().has_alpha() {
+        thumbnail.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).map_err(|err| err.to_string())?;
+        return Ok(Thumbnail { content_type: "image/png", data });
+    }
+    JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode_image(&thumbnail.to_rgb8()).map_err(|err| err.to_string())?;
+    return Ok(Thumbnail { content_type: "image/jpeg", data });
+}
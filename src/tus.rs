@@ -0,0 +1,348 @@
+//! Resumable uploads using the tus protocol (https://tus.io/protocols/resumable-upload), so that
+//! uploads of large files over flaky connections can continue where they stopped instead of
+//! starting over.
+//!
+//! Supported are the core protocol and the 'creation', 'expiration' and 'termination' extensions:
+//! * `OPTIONS` on any folder tells the client which tus version and extensions are supported.
+//! * `POST` on a folder (with an 'Upload-Length' header) creates a new upload into that folder.
+//! * `HEAD` on an upload returns how many bytes were already received ('Upload-Offset').
+//! * `PATCH` on an upload appends more bytes at the given 'Upload-Offset'.
+//! * `DELETE` on an upload cancels it.
+//!
+//! Partial uploads are kept in the hidden `STAGING_DIR_NAME` folder in the root directory until they
+//! are complete, then they are moved into their destination folder.
+
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Error, Read};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Utc};
+use http_share::{HTTPRequest, HTTPResponse, HTTPResponseBuilder};
+use lazy_static::lazy_static;
+use rand::Rng;
+use crate::config::{UserAccount, CONFIG};
+use crate::date_time_str;
+use crate::upload::{resolve_collision, sanitize_filename};
+
+/// The name of the hidden folder (in the root directory) in which partial uploads are stored.
+/// It's neither listed nor served.
+pub const STAGING_DIR_NAME: &str = ".http_share_uploads";
+
+/// The (only) version of the tus protocol that is supported.
+const TUS_VERSION: &str = "1.0.0";
+
+lazy_static! {
+    /// The IDs of the uploads that are currently receiving data, so that no two `PATCH` requests
+    /// append to the same upload at the same time.
+    static ref UPLOADS_IN_PROGRESS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// The state of an upload, stored next to the partial file as "<id>.info".
+struct UploadInfo {
+    /// The total size of the file in bytes.
+    length: u64,
+    /// The (sanitized) name of the file.
+    filename: String,
+    /// The destination folder, relative to the root directory.
+    directory: PathBuf,
+    /// When the upload was created, in seconds since the UNIX epoch.
+    created: u64,
+}
+
+impl UploadInfo {
+    fn read(info_path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(info_path)?;
+        let get = |key: &str| content.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .ok_or_else(|| Error::new(io::ErrorKind::InvalidData, format!("upload info is missing '{}'", key)));
+        let parse_u64 = |value: &str| value.parse::<u64>().map_err(|err| Error::new(io::ErrorKind::InvalidData, err));
+        return Ok(Self {
+            length: parse_u64(get("length")?)?,
+            filename: get("filename")?.to_string(),
+            directory: PathBuf::from(get("directory")?),
+            created: parse_u64(get("created")?)?,
+        });
+    }
+
+    fn write(&self, info_path: &Path) -> io::Result<()> {
+        fs::write(info_path, format!("length={}\nfilename={}\ndirectory={}\ncreated={}\n",
+                                     self.length, self.filename, self.directory.display(), self.created))
+    }
+
+    /// When this upload expires if it isn't completed until then.
+    fn expires(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created) + CONFIG.upload_expiration
+    }
+}
+
+/// Handles an `OPTIONS` request, telling the client about the tus (and WebDAV) support of this server.
+pub fn options_response(stream: &mut TcpStream) -> io::Result<()> {
+    let mut response = crate::webdav::add_options_headers(HTTPResponse::builder())
+        .status(204)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", "creation,expiration,termination");
+    if let Some(max_upload_size) = CONFIG.max_upload_size {
+        response = response.header("Tus-Max-Size", max_upload_size);
+    }
+    response.empty().send_to_tcp_stream(stream)
+}
+
+/// Handles a `POST` request creating a new upload into the folder `dir_path`.
+pub fn creation_response(http_request: &HTTPRequest, dir_path: &Path, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    if let Err(err) = check_request(http_request, root_dir, dir_path, user) {
+        return send_error(stream, err);
+    }
+    let length: u64 = match http_request.get_header("Upload-Length").and_then(|length| length.parse().ok()) {
+        Some(length) => length,
+        None => return send_error(stream, (400, "missing or invalid Upload-Length header".to_string())),
+    };
+    if let Some(max_upload_size) = CONFIG.max_upload_size.filter(|&max| length > max) {
+        return send_error(stream, (413, format!("uploads may not be larger than {} bytes", max_upload_size)));
+    }
+    let filename: String = match parse_metadata(http_request.get_header("Upload-Metadata").unwrap_or(""))
+        .into_iter()
+        .find(|(key, _)| key == "filename" || key == "name")
+        .and_then(|(_, filename)| sanitize_filename(&filename))
+    {
+        Some(filename) => filename,
+        None => return send_error(stream, (400, "Upload-Metadata has to contain a valid filename".to_string())),
+    };
+
+    let staging_dir: PathBuf = root_dir.join(STAGING_DIR_NAME);
+    fs::create_dir_all(&staging_dir)?;
+    let id: String = format!("{:032x}", rand::thread_rng().gen::<u128>());
+    let info = UploadInfo {
+        length,
+        filename,
+        directory: dir_path.strip_prefix(root_dir).unwrap().to_path_buf(),
+        created: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs()),
+    };
+    fs::File::create(staging_dir.join(&id))?;
+    info.write(&staging_dir.join(format!("{}.info", id)))?;
+    println!("[{}] {} started a resumable upload of {} ({} bytes) to /{}",
+             date_time_str(), peer_addr(stream), info.filename, info.length, info.directory.display());
+    if length == 0 {
+        if let Err(err) = complete_upload(&id, &info, root_dir, stream) {
+            return send_error(stream, completion_error(err));
+        }
+    }
+
+    tus_response(201)
+        .header("Location", format!("/{}/{}", STAGING_DIR_NAME, id))
+        .header("Upload-Expires", http_date(info.expires()))
+        .empty()
+        .send_to_tcp_stream(stream)
+}
+
+/// Handles a request to the upload called `id`, i.e. a `HEAD`, `PATCH` or `DELETE` request to
+/// "/.http_share_uploads/<id>".
+pub fn upload_response(http_request: &HTTPRequest, id: &str, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    let staging_dir: PathBuf = root_dir.join(STAGING_DIR_NAME);
+    // Only ever touch files in the staging folder, the ID must not be a path:
+    let info: Option<UploadInfo> = Some(id)
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|id| UploadInfo::read(&staging_dir.join(format!("{}.info", id))).ok())
+        .filter(|info| info.expires() > SystemTime::now());
+    let info: UploadInfo = match info {
+        Some(info) => info,
+        None => return send_error(stream, (404, format!("no such upload: {}", id))),
+    };
+    if let Err(err) = check_request(http_request, root_dir, &root_dir.join(&info.directory), user) {
+        return send_error(stream, err);
+    }
+    let data_path: PathBuf = staging_dir.join(id);
+    let offset: u64 = fs::metadata(&data_path)?.len();
+
+    match http_request.get_method() {
+        "HEAD" => tus_response(200)
+            .header("Upload-Offset", offset)
+            .header("Upload-Length", info.length)
+            .header("Upload-Expires", http_date(info.expires()))
+            .header("Cache-Control", "no-store")
+            .empty()
+            .send_to_tcp_stream(stream),
+        "PATCH" => {
+            if http_request.get_header("Content-Type") != Some("application/offset+octet-stream") {
+                return send_error(stream, (415, "Content-Type has to be application/offset+octet-stream".to_string()));
+            }
+            if http_request.get_header("Upload-Offset").and_then(|offset| offset.parse::<u64>().ok()) != Some(offset) {
+                return send_error(stream, (409, format!("Upload-Offset does not match the current offset {}", offset)));
+            }
+            let content_length: u64 = http_request.get_content_length().unwrap_or(0);
+            if offset.checked_add(content_length).is_none_or(|end| end > info.length) {
+                return send_error(stream, (400, "the upload would be longer than its Upload-Length".to_string()));
+            }
+            if !UPLOADS_IN_PROGRESS.lock().unwrap().insert(id.to_string()) {
+                return send_error(stream, (423, "another request is currently appending to this upload".to_string()));
+            }
+            // Another request might have appended to the upload since the offset was checked above:
+            let current_offset: u64 = match fs::metadata(&data_path) {
+                Ok(metadata) => metadata.len(),
+                Err(err) => {
+                    UPLOADS_IN_PROGRESS.lock().unwrap().remove(id);
+                    return Err(err);
+                }
+            };
+            if current_offset != offset {
+                UPLOADS_IN_PROGRESS.lock().unwrap().remove(id);
+                return send_error(stream, (409, format!("Upload-Offset does not match the current offset {}", current_offset)));
+            }
+            // Append the body to the partial file (chunked bodies have no Content-Length, so never more than
+            // what is left of the Upload-Length). When the connection breaks in between, everything that was
+            // received until then is kept, so that the client can resume from there:
+            let remaining: u64 = info.length - offset;
+            let result = OpenOptions::new().append(true).open(&data_path)
+                .and_then(|mut file| {
+                    let mut body = http_request.body_reader(stream);
+                    io::copy(&mut (&mut body).take(remaining), &mut file)?;
+                    // Anything after that is more than the Upload-Length allows, so the whole request is rejected:
+                    let too_long: bool = body.read(&mut [0u8; 1])? > 0;
+                    if too_long {
+                        file.set_len(offset)?;
+                    }
+                    return Ok(too_long);
+                });
+            UPLOADS_IN_PROGRESS.lock().unwrap().remove(id);
+            if result? {
+                return send_error(stream, (400, "the upload would be longer than its Upload-Length".to_string()));
+            }
+
+            let offset: u64 = fs::metadata(&data_path)?.len();
+            if offset == info.length {
+                if let Err(err) = complete_upload(id, &info, root_dir, stream) {
+                    return send_error(stream, completion_error(err));
+                }
+            }
+            tus_response(204)
+                .header("Upload-Offset", offset)
+                .header("Upload-Expires", http_date(info.expires()))
+                .empty()
+                .send_to_tcp_stream(stream)
+        },
+        "DELETE" => {
+            remove_upload(&staging_dir, id)?;
+            println!("[{}] {} cancelled the resumable upload of {}", date_time_str(), peer_addr(stream), info.filename);
+            tus_response(204).empty().send_to_tcp_stream(stream)
+        },
+        _ => send_error(stream, (405, "only HEAD, PATCH and DELETE are allowed on uploads".to_string())),
+    }
+}
+
+/// Deletes all partial uploads that expired, i.e. that were not completed in time.
+pub fn remove_expired_uploads(root_dir: &Path) {
+    let staging_dir: PathBuf = root_dir.join(STAGING_DIR_NAME);
+    let entries = match fs::read_dir(&staging_dir) {
+        Ok(entries) => entries,
+        Err(_) => return, // There is no staging folder (yet), so there's nothing to remove.
+    };
+    for entry in entries.flatten() {
+        let file_name: String = entry.file_name().to_string_lossy().to_string();
+        let id: &str = match file_name.strip_suffix(".info") {
+            Some(id) => id,
+            None => continue,
+        };
+        let expired: bool = UploadInfo::read(&entry.path()).map_or(true, |info| info.expires() <= SystemTime::now());
+        if expired && !UPLOADS_IN_PROGRESS.lock().unwrap().contains(id) {
+            match remove_upload(&staging_dir, id) {
+                Ok(()) => println!("[{}] Removed expired partial upload {}", date_time_str(), id),
+                Err(err) => eprintln!("[{}] Error: Failed to remove expired partial upload {}: {}", date_time_str(), id, err),
+            }
+        }
+    }
+}
+
+/// Moves a completely received upload from the staging folder into its destination folder.
+fn complete_upload(id: &str, info: &UploadInfo, root_dir: &Path, stream: &TcpStream) -> io::Result<()> {
+    let staging_dir: PathBuf = root_dir.join(STAGING_DIR_NAME);
+    let dir_path: PathBuf = root_dir.join(&info.directory);
+    match resolve_collision(&staging_dir.join(id), &dir_path, &info.filename, CONFIG.upload_collision)? {
+        Some(final_path) => {
+            fs::remove_file(staging_dir.join(format!("{}.info", id)))?;
+            println!("[{}] {} uploaded {} ({} bytes) to /{}", date_time_str(), peer_addr(stream),
+                     final_path.file_name().unwrap().to_string_lossy(), info.length, info.directory.display());
+            return Ok(());
+        },
+        None => {
+            remove_upload(&staging_dir, id)?;
+            return Err(Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", info.filename)));
+        }
+    }
+}
+
+/// The status code and message to respond with when `complete_upload` failed.
+fn completion_error(err: io::Error) -> (u16, String) {
+    let status_code: u16 = if err.kind() == io::ErrorKind::AlreadyExists { 409 } else { 500 };
+    return (status_code, format!("could not complete the upload: {}", err));
+}
+
+/// Deletes the partial file and the info file of the upload called `id`.
+fn remove_upload(staging_dir: &Path, id: &str) -> io::Result<()> {
+    fs::remove_file(staging_dir.join(format!("{}.info", id)))?;
+    match fs::remove_file(staging_dir.join(id)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// The checks done for every tus request (except `OPTIONS`): the `user` has to be allowed to upload, the client has to
+/// speak the supported tus version and the destination folder has to be a writable folder within the root
+/// directory. Returns the status code and message to respond with when a check fails.
+fn check_request(http_request: &HTTPRequest, root_dir: &Path, dir_path: &Path, user: &UserAccount) -> Result<(), (u16, String)> {
+    if !user.can_upload() {
+        return Err((403, "you are not allowed to upload files".to_string()));
+    }
+    if http_request.get_header("Tus-Resumable") != Some(TUS_VERSION) {
+        return Err((412, format!("only version {} of the tus protocol is supported", TUS_VERSION)));
+    }
+    let within_root: bool = match (fs::canonicalize(dir_path), fs::canonicalize(root_dir)) {
+        (Ok(dir_path), Ok(root_dir)) => dir_path.starts_with(root_dir),
+        _ => false,
+    };
+    if !within_root || !dir_path.is_dir() {
+        return Err((404, format!("no such folder: {}", dir_path.strip_prefix(root_dir).unwrap_or(dir_path).display())));
+    }
+    if fs::metadata(dir_path).map_or(true, |metadata| metadata.permissions().readonly()) {
+        return Err((403, "this folder is read-only".to_string()));
+    }
+    return Ok(());
+}
+
+/// Parses the value of an 'Upload-Metadata' header, i.e. comma-separated pairs of a key and a
+/// Base64-encoded value, like "filename d29ybGQucGRm,is_confidential".
+fn parse_metadata(upload_metadata: &str) -> Vec<(String, String)> {
+    upload_metadata.split(',')
+        .filter_map(|pair| {
+            let mut key_and_value = pair.trim().splitn(2, ' ');
+            let key: &str = key_and_value.next().filter(|key| !key.is_empty())?;
+            let value: Vec<u8> = base64::decode(key_and_value.next().unwrap_or("").trim()).ok()?;
+            Some((key.to_string(), String::from_utf8(value).ok()?))
+        })
+        .collect()
+}
+
+/// Start building a response to a tus request, which always contains the 'Tus-Resumable' header.
+fn tus_response(status_code: u16) -> HTTPResponseBuilder {
+    HTTPResponse::builder()
+        .status(status_code)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header("Tus-Version", TUS_VERSION)
+}
+
+/// Responds with the given error and returns it as an `Err`, so that it gets logged.
+fn send_error(stream: &mut TcpStream, (status_code, message): (u16, String)) -> io::Result<()> {
+    tus_response(status_code).bytes(format!("Error: {}", message)).send_to_tcp_stream(stream)?;
+    return Err(Error::other(format!("resumable upload failed: {}", message)));
+}
+
+/// Formats a `SystemTime` as an HTTP date, e.g. "Wed, 25 Jun 2014 16:00:00 GMT".
+fn http_date(system_time: SystemTime) -> String {
+    DateTime::<Utc>::from(system_time).format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn peer_addr(stream: &TcpStream) -> String {
+    stream.peer_addr().map_or("???".to_string(), |addr| addr.to_string())
+}
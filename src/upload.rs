@@ -0,0 +1,237 @@
+//! File uploads via 'multipart/form-data' POST requests, as sent by the upload form shown on every
+//! directory page (when uploads are enabled using the `--uploads` option).
+
+use std::fs::{self, File};
+use std::io::{self, Error, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use http_share::{HTTPRequest, HTTPResponse};
+use http_share::multipart::MultipartReader;
+use rand::Rng;
+use crate::config::{CollisionPolicy, UserAccount, CONFIG};
+use crate::date_time_str;
+
+/// The HTML upload form (including the JavaScript for drag and drop) shown on every directory page
+/// when uploads are enabled. The selected files are uploaded into the current folder using the tus
+/// protocol (cf. `tus.rs`), so that interrupted uploads are resumed automatically, even after
+/// reloading the page. Without JavaScript, the form is posted as 'multipart/form-data' instead.
+pub const UPLOAD_FORM_HTML: &str =
+"<form id=\"uploadForm\" method=\"post\" enctype=\"multipart/form-data\">\r\n\
+Upload: <input type=\"file\" name=\"files\" multiple onchange=\"uploadFiles(this.files);\"> \
+<noscript><input type=\"submit\" value=\"Upload\"></noscript> \
+<i>(or drop files anywhere on this page)</i> <span id=\"uploadStatus\"></span>\r\n\
+</form>\r\n\
+<script>
+const TUS_CHUNK_SIZE = 8 * 1024 * 1024;
+async function tusRequest(url, method, headers, body) {
+    headers['Tus-Resumable'] = '1.0.0';
+    const response = await fetch(url, {method: method, headers: headers, body: body});
+    if (!response.ok) {
+        const error = new Error(response.status + ' ' + await response.text());
+        error.status = response.status;
+        throw error;
+    }
+    return response;
+}
+/* Uploads a file using the tus protocol, resuming an earlier upload of the same file if there is one: */
+async function tusUpload(file, showProgress) {
+    const key = 'tus ' + location.pathname + ' ' + file.name + ' ' + file.size + ' ' + file.lastModified;
+    let url = localStorage.getItem(key);
+    let offset = 0;
+    if (url) {
+        try {
+            offset = parseInt((await tusRequest(url, 'HEAD', {})).headers.get('Upload-Offset'));
+        } catch (error) {
+            url = null; /* (e.g. because the upload expired) */
+        }
+    }
+    if (!url) {
+        const metadata = 'filename ' + btoa(unescape(encodeURIComponent(file.name)));
+        url = (await tusRequest(location.pathname, 'POST', {'Upload-Length': file.size, 'Upload-Metadata': metadata})).headers.get('Location');
+        localStorage.setItem(key, url);
+    }
+    let failures = 0;
+    while (offset < file.size) {
+        showProgress(offset);
+        try {
+            const chunk = file.slice(offset, offset + TUS_CHUNK_SIZE);
+            const headers = {'Upload-Offset': offset, 'Content-Type': 'application/offset+octet-stream'};
+            offset = parseInt((await tusRequest(url, 'PATCH', headers, chunk)).headers.get('Upload-Offset'));
+            failures = 0;
+        } catch (error) {
+            if ((error.status && error.status < 500) || ++failures > 20) {
+                localStorage.removeItem(key);
+                throw error;
+            }
+            /* Wait for the connection to come back and ask the server where to continue: */
+            await new Promise(resolve => setTimeout(resolve, 3000));
+            try {
+                offset = parseInt((await tusRequest(url, 'HEAD', {})).headers.get('Upload-Offset'));
+            } catch (error) {}
+        }
+    }
+    localStorage.removeItem(key);
+}
+async function uploadFiles(files) {
+    const status = document.getElementById('uploadStatus');
+    for (const file of files) {
+        try {
+            await tusUpload(file, offset => status.textContent = 'Uploading ' + file.name + ': ' + Math.floor(100 * offset / file.size) + '%');
+        } catch (error) {
+            status.textContent = 'Upload of ' + file.name + ' failed: ' + error.message;
+            return;
+        }
+    }
+    location.reload();
+}
+document.addEventListener('dragover', event => event.preventDefault());
+document.addEventListener('drop', event => {
+    event.preventDefault();
+    uploadFiles(event.dataTransfer.files);
+});
+</script>\r\n";
+
+/// Handles a 'multipart/form-data' POST request uploading files into the directory `dir_path`.
+/// Every file is streamed to a temporary file in `dir_path` first and only renamed to its final
+/// (sanitized) name once it was received completely, so aborted uploads leave no half-written files behind.
+///
+/// `request_target` is the URL the request was sent to, the client is redirected back there afterwards.
+pub fn upload_response(http_request: &HTTPRequest, dir_path: &Path, root_dir: &Path, request_target: &str, user: &UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    if !user.can_upload() {
+        HTTPResponse::new_403_forbidden("You are not allowed to upload files.").send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("tried to upload to {} without being allowed to", request_target)));
+    }
+    // Never write outside of the shared folder (e.g. because of a '..' in the path):
+    if !fs::canonicalize(dir_path)?.starts_with(fs::canonicalize(root_dir)?) {
+        HTTPResponse::new_403_forbidden("Forbidden").send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("tried to upload to {} which is outside of the shared folder", dir_path.display())));
+    }
+    if fs::metadata(dir_path)?.permissions().readonly() {
+        HTTPResponse::new_403_forbidden("This folder is read-only.").send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("tried to upload to read-only folder {}", request_target)));
+    }
+    let boundary: String = match http_request.get_header("Content-Type").and_then(MultipartReader::<io::Empty>::boundary_from_content_type) {
+        Some(boundary) => boundary,
+        None => {
+            HTTPResponse::builder().status(415).bytes("Uploads have to be sent as multipart/form-data.").send_to_tcp_stream(stream)?;
+            return Err(Error::other(format!("tried to upload to {} without using multipart/form-data", request_target)));
+        }
+    };
+    let content_length: u64 = match http_request.get_content_length() {
+        Some(content_length) => content_length,
+        None => {
+            HTTPResponse::builder().status(411).bytes("Length Required").send_to_tcp_stream(stream)?;
+            return Err(Error::other(format!("tried to upload to {} without a Content-Length", request_target)));
+        }
+    };
+    if let Some(max_upload_size) = CONFIG.max_upload_size.filter(|&max| content_length > max) {
+        HTTPResponse::builder().status(413).bytes(format!("Uploads may not be larger than {} bytes.", max_upload_size)).send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("tried to upload {} bytes to {} (limit: {} bytes)", content_length, request_target, max_upload_size)));
+    }
+    if http_request.get_header("Expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?; // e.g. curl waits for this before sending large bodies
+    }
+
+    let ip_addr: String = stream.peer_addr().map_or("???".to_string(), |addr| addr.to_string());
+    let mut uploaded: Vec<String> = Vec::new();
+    let mut rejected: Vec<String> = Vec::new();
+    let result: io::Result<()> = (|| {
+        let mut multipart = MultipartReader::new(http_request.body_reader(stream), &boundary);
+        while let Some(part) = multipart.next_part()? {
+            // Only file fields are of interest, everything else (including empty file fields) is skipped:
+            let filename: String = match part.filename.as_deref().and_then(sanitize_filename) {
+                Some(filename) => filename,
+                None => continue,
+            };
+            let temp_path: PathBuf = dir_path.join(format!(".{}.http_share_upload_{:08x}", filename, rand::thread_rng().gen::<u32>()));
+            let bytes_written: u64 = match File::create(&temp_path).and_then(|mut file| io::copy(&mut multipart, &mut file)) {
+                Ok(bytes_written) => bytes_written,
+                Err(err) => {
+                    let _ = fs::remove_file(&temp_path);
+                    return Err(err);
+                }
+            };
+            match resolve_collision(&temp_path, dir_path, &filename, CONFIG.upload_collision).inspect_err(|_| { let _ = fs::remove_file(&temp_path); })? {
+                Some(final_path) => {
+                    let final_name: String = final_path.file_name().unwrap().to_string_lossy().to_string();
+                    println!("[{}] {} uploaded {} ({} bytes) to {}", date_time_str(), ip_addr, final_name, bytes_written, request_target);
+                    uploaded.push(final_name);
+                },
+                None => {
+                    fs::remove_file(&temp_path)?;
+                    rejected.push(filename);
+                }
+            }
+        }
+        return Ok(());
+    })();
+
+    if let Err(err) = result {
+        let message = format!("Upload failed after {} file(s): {}", uploaded.len(), err);
+        let status_code: u16 = if err.kind() == io::ErrorKind::PermissionDenied { 403 } else { 500 };
+        HTTPResponse::builder().status(status_code).bytes(message.clone()).send_to_tcp_stream(stream)?;
+        return Err(Error::other(message));
+    }
+    if !rejected.is_empty() {
+        HTTPResponse::builder()
+            .status(409)
+            .bytes(format!("The following files already exist and were not uploaded: {}", rejected.join(", ")))
+            .send_to_tcp_stream(stream)?;
+        return Err(Error::other(format!("upload of already existing file(s) {} to {} rejected", rejected.join(", "), request_target)));
+    }
+    // Redirect the browser back to the directory listing (which now contains the uploaded files):
+    return HTTPResponse::builder()
+        .status(303)
+        .header("Location", request_target)
+        .bytes(format!("Uploaded: {}", uploaded.join(", ")))
+        .send_to_tcp_stream(stream);
+}
+
+/// Turns a file name given by the client into one that is safe to use in the shared folder, or returns
+/// `None` when nothing usable is left. Only the last path component is kept (some browsers send full
+/// paths), characters not allowed in file names on common file systems are replaced, and leading dots
+/// are removed so that no hidden files can be created.
+pub fn sanitize_filename(filename: &str) -> Option<String> {
+    let filename: &str = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let sanitized: String = filename.chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    let sanitized: &str = sanitized.trim().trim_start_matches('.').trim_end_matches(['.', ' ']);
+    return if sanitized.is_empty() { None } else { Some(sanitized.to_string()) };
+}
+
+/// Moves the completely received upload at `source` into `dir_path` as `filename`, resolving a collision
+/// with an existing file according to the `policy`. Returns the final path, or `None` when the upload has
+/// to be rejected (`source` is left in place then).
+///
+/// Unless existing files are overwritten anyway, the destination is claimed by atomically creating it
+/// before the upload is moved there, so that concurrent uploads of the same name can't replace each other.
+pub fn resolve_collision(source: &Path, dir_path: &Path, filename: &str, policy: CollisionPolicy) -> io::Result<Option<PathBuf>> {
+    if policy == CollisionPolicy::Overwrite {
+        let path: PathBuf = dir_path.join(filename);
+        fs::rename(source, &path)?;
+        return Ok(Some(path));
+    }
+    // "video.mp4" -> "video (1).mp4", "video (2).mp4", ...
+    let (stem, extension): (&str, &str) = match filename.rfind('.') {
+        Some(index) if index > 0 => filename.split_at(index),
+        _ => (filename, ""),
+    };
+    for i in 0.. {
+        let path: PathBuf = match i {
+            0 => dir_path.join(filename),
+            _ => dir_path.join(format!("{} ({}){}", stem, i, extension)),
+        };
+        match File::options().write(true).create_new(true).open(&path) {
+            Ok(_) => {
+                // The (empty) file created above is replaced by the upload:
+                fs::rename(source, &path).inspect_err(|_| { let _ = fs::remove_file(&path); })?;
+                return Ok(Some(path));
+            },
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && policy == CollisionPolicy::Rename => continue,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("there is always a free name");
+}
@@ -0,0 +1,661 @@
+//! A WebDAV server (RFC 4918, class 1 and 2), so that the shared folder can be mounted as a network
+//! drive, e.g. in the macOS Finder, the Windows Explorer, Linux file managers or the iOS Files app.
+//! Enabled using the `--webdav` option.
+//!
+//! Reading (`PROPFIND`) is possible for everyone who may view the shared folder. Writing requires the same
+//! permissions as in the browser: `PUT` requires the 'upload' permission, `MKCOL`, `DELETE`, `COPY` and
+//! `MOVE` the 'manage' permission (deleted items are moved into the trash folder as well).
+//! Locks (`LOCK`/`UNLOCK`) are held in memory only, and `PROPPATCH` is accepted but dead properties are
+//! not stored.
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, Error, Read, Write};
+use std::net::TcpStream;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use chrono::{DateTime, SecondsFormat, Utc};
+use http_share::{HTTPRequest, HTTPResponse, HTTPResponseBuilder};
+use http_share::mime::mime_type;
+use lazy_static::lazy_static;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::Rng;
+use crate::config::{UserAccount, CONFIG};
+use crate::{date_time_str, hidden};
+
+/// The methods supported in WebDAV mode (besides `GET`, `HEAD`, `POST` and `OPTIONS`).
+pub const WEBDAV_METHODS: [&str; 9] = ["PROPFIND", "PROPPATCH", "MKCOL", "PUT", "DELETE", "COPY", "MOVE", "LOCK", "UNLOCK"];
+
+/// The maximum size of an XML request body (`PROPFIND`, `PROPPATCH`, `LOCK`), in bytes.
+const MAX_XML_BODY_SIZE: u64 = 64 * 1024;
+
+/// The timeout of locks when the client doesn't ask for a specific one, and the maximum timeout.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const MAX_LOCK_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The characters that are percent-encoded in the `<D:href>`s: everything except for letters, digits,
+/// slashes and a few other unreserved characters.
+const HREF_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'/').remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+/// A WebDAV lock on a file or folder.
+struct Lock {
+    /// The lock token, like "opaquelocktoken:0123456789abcdef...".
+    token: String,
+    /// The locked file or folder.
+    fs_path: PathBuf,
+    /// Whether everything within a locked folder is locked too (Depth: infinity).
+    depth_infinity: bool,
+    /// Whether the lock is exclusive (otherwise shared).
+    exclusive: bool,
+    /// The `<D:owner>` given by the client (raw XML), returned in the lock discovery.
+    owner: String,
+    timeout: Duration,
+    expires: Instant,
+}
+
+impl Lock {
+    /// Whether this lock (also) applies to `fs_path`.
+    fn covers(&self, fs_path: &Path) -> bool {
+        self.fs_path == fs_path || (self.depth_infinity && fs_path.starts_with(&self.fs_path))
+    }
+
+    /// The `<D:activelock>` XML describing this lock.
+    fn active_lock_xml(&self, root_dir: &Path) -> String {
+        format!("<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{}</D:lockscope>\
+                 <D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:timeout>\
+                 <D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+                if self.exclusive { "<D:exclusive/>" } else { "<D:shared/>" },
+                if self.depth_infinity { "infinity" } else { "0" },
+                self.owner,
+                self.expires.saturating_duration_since(Instant::now()).as_secs(),
+                self.token,
+                href(&self.fs_path, root_dir, self.fs_path.is_dir()))
+    }
+}
+
+lazy_static! {
+    /// All currently held locks.
+    static ref LOCKS: Mutex<Vec<Lock>> = Mutex::new(Vec::new());
+}
+
+/// Handles a WebDAV request (one of `WEBDAV_METHODS`) concerning `fs_path`.
+pub fn webdav_response(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> io::Result<()> {
+    if !CONFIG.webdav_enabled {
+        return send_error(stream, 405, format!("{} requests are only supported when WebDAV is enabled", http_request.get_method()));
+    }
+    // Never touch anything outside of the shared folder (e.g. because of a '..' in the path):
+    if !is_within_root(fs_path, root_dir) {
+        return send_error(stream, 403, format!("{} is outside of the shared folder", fs_path.display()));
+    }
+    LOCKS.lock().unwrap().retain(|lock| lock.expires > Instant::now()); // Forget about expired locks.
+
+    let result: Result<HTTPResponse, (u16, String)> = match http_request.get_method() {
+        "PROPFIND" => propfind(http_request, fs_path, root_dir, user, stream),
+        "PROPPATCH" => proppatch(http_request, fs_path, root_dir, user, stream),
+        "MKCOL" => mkcol(http_request, fs_path, user),
+        "PUT" => put(http_request, fs_path, user, stream),
+        "DELETE" => delete(http_request, fs_path, root_dir, user),
+        "COPY" | "MOVE" => copy_or_move(http_request, fs_path, root_dir, user),
+        "LOCK" => lock(http_request, fs_path, root_dir, user, stream),
+        "UNLOCK" => unlock(http_request, fs_path),
+        method => Err((405, format!("unsupported method: {}", method))),
+    };
+    match result {
+        Ok(response) => {
+            if !["PROPFIND", "LOCK", "UNLOCK"].contains(&http_request.get_method()) {
+                println!("[{}] {} ({}) {} /{} via WebDAV", date_time_str(), stream.peer_addr().map_or("???".to_string(), |addr| addr.to_string()),
+                         user.name, http_request.get_method(), fs_path.strip_prefix(root_dir).unwrap_or(fs_path).display());
+            }
+            response.send_to_tcp_stream(stream)
+        },
+        Err((status_code, message)) => send_error(stream, status_code, message),
+    }
+}
+
+/// The headers advertising WebDAV support in the response to an `OPTIONS` request.
+pub fn add_options_headers(response: HTTPResponseBuilder) -> HTTPResponseBuilder {
+    if !CONFIG.webdav_enabled {
+        return response;
+    }
+    response
+        .header("DAV", "1, 2")
+        .header("MS-Author-Via", "DAV") // (needed by Windows)
+        .header("Allow", format!("OPTIONS, GET, HEAD, POST, {}", WEBDAV_METHODS.join(", ")))
+}
+
+/// `PROPFIND`: returns the properties of `fs_path` and, for folders with 'Depth: 1', of everything in it
+/// that is visible to the `user` (cf. hidden.rs).
+fn propfind(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> Result<HTTPResponse, (u16, String)> {
+    let metadata: Metadata = fs::metadata(fs_path).map_err(|_| (404, format!("{} not found", fs_path.display())))?;
+    let depth_one: bool = match http_request.get_header("Depth") {
+        Some("0") => false,
+        Some("1") => true,
+        // "infinity" (the default) would mean listing the entire shared folder at once, which is not supported:
+        _ => return Ok(HTTPResponse::builder()
+            .status(403)
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .bytes("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>")),
+    };
+    let body: String = read_xml_body(http_request, stream)?;
+    let requested: RequestedProperties = if body.trim().is_empty() || !xml_elements(&body).iter().any(|element| element.name == "prop" || element.name == "propname") {
+        RequestedProperties::All // an empty body or <D:allprop/>
+    } else if xml_elements(&body).iter().any(|element| element.name == "propname") {
+        RequestedProperties::Names
+    } else {
+        RequestedProperties::Some(xml_child_elements(&body, "prop"))
+    };
+
+    let mut entries: Vec<(PathBuf, Metadata)> = vec![(fs_path.to_path_buf(), metadata.clone())];
+    if depth_one && metadata.is_dir() {
+        let children = fs::read_dir(fs_path).map_err(|err| (500, err.to_string()))?;
+        let hidden_items = hidden::FolderRules::of(fs_path, root_dir, user);
+        entries.extend(children
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| !hidden_items.hides(path, path.is_dir()))
+            .filter_map(|path| fs::metadata(&path).ok().map(|metadata| (path, metadata))));
+    }
+
+    // Generate the response while sending it, as there may be a lot of entries:
+    let root_dir: PathBuf = root_dir.to_path_buf();
+    return Ok(HTTPResponse::builder()
+        .status(207)
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .stream(move |writer| {
+            writer.write_all(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n<D:multistatus xmlns:D=\"DAV:\">\r\n")?;
+            for (path, metadata) in entries {
+                writer.write_all(properties_response_xml(&path, &metadata, &root_dir, &requested).as_bytes())?;
+            }
+            writer.write_all(b"</D:multistatus>\r\n")
+        }))
+}
+
+/// Which properties a `PROPFIND` asks for.
+enum RequestedProperties {
+    /// All properties (<D:allprop/>).
+    All,
+    /// Only the names of all properties (<D:propname/>).
+    Names,
+    /// Only the given properties (<D:prop>...</D:prop>).
+    Some(Vec<XmlElement>),
+}
+
+/// The `<D:response>` XML containing the requested properties of the file or folder at `fs_path`.
+fn properties_response_xml(fs_path: &Path, metadata: &Metadata, root_dir: &Path, requested: &RequestedProperties) -> String {
+    let display_name: String = fs_path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let modified: Option<SystemTime> = metadata.modified().ok();
+    // The live properties supported by this server, (name, value as XML):
+    let mut properties: Vec<(&str, String)> = vec![
+        ("displayname", xml_escape(&display_name)),
+        ("resourcetype", if metadata.is_dir() { "<D:collection/>".to_string() } else { String::new() }),
+        ("getlastmodified", modified.map_or(String::new(), |modified| DateTime::<Utc>::from(modified).format("%a, %d %b %Y %H:%M:%S GMT").to_string())),
+        ("creationdate", metadata.created().ok().or(modified)
+            .map_or(String::new(), |created| DateTime::<Utc>::from(created).to_rfc3339_opts(SecondsFormat::Secs, true))),
+        ("supportedlock", "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+                           <D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>".to_string()),
+        ("lockdiscovery", LOCKS.lock().unwrap().iter()
+            .filter(|lock| lock.covers(fs_path))
+            .map(|lock| lock.active_lock_xml(root_dir))
+            .collect()),
+    ];
+    if metadata.is_file() {
+        properties.push(("getcontentlength", metadata.len().to_string()));
+        properties.push(("getcontenttype", mime_type(fs_path).to_string()));
+        properties.push(("getetag", format!("\"{}\"", etag(metadata))));
+    }
+
+    let (found, not_found): (String, String) = match requested {
+        RequestedProperties::All => (
+            properties.iter().map(|(name, value)| format!("<D:{0}>{1}</D:{0}>", name, value)).collect(),
+            String::new(),
+        ),
+        RequestedProperties::Names => (
+            properties.iter().map(|(name, _)| format!("<D:{}/>", name)).collect(),
+            String::new(),
+        ),
+        RequestedProperties::Some(requested) => {
+            let mut found = String::new();
+            let mut not_found = String::new();
+            for element in requested {
+                match properties.iter().find(|(name, _)| element.namespace == "DAV:" && *name == element.name) {
+                    Some((name, value)) => found += &format!("<D:{0}>{1}</D:{0}>", name, value),
+                    None => not_found += &format!("<X:{} xmlns:X=\"{}\"/>", element.name, xml_escape(&element.namespace)),
+                }
+            }
+            (found, not_found)
+        }
+    };
+    let mut response = format!("<D:response><D:href>{}</D:href>", href(fs_path, root_dir, metadata.is_dir()));
+    if !found.is_empty() {
+        response += &format!("<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>", found);
+    }
+    if !not_found.is_empty() {
+        response += &format!("<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 404 Not Found</D:status></D:propstat>", not_found);
+    }
+    return response + "</D:response>\r\n";
+}
+
+/// `PROPPATCH`: dead properties are not stored, but the request is acknowledged, as some clients
+/// (e.g. Windows, setting the file times after an upload) fail otherwise.
+fn proppatch(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> Result<HTTPResponse, (u16, String)> {
+    if !user.can_upload() && !user.can_manage() {
+        return Err((403, "you are not allowed to change files".to_string()));
+    }
+    let metadata: Metadata = fs::metadata(fs_path).map_err(|_| (404, format!("{} not found", fs_path.display())))?;
+    check_locks(http_request, fs_path)?;
+    let body: String = read_xml_body(http_request, stream)?;
+    let properties: String = xml_child_elements(&body, "prop").iter()
+        .map(|element| format!("<X:{} xmlns:X=\"{}\"/>", element.name, xml_escape(&element.namespace)))
+        .collect();
+    return Ok(HTTPResponse::builder()
+        .status(207)
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .bytes(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n<D:multistatus xmlns:D=\"DAV:\"><D:response><D:href>{}</D:href>\
+                        <D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response></D:multistatus>\r\n",
+                       href(fs_path, root_dir, metadata.is_dir()), properties)))
+}
+
+/// `MKCOL`: creates a new folder.
+fn mkcol(http_request: &HTTPRequest, fs_path: &Path, user: &UserAccount) -> Result<HTTPResponse, (u16, String)> {
+    if !user.can_manage() {
+        return Err((403, "you are not allowed to create folders".to_string()));
+    }
+    if http_request.get_content_length().unwrap_or(0) > 0 || http_request.is_chunked() {
+        return Err((415, "MKCOL requests with a body are not supported".to_string()));
+    }
+    if fs_path.exists() {
+        return Err((405, format!("{} already exists", fs_path.display())));
+    }
+    if !fs_path.parent().is_some_and(|parent| parent.is_dir()) {
+        return Err((409, format!("the parent folder of {} does not exist", fs_path.display())));
+    }
+    check_locks(http_request, fs_path)?;
+    fs::create_dir(fs_path).map_err(io_error)?;
+    return Ok(HTTPResponse::builder().status(201).empty());
+}
+
+/// `PUT`: uploads a file, replacing it if it already exists. Like with uploads from the browser, the file is
+/// written to a temporary file first and only renamed once it was received completely.
+fn put(http_request: &HTTPRequest, fs_path: &Path, user: &UserAccount, stream: &mut TcpStream) -> Result<HTTPResponse, (u16, String)> {
+    if !user.can_upload() {
+        return Err((403, "you are not allowed to upload files".to_string()));
+    }
+    if fs_path.is_dir() {
+        return Err((405, format!("{} is a folder", fs_path.display())));
+    }
+    let parent: &Path = fs_path.parent().filter(|parent| parent.is_dir())
+        .ok_or((409, format!("the parent folder of {} does not exist", fs_path.display())))?;
+    let filename: String = fs_path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    // Only names that can't lead anywhere else are rejected, whether dotfiles (like the "._name" files of macOS)
+    // may be uploaded is decided by the hidden rules (cf. hidden.rs):
+    if filename.is_empty() || filename.contains(['/', '\\']) || fs_path.components().any(|component| component == Component::ParentDir) {
+        return Err((400, format!("invalid file name: {}", filename)));
+    }
+    if let Some(max_upload_size) = CONFIG.max_upload_size {
+        // (macOS sends the size of chunked uploads in 'X-Expected-Entity-Length')
+        let length: u64 = http_request.get_content_length()
+            .or_else(|| http_request.get_header("X-Expected-Entity-Length").and_then(|length| length.parse().ok()))
+            .unwrap_or(0);
+        if length > max_upload_size {
+            return Err((413, format!("uploads may not be larger than {} bytes", max_upload_size)));
+        }
+    }
+    check_locks(http_request, fs_path)?;
+    if http_request.get_header("Expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").map_err(io_error)?;
+    }
+
+    let existed: bool = fs_path.exists();
+    let temp_path: PathBuf = parent.join(format!(".{}.http_share_upload_{:08x}", filename, rand::thread_rng().gen::<u32>()));
+    let max_upload_size: u64 = CONFIG.max_upload_size.unwrap_or(u64::MAX);
+    let result: io::Result<u64> = File::create(&temp_path)
+        .and_then(|mut file| io::copy(&mut http_request.body_reader(stream).take(max_upload_size.saturating_add(1)), &mut file))
+        .and_then(|bytes_written| if bytes_written > max_upload_size {
+            Err(Error::other(format!("uploads may not be larger than {} bytes", max_upload_size)))
+        } else {
+            Ok(bytes_written)
+        })
+        .and_then(|bytes_written| fs::rename(&temp_path, fs_path).map(|_| bytes_written));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(io_error(err));
+    }
+    return Ok(HTTPResponse::builder().status(if existed { 204 } else { 201 }).empty());
+}
+
+/// `DELETE`: moves a file or folder into the trash folder.
+fn delete(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount) -> Result<HTTPResponse, (u16, String)> {
+    if !user.can_manage() {
+        return Err((403, "you are not allowed to delete files".to_string()));
+    }
+    if !fs_path.exists() {
+        return Err((404, format!("{} not found", fs_path.display())));
+    }
+    if is_root(fs_path, root_dir) {
+        return Err((403, "the shared folder itself cannot be deleted".to_string()));
+    }
+    check_locks(http_request, fs_path)?;
+    crate::manage::move_to_trash(fs_path, &crate::manage::trash_dir(root_dir))?;
+    LOCKS.lock().unwrap().retain(|lock| !lock.fs_path.starts_with(fs_path));
+    return Ok(HTTPResponse::builder().status(204).empty());
+}
+
+/// `COPY` and `MOVE`: copies/moves a file or folder to the URL given in the 'Destination' header.
+fn copy_or_move(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount) -> Result<HTTPResponse, (u16, String)> {
+    let is_move: bool = http_request.get_method() == "MOVE";
+    if !user.can_manage() {
+        return Err((403, "you are not allowed to copy or move files".to_string()));
+    }
+    if !fs_path.exists() {
+        return Err((404, format!("{} not found", fs_path.display())));
+    }
+    if is_move && is_root(fs_path, root_dir) {
+        return Err((403, "the shared folder itself cannot be moved".to_string()));
+    }
+    // The destination is a full URL like "http://192.168.0.2:8080/Photos/2021/image.jpg":
+    let destination: &str = http_request.get_header("Destination").ok_or((400, "missing Destination header".to_string()))?;
+    let destination_path: &str = match destination.split_once("://") {
+        Some((_scheme, rest)) => rest.find('/').map_or("/", |index| &rest[index..]),
+        None => destination,
+    };
+    let destination_path: String = percent_decode_str(destination_path.split('?').next().unwrap()).decode_utf8_lossy().to_string();
+    let destination_fs_path: PathBuf = root_dir.join(destination_path.trim_start_matches('/').trim_end_matches('/'));
+    if !is_within_root(&destination_fs_path, root_dir) || hidden::is_hidden(&destination_fs_path, root_dir, user) || is_root(&destination_fs_path, root_dir) {
+        return Err((403, format!("invalid destination: {}", destination_path)));
+    }
+    if destination_fs_path.starts_with(fs_path) {
+        return Err((403, "a folder cannot be copied or moved into itself".to_string()));
+    }
+    if !destination_fs_path.parent().is_some_and(|parent| parent.is_dir()) {
+        return Err((409, format!("the parent folder of {} does not exist", destination_path)));
+    }
+    let overwrite: bool = http_request.get_header("Overwrite").is_none_or(|overwrite| !overwrite.eq_ignore_ascii_case("F"));
+    let existed: bool = destination_fs_path.exists();
+    if existed && !overwrite {
+        return Err((412, format!("{} already exists", destination_path)));
+    }
+    check_locks(http_request, &destination_fs_path)?;
+    if is_move {
+        check_locks(http_request, fs_path)?;
+    }
+
+    if existed { // Overwriting means moving the old version into the trash first:
+        crate::manage::move_to_trash(&destination_fs_path, &crate::manage::trash_dir(root_dir))?;
+    }
+    if is_move {
+        fs::rename(fs_path, &destination_fs_path).map_err(io_error)?;
+        LOCKS.lock().unwrap().retain(|lock| !lock.fs_path.starts_with(fs_path));
+    } else {
+        let depth_infinity: bool = http_request.get_header("Depth").is_none_or(|depth| depth.eq_ignore_ascii_case("infinity"));
+        copy_recursively(fs_path, &destination_fs_path, depth_infinity, Some((root_dir, user))).map_err(io_error)?;
+    }
+    return Ok(HTTPResponse::builder().status(if existed { 204 } else { 201 }).empty());
+}
+
+/// Copies the file or folder at `from` to `to`. The contents of folders are only copied when `recursive`.
+/// Given the root directory and a user in `visible_to`, the items inside of folders that are hidden from
+/// that user (including the internal folders and settings files, cf. hidden.rs) are left out.
+pub fn copy_recursively(from: &Path, to: &Path, recursive: bool, visible_to: Option<(&Path, &UserAccount)>) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir(to)?;
+        if recursive {
+            let rules: Option<hidden::FolderRules> = visible_to.map(|(root_dir, user)| hidden::FolderRules::of(from, root_dir, user));
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                let entry_path: PathBuf = entry.path();
+                if rules.as_ref().is_some_and(|rules| rules.hides(&entry_path, entry_path.is_dir())) {
+                    continue;
+                }
+                copy_recursively(&entry_path, &to.join(entry.file_name()), true, visible_to)?;
+            }
+        }
+        return Ok(());
+    }
+    return fs::copy(from, to).map(|_| ());
+}
+
+/// `LOCK`: locks a file or folder (or refreshes an existing lock when no body is given).
+/// Locking a file that doesn't exist yet creates it empty, as clients do so before uploading.
+fn lock(http_request: &HTTPRequest, fs_path: &Path, root_dir: &Path, user: &UserAccount, stream: &mut TcpStream) -> Result<HTTPResponse, (u16, String)> {
+    if !user.can_upload() && !user.can_manage() {
+        return Err((403, "you are not allowed to change files".to_string()));
+    }
+    let timeout: Duration = http_request.get_header("Timeout")
+        .and_then(|timeout| timeout.split(',').find_map(|timeout| timeout.trim().strip_prefix("Second-")?.parse::<u64>().ok()))
+        .map_or(DEFAULT_LOCK_TIMEOUT, |seconds| Duration::from_secs(seconds).min(MAX_LOCK_TIMEOUT));
+    let body: String = read_xml_body(http_request, stream)?;
+
+    let mut locks = LOCKS.lock().unwrap();
+    let (status_code, token): (u16, String) = if body.trim().is_empty() {
+        // Refreshing an existing lock, the token of which is given in the 'If' header:
+        let lock: &mut Lock = locks.iter_mut()
+            .find(|lock| lock.covers(fs_path) && submitted_lock_token(http_request, &lock.token))
+            .ok_or((412, "no matching lock to refresh".to_string()))?;
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + timeout;
+        (200, lock.token.clone())
+    } else {
+        let elements: Vec<XmlElement> = xml_elements(&body);
+        let exclusive: bool = !elements.iter().any(|element| element.name == "shared");
+        let depth_infinity: bool = http_request.get_header("Depth").is_none_or(|depth| depth.eq_ignore_ascii_case("infinity"));
+        let conflict: bool = locks.iter().any(|lock| {
+            (lock.covers(fs_path) || (depth_infinity && lock.fs_path.starts_with(fs_path))) && (lock.exclusive || exclusive)
+        });
+        if conflict {
+            return Err((423, format!("{} is already locked", fs_path.display())));
+        }
+        let mut status_code: u16 = 200;
+        if !fs_path.exists() {
+            if !user.can_upload() {
+                return Err((403, "you are not allowed to upload files".to_string()));
+            }
+            File::create(fs_path).map_err(|_| (409, format!("could not create {}", fs_path.display())))?;
+            status_code = 201;
+        }
+        let token: String = format!("opaquelocktoken:{:032x}", rand::thread_rng().gen::<u128>());
+        locks.push(Lock {
+            token: token.clone(),
+            fs_path: fs_path.to_path_buf(),
+            depth_infinity,
+            exclusive,
+            owner: raw_xml_element(&body, "owner").unwrap_or_default(),
+            timeout,
+            expires: Instant::now() + timeout,
+        });
+        (status_code, token)
+    };
+    let lock: &Lock = locks.iter().find(|lock| lock.token == token).unwrap();
+    return Ok(HTTPResponse::builder()
+        .status(status_code)
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .header("Lock-Token", format!("<{}>", token))
+        .bytes(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>\r\n",
+                       lock.active_lock_xml(root_dir))));
+}
+
+/// `UNLOCK`: removes the lock given in the 'Lock-Token' header.
+fn unlock(http_request: &HTTPRequest, fs_path: &Path) -> Result<HTTPResponse, (u16, String)> {
+    let token: &str = http_request.get_header("Lock-Token")
+        .map(|token| token.trim_start_matches('<').trim_end_matches('>'))
+        .ok_or((400, "missing Lock-Token header".to_string()))?;
+    let mut locks = LOCKS.lock().unwrap();
+    let lock_count: usize = locks.len();
+    locks.retain(|lock| !(lock.token == token && lock.covers(fs_path)));
+    if locks.len() == lock_count {
+        return Err((409, format!("no lock {} on {}", token, fs_path.display())));
+    }
+    return Ok(HTTPResponse::builder().status(204).empty());
+}
+
+/// Makes sure that changing `fs_path` doesn't violate any lock: when `fs_path` (or something in it) is
+/// locked, the token of that lock has to be submitted in the 'If' header.
+fn check_locks(http_request: &HTTPRequest, fs_path: &Path) -> Result<(), (u16, String)> {
+    let locks = LOCKS.lock().unwrap();
+    let violated: Option<&Lock> = locks.iter()
+        .filter(|lock| lock.covers(fs_path) || lock.fs_path.starts_with(fs_path))
+        .find(|lock| !submitted_lock_token(http_request, &lock.token));
+    return match violated {
+        Some(lock) => Err((423, format!("{} is locked", lock.fs_path.display()))),
+        None => Ok(()),
+    };
+}
+
+/// Whether `token` was submitted in the 'If' header of `http_request`, like `If: (<opaquelocktoken:...>)`.
+fn submitted_lock_token(http_request: &HTTPRequest, token: &str) -> bool {
+    http_request.get_header("If").is_some_and(|if_header| if_header.contains(&format!("<{}>", token)))
+}
+
+/// Reads the (XML) body of a request into a String.
+fn read_xml_body(http_request: &HTTPRequest, stream: &mut TcpStream) -> Result<String, (u16, String)> {
+    let mut body = String::new();
+    http_request.body_reader(stream).take(MAX_XML_BODY_SIZE).read_to_string(&mut body)
+        .map_err(|err| (400, format!("could not read the request body: {}", err)))?;
+    return Ok(body);
+}
+
+/// The (percent-encoded) URL path of `fs_path`, with a trailing slash for folders.
+fn href(fs_path: &Path, root_dir: &Path, is_dir: bool) -> String {
+    let relative_path: String = fs_path.strip_prefix(root_dir).unwrap_or(fs_path).to_string_lossy().to_string();
+    let mut href: String = format!("/{}", utf8_percent_encode(&relative_path, HREF_ENCODE_SET));
+    if is_dir && !href.ends_with('/') {
+        href.push('/');
+    }
+    return href;
+}
+
+/// An entity tag for a file, changing whenever the file is modified.
+fn etag(metadata: &Metadata) -> String {
+    let modified: u128 = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+    return format!("{:x}-{:x}", modified, metadata.len());
+}
+
+fn is_within_root(fs_path: &Path, root_dir: &Path) -> bool {
+    // (The path itself might not exist yet, e.g. for PUT or MKCOL, so its parent is checked instead.)
+    let existing: &Path = if fs_path.exists() { fs_path } else { fs_path.parent().unwrap_or(fs_path) };
+    match (fs::canonicalize(existing), fs::canonicalize(root_dir)) {
+        (Ok(existing), Ok(root_dir)) => existing.starts_with(root_dir) && !fs_path.ends_with(".."),
+        _ => false,
+    }
+}
+
+fn is_root(fs_path: &Path, root_dir: &Path) -> bool {
+    match (fs::canonicalize(fs_path), fs::canonicalize(root_dir)) {
+        (Ok(fs_path), Ok(root_dir)) => fs_path == root_dir,
+        _ => false,
+    }
+}
+
+/// Turns an `io::Error` into a status code and message.
+fn io_error(err: io::Error) -> (u16, String) {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => (403, err.to_string()),
+        io::ErrorKind::NotFound => (409, err.to_string()),
+        _ => (500, err.to_string()),
+    }
+}
+
+/// Responds with the given error and returns it as an `Err`, so that it gets logged.
+fn send_error(stream: &mut TcpStream, status_code: u16, message: String) -> io::Result<()> {
+    HTTPResponse::builder().status(status_code).bytes(format!("Error: {}", message)).send_to_tcp_stream(stream)?;
+    return Err(Error::other(format!("WebDAV request failed: {}", message)));
+}
+
+/// Escapes the characters that have a special meaning in XML.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// An XML element: its namespace URI and local name, e.g. ("DAV:", "getcontentlength").
+#[derive(Debug, Clone)]
+struct XmlElement {
+    namespace: String,
+    name: String,
+    /// How deeply nested the element is (0 = root element).
+    depth: usize,
+}
+
+/// A minimal XML scanner, which is all that's needed for the small request bodies of WebDAV clients:
+/// returns all elements of `xml` (in document order) with their namespaces resolved.
+/// The namespace declarations are not scoped, which is fine in practice.
+fn xml_elements(xml: &str) -> Vec<XmlElement> {
+    // First, find all tags (start, end and empty-element tags, skipping comments and declarations):
+    let mut tags: Vec<&str> = Vec::new();
+    let mut rest: &str = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end: usize = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        if !rest.starts_with('?') && !rest.starts_with('!') {
+            tags.push(&rest[..end]);
+        }
+        rest = &rest[end + 1..];
+    }
+    // Then collect the namespace declarations, like `xmlns:D="DAV:"` or `xmlns="DAV:"`:
+    let mut namespaces: Vec<(String, String)> = Vec::new();
+    for tag in tags.iter() {
+        let mut attributes: &str = tag;
+        while let Some(index) = attributes.find("xmlns") {
+            attributes = &attributes[index + 5..];
+            let prefix: String = attributes.split('=').next().unwrap_or("").trim().trim_start_matches(':').to_string();
+            let uri: Option<&str> = attributes.split(['"', '\'']).nth(1);
+            if let Some(uri) = uri {
+                namespaces.push((prefix, uri.to_string()));
+            }
+        }
+    }
+    // At last, turn the tags into elements:
+    let mut elements: Vec<XmlElement> = Vec::new();
+    let mut depth: usize = 0;
+    for tag in tags {
+        if tag.starts_with('/') {
+            depth = depth.saturating_sub(1);
+            continue;
+        }
+        let qualified_name: &str = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        let (prefix, name): (&str, &str) = qualified_name.split_once(':').unwrap_or(("", qualified_name));
+        let namespace: String = namespaces.iter().rev()
+            .find(|(declared_prefix, _)| declared_prefix == prefix)
+            .map_or(String::new(), |(_, uri)| uri.clone());
+        elements.push(XmlElement { namespace, name: name.to_string(), depth });
+        if !tag.ends_with('/') {
+            depth += 1;
+        }
+    }
+    return elements;
+}
+
+/// The elements directly within the first element called `parent` (local name) in `xml`.
+fn xml_child_elements(xml: &str, parent: &str) -> Vec<XmlElement> {
+    let elements: Vec<XmlElement> = xml_elements(xml);
+    let parent_index: usize = match elements.iter().position(|element| element.name == parent) {
+        Some(index) => index,
+        None => return Vec::new(),
+    };
+    let parent_depth: usize = elements[parent_index].depth;
+    return elements[parent_index + 1..].iter()
+        .take_while(|element| element.depth > parent_depth)
+        .filter(|element| element.depth == parent_depth + 1)
+        .cloned()
+        .collect();
+}
+
+/// The raw XML of the first element called `name` (local name) in `xml`, including its start and end tag.
+fn raw_xml_element(xml: &str, name: &str) -> Option<String> {
+    let start: usize = xml.match_indices('<')
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let tag_name: &str = xml[index + 1..].split(|c: char| c.is_whitespace() || c == '>' || c == '/').next().unwrap_or("");
+            tag_name == name || tag_name.ends_with(&format!(":{}", name))
+        })?;
+    let start_tag_end: usize = start + xml[start..].find('>')?;
+    if xml[..start_tag_end].ends_with('/') {
+        return Some(xml[start..=start_tag_end].to_string()); // an empty-element tag like <D:owner/>
+    }
+    let qualified_name: &str = xml[start + 1..].split(|c: char| c.is_whitespace() || c == '>').next()?;
+    let end: usize = start_tag_end + xml[start_tag_end..].find(&format!("</{}>", qualified_name))?;
+    return Some(xml[start..end + qualified_name.len() + 3].to_string());
+}
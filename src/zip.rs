@@ -0,0 +1,627 @@
+//! Writing ZIP archives on the fly, e.g. for downloading whole folders, without knowing the sizes
+//! and CRC-32 checksums of the files up front and without ever seeking in the output.
+//!
+//! Every file is therefore followed by a "data descriptor" containing its sizes and checksum, and the
+//! ZIP64 extensions are used wherever necessary, so both the archive and the files in it may be
+//! larger than 4 GB. The format is described in https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+//!
+//! Existing ZIP archives can be read, too: `read_zip_entries` lists their contents and
+//! `ZipEntry::reader` reads a single file from them, without extracting anything else.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+/// Files at least this large get ZIP64 headers. This is a bit less than 4 GB because deflating
+/// incompressible data makes it slightly larger and the size has to be decided on before compressing.
+const ZIP64_THRESHOLD: u64 = 0xF000_0000;
+
+/// The value of a 32-bit (or 16-bit) header field whose actual value is found in the ZIP64 extra field.
+const ZIP64_MARKER: u32 = 0xFFFF_FFFF;
+
+/// General purpose bit 3 (sizes and CRC-32 follow the data in a data descriptor) and
+/// bit 11 (the file name is encoded in UTF-8).
+const FLAGS_DATA_DESCRIPTOR_UTF8: u16 = 0x0808;
+const FLAGS_UTF8: u16 = 0x0800;
+
+/// "Version made by": Unix (3) as the file attributes are Unix permissions, version 4.5 of the specification.
+const VERSION_MADE_BY: u16 = (3 << 8) | 45;
+
+/// The largest central directory `read_zip_entries` reads into memory (enough for several hundred
+/// thousand entries), so that a corrupt or malicious archive can't make it allocate gigabytes.
+const MAX_CENTRAL_DIRECTORY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How the contents of a file are stored in the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionMethod {
+    /// Uncompressed, best for files that are already compressed (images, videos, archives, ...).
+    Stored,
+    /// Compressed using Deflate.
+    Deflated,
+}
+
+impl CompressionMethod {
+    /// Stores the file at `path` if it's (judging by its extension) already compressed, because
+    /// deflating it again would only cost time, and deflates it otherwise.
+    pub fn for_path(path: &Path) -> Self {
+        let mime_type: &str = crate::mime::mime_type(path);
+        let already_compressed: bool = match mime_type {
+            "image/bmp" | "image/svg+xml" | "image/x-icon" | "audio/wav" => false,
+            _ => mime_type.starts_with("image/") || mime_type.starts_with("video/") || mime_type.starts_with("audio/")
+                || ["application/zip", "application/gzip", "application/zstd", "application/x-7z-compressed", "application/pdf"].contains(&mime_type),
+        };
+        if already_compressed { CompressionMethod::Stored } else { CompressionMethod::Deflated }
+    }
+
+    /// The number identifying this method in the headers.
+    fn code(self) -> u16 {
+        match self {
+            CompressionMethod::Stored => 0,
+            CompressionMethod::Deflated => 8,
+        }
+    }
+
+    /// The method identified by `code` in the headers, `None` for the ones not supported.
+    fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => Some(CompressionMethod::Stored),
+            8 => Some(CompressionMethod::Deflated),
+            _ => None,
+        }
+    }
+}
+
+/// Everything about one entry of the archive that is repeated in the central directory at the end.
+struct CentralDirectoryEntry {
+    name: Vec<u8>,
+    method: CompressionMethod,
+    /// The modification time in MS-DOS format, (time, date).
+    dos_date_time: (u16, u16),
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+    is_directory: bool,
+    /// Whether the local header of this entry was written using ZIP64.
+    zip64: bool,
+}
+
+/// A writer counting the bytes written to it, i.e. the current offset within the archive.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_written = self.inner.write(buf)?;
+        self.count += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a ZIP archive to a (non-seekable) writer, entry by entry.
+/// `finish` has to be called at the end to write the central directory!
+pub struct ZipWriter<W: Write> {
+    out: CountingWriter<W>,
+    entries: Vec<CentralDirectoryEntry>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Create a new `ZipWriter` writing the archive to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self { out: CountingWriter { inner, count: 0 }, entries: Vec::new() }
+    }
+
+    /// Adds an (empty) directory called `name` (using '/' as the separator, without a trailing '/').
+    pub fn add_directory(&mut self, name: &str, modified: SystemTime) -> io::Result<()> {
+        let entry = CentralDirectoryEntry {
+            name: format!("{}/", name).into_bytes(),
+            method: CompressionMethod::Stored,
+            dos_date_time: dos_date_time(modified),
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            local_header_offset: self.out.count,
+            is_directory: true,
+            zip64: false,
+        };
+        // The sizes of a directory are known to be zero, so no data descriptor is needed:
+        self.write_local_header(&entry, FLAGS_UTF8)?;
+        self.entries.push(entry);
+        return Ok(());
+    }
+
+    /// Adds a file called `name` (using '/' as the separator) with the contents read from `reader`.
+    /// The `expected_size` (e.g. from the file's metadata) decides whether ZIP64 is used for this
+    /// entry, an error is returned when the contents turn out to be too large for the chosen format.
+    pub fn add_file(&mut self, name: &str, modified: SystemTime, expected_size: u64, method: CompressionMethod, reader: &mut dyn Read) -> io::Result<()> {
+        let mut entry = CentralDirectoryEntry {
+            name: name.as_bytes().to_vec(),
+            method,
+            dos_date_time: dos_date_time(modified),
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            local_header_offset: self.out.count,
+            is_directory: false,
+            zip64: expected_size >= ZIP64_THRESHOLD,
+        };
+        self.write_local_header(&entry, FLAGS_DATA_DESCRIPTOR_UTF8)?;
+
+        // Copy the contents, calculating the checksum on the way:
+        let data_start: u64 = self.out.count;
+        let (crc32, uncompressed_size) = match method {
+            CompressionMethod::Stored => copy_with_crc(reader, &mut self.out)?,
+            CompressionMethod::Deflated => {
+                let mut encoder = DeflateEncoder::new(&mut self.out, Compression::fast());
+                let result = copy_with_crc(reader, &mut encoder)?;
+                encoder.finish()?;
+                result
+            },
+        };
+        entry.crc32 = crc32;
+        entry.uncompressed_size = uncompressed_size;
+        entry.compressed_size = self.out.count - data_start;
+
+        // The data descriptor:
+        let mut descriptor: Vec<u8> = Vec::with_capacity(24);
+        put_u32(&mut descriptor, 0x0807_4b50);
+        put_u32(&mut descriptor, entry.crc32);
+        if entry.zip64 {
+            put_u64(&mut descriptor, entry.compressed_size);
+            put_u64(&mut descriptor, entry.uncompressed_size);
+        } else if entry.compressed_size >= ZIP64_MARKER as u64 || entry.uncompressed_size >= ZIP64_MARKER as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} grew beyond 4 GB while it was being archived", name)));
+        } else {
+            put_u32(&mut descriptor, entry.compressed_size as u32);
+            put_u32(&mut descriptor, entry.uncompressed_size as u32);
+        }
+        self.out.write_all(&descriptor)?;
+        self.entries.push(entry);
+        return Ok(());
+    }
+
+    /// Helper function for `add_directory` and `add_file`: writes the local file header of `entry`.
+    fn write_local_header(&mut self, entry: &CentralDirectoryEntry, flags: u16) -> io::Result<()> {
+        let mut header: Vec<u8> = Vec::with_capacity(30 + entry.name.len() + 20);
+        put_u32(&mut header, 0x0403_4b50);
+        put_u16(&mut header, if entry.zip64 { 45 } else { 20 }); // version needed to extract
+        put_u16(&mut header, flags);
+        put_u16(&mut header, entry.method.code());
+        put_u16(&mut header, entry.dos_date_time.0);
+        put_u16(&mut header, entry.dos_date_time.1);
+        put_u32(&mut header, 0); // CRC-32 (in the data descriptor)
+        let size_field: u32 = if entry.zip64 { ZIP64_MARKER } else { 0 }; // (sizes in the data descriptor)
+        put_u32(&mut header, size_field);
+        put_u32(&mut header, size_field);
+        put_u16(&mut header, entry.name.len() as u16);
+        put_u16(&mut header, if entry.zip64 { 20 } else { 0 });
+        header.extend_from_slice(&entry.name);
+        if entry.zip64 { // The ZIP64 extra field, whose sizes are in the data descriptor, too:
+            put_u16(&mut header, 0x0001);
+            put_u16(&mut header, 16);
+            put_u64(&mut header, 0);
+            put_u64(&mut header, 0);
+        }
+        self.out.write_all(&header)
+    }
+
+    /// Writes the central directory and the end of central directory record(s), ending the archive.
+    /// Returns the `inner` writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let central_directory_offset: u64 = self.out.count;
+        for entry in self.entries.iter() {
+            let uncompressed_size_64: bool = entry.zip64 || entry.uncompressed_size >= ZIP64_MARKER as u64;
+            let compressed_size_64: bool = entry.zip64 || entry.compressed_size >= ZIP64_MARKER as u64;
+            let offset_64: bool = entry.local_header_offset >= ZIP64_MARKER as u64;
+            // The ZIP64 extra field contains exactly those values that don't fit into their regular fields:
+            let mut extra: Vec<u8> = Vec::new();
+            if uncompressed_size_64 { put_u64(&mut extra, entry.uncompressed_size); }
+            if compressed_size_64 { put_u64(&mut extra, entry.compressed_size); }
+            if offset_64 { put_u64(&mut extra, entry.local_header_offset); }
+            if !extra.is_empty() {
+                let mut zip64_extra: Vec<u8> = Vec::with_capacity(4 + extra.len());
+                put_u16(&mut zip64_extra, 0x0001);
+                put_u16(&mut zip64_extra, extra.len() as u16);
+                zip64_extra.extend_from_slice(&extra);
+                extra = zip64_extra;
+            }
+
+            let mut header: Vec<u8> = Vec::with_capacity(46 + entry.name.len() + extra.len());
+            put_u32(&mut header, 0x0201_4b50);
+            put_u16(&mut header, VERSION_MADE_BY);
+            put_u16(&mut header, if extra.is_empty() { 20 } else { 45 }); // version needed to extract
+            put_u16(&mut header, if entry.is_directory { FLAGS_UTF8 } else { FLAGS_DATA_DESCRIPTOR_UTF8 });
+            put_u16(&mut header, entry.method.code());
+            put_u16(&mut header, entry.dos_date_time.0);
+            put_u16(&mut header, entry.dos_date_time.1);
+            put_u32(&mut header, entry.crc32);
+            put_u32(&mut header, if compressed_size_64 { ZIP64_MARKER } else { entry.compressed_size as u32 });
+            put_u32(&mut header, if uncompressed_size_64 { ZIP64_MARKER } else { entry.uncompressed_size as u32 });
+            put_u16(&mut header, entry.name.len() as u16);
+            put_u16(&mut header, extra.len() as u16);
+            put_u16(&mut header, 0); // file comment length
+            put_u16(&mut header, 0); // disk number start
+            put_u16(&mut header, 0); // internal file attributes
+            // External file attributes: the Unix permissions in the upper 16 bits, plus the MS-DOS directory flag:
+            put_u32(&mut header, if entry.is_directory { (0o040755 << 16) | 0x10 } else { 0o100644 << 16 });
+            put_u32(&mut header, if offset_64 { ZIP64_MARKER } else { entry.local_header_offset as u32 });
+            header.extend_from_slice(&entry.name);
+            header.extend_from_slice(&extra);
+            self.out.write_all(&header)?;
+        }
+        let central_directory_size: u64 = self.out.count - central_directory_offset;
+        let entry_count: u64 = self.entries.len() as u64;
+
+        let mut end: Vec<u8> = Vec::with_capacity(56 + 20 + 22);
+        let zip64: bool = entry_count >= 0xFFFF
+            || central_directory_size >= ZIP64_MARKER as u64
+            || central_directory_offset >= ZIP64_MARKER as u64;
+        if zip64 {
+            let zip64_end_offset: u64 = self.out.count;
+            // The ZIP64 end of central directory record:
+            put_u32(&mut end, 0x0606_4b50);
+            put_u64(&mut end, 44); // size of the remaining record
+            put_u16(&mut end, VERSION_MADE_BY);
+            put_u16(&mut end, 45); // version needed to extract
+            put_u32(&mut end, 0); // number of this disk
+            put_u32(&mut end, 0); // disk with the central directory
+            put_u64(&mut end, entry_count); // entries on this disk
+            put_u64(&mut end, entry_count); // entries in total
+            put_u64(&mut end, central_directory_size);
+            put_u64(&mut end, central_directory_offset);
+            // The ZIP64 end of central directory locator:
+            put_u32(&mut end, 0x0706_4b50);
+            put_u32(&mut end, 0); // disk with the ZIP64 end of central directory record
+            put_u64(&mut end, zip64_end_offset);
+            put_u32(&mut end, 1); // total number of disks
+        }
+        // The (regular) end of central directory record:
+        put_u32(&mut end, 0x0605_4b50);
+        put_u16(&mut end, 0); // number of this disk
+        put_u16(&mut end, 0); // disk with the central directory
+        put_u16(&mut end, entry_count.min(0xFFFF) as u16); // entries on this disk
+        put_u16(&mut end, entry_count.min(0xFFFF) as u16); // entries in total
+        put_u32(&mut end, central_directory_size.min(ZIP64_MARKER as u64) as u32);
+        put_u32(&mut end, central_directory_offset.min(ZIP64_MARKER as u64) as u32);
+        put_u16(&mut end, 0); // comment length
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        return Ok(self.out.inner);
+    }
+}
+
+/// Helper function for `ZipWriter::add_file`: copies everything from `reader` to `writer`.
+/// Returns the CRC-32 checksum and the number of bytes copied.
+fn copy_with_crc(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<(u32, u64)> {
+    let mut crc = Crc::new();
+    let mut bytes_copied: u64 = 0; // (`Crc::amount` is only 32 bits wide)
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok((crc.sum(), bytes_copied)),
+            Ok(bytes_read) => bytes_read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        crc.update(&buffer[..bytes_read]);
+        bytes_copied += bytes_read as u64;
+        writer.write_all(&buffer[..bytes_read])?;
+    }
+}
+
+/// A file or directory in an existing ZIP archive, as listed in its central directory.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    /// The full name within the archive, using '/' as the separator, directories ending with a '/'.
+    pub name: String,
+    /// How the contents are stored, `None` if they are compressed using a method that isn't supported.
+    pub method: Option<CompressionMethod>,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// The modification time, if it's a valid one.
+    pub modified: Option<SystemTime>,
+    /// Whether the contents are encrypted (which isn't supported).
+    pub encrypted: bool,
+    local_header_offset: u64,
+}
+
+impl ZipEntry {
+    /// Whether this entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// The offset of the (possibly compressed) contents of this entry within the `archive`, which is
+    /// found in its local header.
+    pub fn data_offset<R: Read + Seek>(&self, archive: &mut R) -> io::Result<u64> {
+        let mut header = [0u8; 30];
+        archive.seek(SeekFrom::Start(self.local_header_offset))?;
+        archive.read_exact(&mut header)?;
+        if get_u32(&header, 0) != 0x0403_4b50 {
+            return Err(invalid_zip("invalid local file header"));
+        }
+        return Ok(self.local_header_offset + 30 + get_u16(&header, 26) as u64 + get_u16(&header, 28) as u64);
+    }
+
+    /// Returns a reader over the (decompressed) contents of this entry, read from the `archive`.
+    pub fn reader<R: Read + Seek + Send + 'static>(&self, mut archive: R) -> io::Result<Box<dyn Read + Send>> {
+        if self.encrypted {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} is encrypted", self.name)));
+        }
+        let data_offset: u64 = self.data_offset(&mut archive)?;
+        archive.seek(SeekFrom::Start(data_offset))?;
+        let data = archive.take(self.compressed_size);
+        match self.method {
+            Some(CompressionMethod::Stored) => Ok(Box::new(data)),
+            Some(CompressionMethod::Deflated) => Ok(Box::new(DeflateDecoder::new(data).take(self.uncompressed_size))),
+            None => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} is compressed using an unsupported method", self.name))),
+        }
+    }
+}
+
+/// Lists all entries of the ZIP `archive` by reading its central directory (ZIP64 included).
+pub fn read_zip_entries<R: Read + Seek>(archive: &mut R) -> io::Result<Vec<ZipEntry>> {
+    // The end of central directory record is at the very end, only followed by a comment of up to 64 KB:
+    let archive_size: u64 = archive.seek(SeekFrom::End(0))?;
+    let tail_size: u64 = archive_size.min(22 + 0xFFFF);
+    let mut tail: Vec<u8> = vec![0u8; tail_size as usize];
+    archive.seek(SeekFrom::Start(archive_size - tail_size))?;
+    archive.read_exact(&mut tail)?;
+    let end_index: usize = (0..tail.len().saturating_sub(21)).rev()
+        .find(|&index| get_u32(&tail, index) == 0x0605_4b50)
+        .ok_or_else(|| invalid_zip("end of central directory not found"))?;
+    let mut entry_count: u64 = get_u16(&tail, end_index + 10) as u64;
+    let mut central_directory_size: u64 = get_u32(&tail, end_index + 12) as u64;
+    let mut central_directory_offset: u64 = get_u32(&tail, end_index + 16) as u64;
+
+    // A ZIP64 end of central directory locator right before it points to the ZIP64 record with the actual values:
+    if end_index >= 20 && get_u32(&tail, end_index - 20) == 0x0706_4b50 {
+        let mut zip64_end = [0u8; 56];
+        archive.seek(SeekFrom::Start(get_u64(&tail, end_index - 20 + 8)))?;
+        archive.read_exact(&mut zip64_end)?;
+        if get_u32(&zip64_end, 0) != 0x0606_4b50 {
+            return Err(invalid_zip("invalid ZIP64 end of central directory"));
+        }
+        entry_count = get_u64(&zip64_end, 32);
+        central_directory_size = get_u64(&zip64_end, 40);
+        central_directory_offset = get_u64(&zip64_end, 48);
+    }
+    if central_directory_size > MAX_CENTRAL_DIRECTORY_SIZE {
+        return Err(invalid_zip("central directory too large"));
+    }
+    if central_directory_offset.saturating_add(central_directory_size) > archive_size {
+        return Err(invalid_zip("central directory out of bounds"));
+    }
+
+    let mut central_directory: Vec<u8> = vec![0u8; central_directory_size as usize];
+    archive.seek(SeekFrom::Start(central_directory_offset))?;
+    archive.read_exact(&mut central_directory)?;
+    let mut entries: Vec<ZipEntry> = Vec::with_capacity(entry_count.min(0xFFFF) as usize);
+    let mut index: usize = 0;
+    while index + 46 <= central_directory.len() && get_u32(&central_directory, index) == 0x0201_4b50 {
+        let header: &[u8] = &central_directory[index..];
+        let name_length: usize = get_u16(header, 28) as usize;
+        let extra_length: usize = get_u16(header, 30) as usize;
+        let comment_length: usize = get_u16(header, 32) as usize;
+        if 46 + name_length + extra_length > header.len() {
+            return Err(invalid_zip("central directory entry out of bounds"));
+        }
+        let mut entry = ZipEntry {
+            // (Names that aren't flagged as UTF-8 are in code page 437, whose ASCII part is the same.)
+            name: String::from_utf8_lossy(&header[46..46 + name_length]).to_string(),
+            method: CompressionMethod::from_code(get_u16(header, 10)),
+            compressed_size: get_u32(header, 20) as u64,
+            uncompressed_size: get_u32(header, 24) as u64,
+            modified: system_time_from_dos(get_u16(header, 12), get_u16(header, 14)),
+            encrypted: get_u16(header, 8) & 1 != 0,
+            local_header_offset: get_u32(header, 42) as u64,
+        };
+        // The values that don't fit into their regular fields are found in the ZIP64 extra field:
+        let mut extra: &[u8] = &header[46 + name_length..46 + name_length + extra_length];
+        while extra.len() >= 4 {
+            let (id, size) = (get_u16(extra, 0), (get_u16(extra, 2) as usize).min(extra.len() - 4));
+            if id == 0x0001 {
+                let mut values = extra[4..4 + size].chunks_exact(8).map(|value| get_u64(value, 0));
+                for field in [&mut entry.uncompressed_size, &mut entry.compressed_size, &mut entry.local_header_offset] {
+                    if *field == ZIP64_MARKER as u64 {
+                        *field = values.next().ok_or_else(|| invalid_zip("ZIP64 extra field too short"))?;
+                    }
+                }
+            }
+            extra = &extra[4 + size..];
+        }
+        entries.push(entry);
+        index += 46 + name_length + extra_length + comment_length;
+    }
+    return Ok(entries);
+}
+
+/// Returns an error about an invalid ZIP archive.
+fn invalid_zip(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid ZIP archive: {}", message))
+}
+
+/// Converts a (time, date) in MS-DOS format (in local time) back into a `SystemTime`, cf. `dos_date_time`.
+fn system_time_from_dos(time: u16, date: u16) -> Option<SystemTime> {
+    let date_time = Local.with_ymd_and_hms(
+        1980 + (date >> 9) as i32, ((date >> 5) & 0xF) as u32, (date & 0x1F) as u32,
+        (time >> 11) as u32, ((time >> 5) & 0x3F) as u32, 2 * (time & 0x1F) as u32,
+    ).earliest()?;
+    return Some(date_time.into());
+}
+
+/// Converts `system_time` into the MS-DOS (time, date) format used by ZIP, in local time.
+/// MS-DOS dates start in 1980, so earlier times are clamped to 1980-01-01 00:00:00.
+fn dos_date_time(system_time: SystemTime) -> (u16, u16) {
+    let date_time: DateTime<Local> = DateTime::from(system_time);
+    if date_time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time: u16 = ((date_time.hour() << 11) | (date_time.minute() << 5) | (date_time.second() / 2)) as u16;
+    let date: u16 = ((((date_time.year() - 1980).min(127) as u32) << 9) | (date_time.month() << 5) | date_time.day()) as u16;
+    return (time, date);
+}
+
+/// Reads the little-endian number at `index` of `buffer`, like all numbers in a ZIP archive.
+fn get_u16(buffer: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([buffer[index], buffer[index + 1]])
+}
+
+/// Reads the little-endian number at `index` of `buffer`.
+fn get_u32(buffer: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes(buffer[index..index + 4].try_into().unwrap())
+}
+
+/// Reads the little-endian number at `index` of `buffer`.
+fn get_u64(buffer: &[u8], index: usize) -> u64 {
+    u64::from_le_bytes(buffer[index..index + 8].try_into().unwrap())
+}
+
+/// Appends `value` to `buffer` in little-endian byte order, like all numbers in a ZIP archive.
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends `value` to `buffer` in little-endian byte order.
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends `value` to `buffer` in little-endian byte order.
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Whether `needle` occurs in `haystack`.
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn archive_layout() {
+        let mut writer = ZipWriter::new(Vec::new());
+        writer.add_directory("docs", SystemTime::now()).unwrap();
+        writer.add_file("docs/a.txt", SystemTime::now(), 3, CompressionMethod::Stored, &mut &b"abc"[..]).unwrap();
+        let archive: Vec<u8> = writer.finish().unwrap();
+
+        assert!(archive.starts_with(b"PK\x03\x04")); // (the local header of the directory)
+        assert!(contains(&archive, b"docs/a.txtabc")); // (a stored file follows its local header and name)
+        assert!(contains(&archive, b"abcPK\x07\x08")); // (and is followed by its data descriptor)
+        let end: &[u8] = &archive[archive.len() - 22..];
+        assert!(end.starts_with(b"PK\x05\x06"));
+        assert_eq!(get_u16(end, 10), 2); // (the number of entries)
+        let central_directory_offset: usize = get_u32(end, 16) as usize;
+        assert!(archive[central_directory_offset..].starts_with(b"PK\x01\x02"));
+    }
+
+    #[test]
+    fn empty_archive_layout() {
+        let archive: Vec<u8> = ZipWriter::new(Vec::new()).finish().unwrap();
+        assert_eq!(archive.len(), 22);
+        assert!(archive.starts_with(b"PK\x05\x06"));
+    }
+
+    #[test]
+    fn dos_date_times() {
+        assert_eq!(dos_date_time(UNIX_EPOCH), (0, (1 << 5) | 1)); // (clamped to 1980-01-01)
+        let local_time = |year: i32| -> SystemTime { Local.with_ymd_and_hms(year, 9, 13, 14, 26, 41).unwrap().into() };
+        assert_eq!(dos_date_time(local_time(2020)), ((14 << 11) | (26 << 5) | 20, (40 << 9) | (9 << 5) | 13));
+        assert_eq!(dos_date_time(local_time(2200)).1 >> 9, 127); // (the year is clamped to 2107)
+    }
+
+    #[test]
+    fn compression_methods() {
+        assert_eq!(CompressionMethod::for_path(Path::new("photo.JPG")), CompressionMethod::Stored);
+        assert_eq!(CompressionMethod::for_path(Path::new("backup.zip")), CompressionMethod::Stored);
+        assert_eq!(CompressionMethod::for_path(Path::new("notes.txt")), CompressionMethod::Deflated);
+        assert_eq!(CompressionMethod::for_path(Path::new("image.bmp")), CompressionMethod::Deflated);
+    }
+
+    /// Writes an archive with a directory, a deflated and a stored file.
+    fn example_archive(modified: SystemTime) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Vec::new());
+        writer.add_directory("docs", modified).unwrap();
+        let text: String = "All work and no play makes Jack a dull boy.\n".repeat(100);
+        writer.add_file("docs/jack.txt", modified, text.len() as u64, CompressionMethod::Deflated, &mut text.as_bytes()).unwrap();
+        writer.add_file("Ünïcödé.bin", modified, 5, CompressionMethod::Stored, &mut &b"\x00\x01\x02\x03\x04"[..]).unwrap();
+        return writer.finish().unwrap();
+    }
+
+    /// Reads the contents of `entry` of the `archive`.
+    fn contents(archive: &[u8], entry: &ZipEntry) -> Vec<u8> {
+        let mut contents: Vec<u8> = Vec::new();
+        entry.reader(Cursor::new(archive.to_vec())).unwrap().read_to_end(&mut contents).unwrap();
+        return contents;
+    }
+
+    #[test]
+    fn round_trip() {
+        let modified: SystemTime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let archive: Vec<u8> = example_archive(modified);
+        let entries: Vec<ZipEntry> = read_zip_entries(&mut Cursor::new(&archive)).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["docs/", "docs/jack.txt", "Ünïcödé.bin"]);
+
+        assert!(entries[0].is_dir());
+        assert!(!entries[1].is_dir());
+        assert_eq!(entries[1].method, Some(CompressionMethod::Deflated));
+        assert_eq!(entries[1].uncompressed_size, 4400);
+        assert!(entries[1].compressed_size < entries[1].uncompressed_size);
+        assert_eq!(contents(&archive, &entries[1]), "All work and no play makes Jack a dull boy.\n".repeat(100).as_bytes());
+        assert_eq!(entries[2].method, Some(CompressionMethod::Stored));
+        assert_eq!(contents(&archive, &entries[2]), b"\x00\x01\x02\x03\x04");
+        // MS-DOS times have a resolution of two seconds:
+        assert_eq!(entries[2].modified, Some(modified));
+    }
+
+    #[test]
+    fn stored_data_offset() {
+        let archive: Vec<u8> = example_archive(SystemTime::now());
+        let entries: Vec<ZipEntry> = read_zip_entries(&mut Cursor::new(&archive)).unwrap();
+        let offset: usize = entries[2].data_offset(&mut Cursor::new(&archive)).unwrap() as usize;
+        assert_eq!(&archive[offset..offset + 5], b"\x00\x01\x02\x03\x04");
+    }
+
+    #[test]
+    fn empty_archive() {
+        let archive: Vec<u8> = ZipWriter::new(Vec::new()).finish().unwrap();
+        assert!(read_zip_entries(&mut Cursor::new(&archive)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_archives() {
+        assert_eq!(read_zip_entries(&mut Cursor::new(b"not a zip file")).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // A cut off archive lacks the end of central directory:
+        let archive: Vec<u8> = example_archive(SystemTime::now());
+        assert!(read_zip_entries(&mut Cursor::new(&archive[..archive.len() - 30])).is_err());
+        // An end of central directory record claiming a huge central directory:
+        let mut end: Vec<u8> = vec![0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0, 1, 0, 1, 0];
+        end.extend_from_slice(&(128u32 * 1024 * 1024).to_le_bytes());
+        end.extend_from_slice(&[0; 6]);
+        assert_eq!(read_zip_entries(&mut Cursor::new(end)).unwrap_err().to_string(), "invalid ZIP archive: central directory too large");
+    }
+
+    #[test]
+    fn dos_date_time_round_trip() {
+        let (time, date) = dos_date_time(UNIX_EPOCH + Duration::from_secs(1_600_000_001));
+        assert_eq!(system_time_from_dos(time, date), Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)));
+        assert_eq!(system_time_from_dos(0, 0), None); // (month 0)
+    }
+}